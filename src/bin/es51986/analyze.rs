@@ -0,0 +1,196 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use clap::Args;
+use es51986::{segment::{Segment, Segmenter}, timestamp};
+use serde::Serialize;
+
+use crate::{args::parse_duration, convert::{InputFormat, decode}, source::with_path};
+
+#[derive(Debug, Args)]
+pub struct AnalyzeArgs {
+    /// Capture, JSON Lines or CSV file. "-" is standard input.
+    pub input: PathBuf,
+
+    /// Input format. Detected from the content when omitted.
+    #[arg(long, value_enum)]
+    pub from: Option<InputFormat>,
+
+    /// Interval between readings counted as a gap.
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub gap: Duration,
+
+    /// Print JSON Lines, one object per segment, instead of a table.
+    #[arg(long, conflicts_with = "csv")]
+    pub json: bool,
+
+    /// Print CSV instead of a table.
+    #[arg(long)]
+    pub csv: bool,
+}
+
+/// One line of the report.
+#[derive(Debug, Serialize)]
+struct Row {
+    meter: Option<String>,
+    start: String,
+    end: String,
+    duration: f64,
+    function: &'static str,
+    range: u8,
+    coupling: &'static str,
+    unit: Option<String>,
+    count: usize,
+    min: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+    stddev: Option<f64>,
+    overflow: usize,
+    parse_errors: usize,
+    gaps: usize,
+    gap_duration: f64,
+}
+
+impl Row {
+    fn new(meter: Option<&str>, s: &Segment) -> Self {
+        Self {
+            meter: meter.map(|m| m.to_owned()), start: timestamp::rfc3339(s.start), end: timestamp::rfc3339(s.end), duration: s.duration().as_secs_f64(),
+            function: s.key.function.name(), range: s.key.range.index(), coupling: s.key.coupling, unit: s.unit.clone(),
+            count: s.count, min: s.min, max: s.max, mean: s.mean, stddev: s.stddev,
+            overflow: s.overflow_count, parse_errors: s.parse_errors, gaps: s.gaps, gap_duration: s.gap_duration.as_secs_f64(),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "meter,start,end,duration,function,range,coupling,unit,count,min,max,mean,stddev,overflow,parse_errors,gaps,gap_duration";
+
+fn number(v: Option<f64>) -> String {
+    v.map_or_else(String::new, |v| format!("{}", v))
+}
+
+fn write_csv<W: Write>(w: &mut W, row: &Row) -> io::Result<()> {
+    write!(w, "{},", row.meter.as_deref().unwrap_or(""))?;
+    write!(w, "{},{},{:.3},{},{},{},{},{},", row.start, row.end, row.duration, row.function, row.range, row.coupling, row.unit.as_deref().unwrap_or(""), row.count)?;
+    write!(w, "{},{},{},{},", number(row.min), number(row.max), number(row.mean), number(row.stddev))?;
+    write!(w, "{},{},{},{:.3}\r\n", row.overflow, row.parse_errors, row.gaps, row.gap_duration)
+}
+
+fn write_text<W: Write>(w: &mut W, index: usize, row: &Row) -> io::Result<()> {
+    let stat = |v: Option<f64>| v.map_or_else(|| "-".to_owned(), |v| format!("{:.4}", v));
+    let mode = format!("{} {} {}", row.function, row.range, row.coupling);
+    writeln!(
+        w, "{:>3}  {:<8}  {}  {:>9.1}s  {:<18} {:<4} {:>6}  {:>10} {:>10} {:>10} {:>10}  {:>4} {:>4} {:>4}",
+        index + 1, row.meter.as_deref().unwrap_or("-"), row.start, row.duration, mode.trim_end(), row.unit.as_deref().unwrap_or("-"), row.count,
+        stat(row.min), stat(row.max), stat(row.mean), stat(row.stddev), row.overflow, row.parse_errors, row.gaps
+    )
+}
+
+/// Split the readings of `input` into segments, separately for each meter id, ordered by start time.
+/// A record that cannot be decoded is counted for the meter of the previous reading.
+pub fn analyze<R: BufRead>(input: R, format: Option<InputFormat>, gap: Duration) -> io::Result<Vec<(Option<String>, Segment)>> {
+    let mut segmenters: Vec<(Option<String>, Segmenter)> = vec![];
+    let mut last = None;
+    let mut leading_errors = 0;
+    let mut segments = vec![];
+    decode(input, format, |result| {
+        match result {
+            Ok((meter, reading)) => {
+                let index = segmenters.iter().position(|(m, _)| *m == meter).unwrap_or_else(|| {
+                    let mut segmenter = Segmenter::new().with_gap_threshold(gap);
+                    for _ in 0..std::mem::take(&mut leading_errors) {
+                        segmenter.push_error();
+                    }
+                    segmenters.push((meter.clone(), segmenter));
+                    segmenters.len() - 1
+                });
+                segments.extend(segmenters[index].1.push(&reading).map(|s| (meter, s)));
+                last = Some(index);
+            }
+            Err(_) => match last {
+                Some(index) => segmenters[index].1.push_error(),
+                None => leading_errors += 1,
+            },
+        }
+        Ok(())
+    })?;
+    segments.extend(segmenters.into_iter().filter_map(|(meter, mut s)| s.finish().map(|s| (meter, s))));
+    segments.sort_by_key(|(_, s)| s.start);
+    Ok(segments)
+}
+
+pub fn run(args: AnalyzeArgs) -> io::Result<()> {
+    let input: Box<dyn BufRead> = if args.input.as_os_str() == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.input).map_err(|e| with_path(args.input.display(), e))?))
+    };
+    let segments = analyze(input, args.from, args.gap)?;
+    let mut out = io::stdout().lock();
+    if args.csv {
+        write!(out, "{}\r\n", CSV_HEADER)?;
+    } else if !args.json {
+        writeln!(
+            out, "{:>3}  {:<8}  {:<24}  {:>10}  {:<18} {:<4} {:>6}  {:>10} {:>10} {:>10} {:>10}  {:>4} {:>4} {:>4}",
+            "#", "meter", "start", "duration", "mode", "unit", "count", "min", "max", "mean", "stddev", "OL", "err", "gap"
+        )?;
+    }
+    for (i, (meter, segment)) in segments.iter().enumerate() {
+        let row = Row::new(meter.as_deref(), segment);
+        if args.json {
+            writeln!(out, "{}", serde_json::to_string(&row).map_err(io::Error::other)?)?;
+        } else if args.csv {
+            write_csv(&mut out, &row)?;
+        } else {
+            write_text(&mut out, i, &row)?;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use es51986::{Output, jsonl::Record, reading::Reading};
+
+    use super::*;
+
+    #[test]
+    fn csv_report() {
+        let csv = "timestamp,function,coupling,range,value,unit,overflow,battery\n\
+            1970-01-01T00:00:00.000Z,voltage,DC,1,10.00,V,0,0\n\
+            1970-01-01T00:00:00.500Z,voltage,DC,1,12.00,V,0,0\n\
+            1970-01-01T00:00:01.000Z,voltage,DC,1,bad,V,0,0\n\
+            1970-01-01T00:00:05.000Z,voltage,DC,1,14.00,V,0,0\n\
+            1970-01-01T00:00:05.500Z,ohm,,1,0.985,kΩ,0,0\n";
+        let segments = analyze(csv.as_bytes(), None, Duration::from_secs(2)).unwrap();
+        assert_eq!(segments.len(), 2);
+        let mut out = vec![];
+        for (meter, segment) in &segments {
+            write_csv(&mut out, &Row::new(meter.as_deref(), segment)).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            ",1970-01-01T00:00:00.000Z,1970-01-01T00:00:05.000Z,5.000,voltage,1,DC,V,3,10,14,12,2,0,1,1,4.500\r\n\
+             ,1970-01-01T00:00:05.500Z,1970-01-01T00:00:05.500Z,0.000,ohm,1,,kΩ,1,0.985,0.985,0.985,,0,0,0,0.000\r\n"
+        );
+    }
+
+    #[test]
+    fn meters_kept_apart() {
+        let record = |meter: &str, millis: u64, frame: &str| {
+            let reading = Reading::new(SystemTime::UNIX_EPOCH + Duration::from_millis(millis), Output::parse(frame.as_bytes()).unwrap());
+            Record::new(&reading, Some(meter)).to_json()
+        };
+        let log: Vec<String> = (0..4).flat_map(|i| {
+            [record("in", i * 500, if i % 2 == 0 { "11000;80:" } else { "11200;80:" }), record("out", i * 500 + 10, "01000;80:")]
+        }).collect();
+        let segments = analyze(log.join("\n").as_bytes(), None, Duration::from_secs(2)).unwrap();
+        let summary: Vec<(Option<&str>, usize, Option<f64>)> = segments.iter().map(|(m, s)| (m.as_deref(), s.count, s.mean)).collect();
+        assert_eq!(summary, [(Some("in"), 4, Some(11.0)), (Some("out"), 4, Some(1.0))]);
+    }
+}
@@ -0,0 +1,91 @@
+//! Value parsers shared by the subcommands.
+
+use std::time::Duration;
+
+use es51986::replay::Speed;
+
+/// Parse "500ms", "30s", "10m", "2h", "1d" or plain seconds such as "1.5".
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid duration '{}'", s))?;
+    let seconds = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(format!("invalid duration unit '{}' (use ms, s, m, h or d)", unit)),
+    };
+    Duration::try_from_secs_f64(number * seconds).map_err(|_| format!("invalid duration '{}'", s))
+}
+
+/// Like `parse_duration`, but zero is rejected.
+pub fn parse_positive_duration(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        d if d.is_zero() => Err(format!("duration '{}' should be longer than zero", s.trim())),
+        d => Ok(d),
+    }
+}
+
+/// Parse a byte count such as "4096", "500k", "100M" or "1G" (powers of 1024).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, shift) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    match number.parse::<u64>() {
+        Ok(n) if 0 < n => n.checked_mul(1 << shift).ok_or_else(|| format!("size '{}' is too large", s)),
+        _ => Err(format!("invalid size '{}' (use bytes or k, M, G)", s)),
+    }
+}
+
+/// Parse "max" or a positive factor where 1 is the original pacing.
+pub fn parse_speed(s: &str) -> Result<Speed, String> {
+    if s.eq_ignore_ascii_case("max") {
+        return Ok(Speed::AsFastAsPossible);
+    }
+    match s.parse::<f64>() {
+        Ok(1.0) => Ok(Speed::RealTime),
+        Ok(f) if 0.0 < f && f.is_finite() => Ok(Speed::Scaled(f)),
+        _ => Err(format!("invalid speed '{}' (use a positive factor or max)", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert_eq!(parse_positive_duration("1ms"), Ok(Duration::from_millis(1)));
+        assert!(parse_positive_duration("0s").is_err());
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500k"), Ok(512_000));
+        assert_eq!(parse_size("100M"), Ok(100 << 20));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("1T").is_err());
+    }
+
+    #[test]
+    fn speed() {
+        assert_eq!(parse_speed("MAX"), Ok(Speed::AsFastAsPossible));
+        assert_eq!(parse_speed("1"), Ok(Speed::RealTime));
+        assert_eq!(parse_speed("2.5"), Ok(Speed::Scaled(2.5)));
+        assert!(parse_speed("0").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use clap::{Args, ValueEnum};
+use es51986::{
+    Function,
+    capture::MAGIC,
+    csv::{CsvReader, CsvWriter},
+    influx::{self, DEFAULT_MEASUREMENT},
+    jsonl::Record,
+    parser::Parser,
+    reading::Reading,
+    replay::{Replayer, Speed},
+    sink::Sink,
+    timestamp,
+};
+
+use crate::{args::parse_duration, read::TextWriter, source::with_path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Raw capture written by `record`.
+    Capture,
+    Jsonl,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Csv,
+    Jsonl,
+    /// InfluxDB line protocol.
+    Influx,
+}
+
+/// Start or end of the time range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBound {
+    At(SystemTime),
+    /// Offset from the first reading.
+    After(Duration),
+}
+
+fn parse_time_bound(s: &str) -> Result<TimeBound, String> {
+    match s.strip_prefix('+') {
+        Some(offset) => parse_duration(offset).map(TimeBound::After),
+        None => timestamp::parse_rfc3339(s).map(TimeBound::At)
+            .ok_or_else(|| format!("invalid time '{}' (use RFC 3339 such as 2024-03-01T12:00:00Z, or +10m from the first reading)", s)),
+    }
+}
+
+fn parse_function(s: &str) -> Result<Function, String> {
+    Function::from_name(s).ok_or_else(|| {
+        let names: Vec<&str> = Function::ALL.iter().map(|f| f.name()).collect();
+        format!("unknown function '{}' (use {})", s, names.join(", "))
+    })
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// Capture, JSON Lines or CSV file. "-" is standard input.
+    pub input: PathBuf,
+
+    /// Output file. Standard output when omitted.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Input format. Detected from the content when omitted.
+    #[arg(long, value_enum)]
+    pub from: Option<InputFormat>,
+
+    /// Output format. Taken from the output file extension (.csv, .jsonl, .lp) when omitted, otherwise text.
+    #[arg(long, value_enum)]
+    pub to: Option<OutputFormat>,
+
+    /// Skip readings before this time: RFC 3339, or +<duration> from the first reading such as +5m.
+    #[arg(long, value_parser = parse_time_bound)]
+    pub since: Option<TimeBound>,
+
+    /// Skip readings at or after this time: RFC 3339, or +<duration> from the first reading.
+    #[arg(long, value_parser = parse_time_bound)]
+    pub until: Option<TimeBound>,
+
+    /// Keep only these functions, e.g. voltage. Repeat or separate with commas.
+    #[arg(long = "function", value_delimiter = ',', value_parser = parse_function)]
+    pub functions: Vec<Function>,
+
+    /// Keep only the readings of this meter. Readings without a meter id, from captures and CSV, are tagged with it.
+    #[arg(long)]
+    pub meter: Option<String>,
+
+    /// InfluxDB measurement name.
+    #[arg(long, default_value = DEFAULT_MEASUREMENT)]
+    pub measurement: String,
+}
+
+/// Detect the format from the first bytes.
+pub fn detect(head: &[u8]) -> InputFormat {
+    if head.starts_with(MAGIC) {
+        InputFormat::Capture
+    } else if head.trim_ascii_start().starts_with(b"{") {
+        InputFormat::Jsonl
+    } else {
+        InputFormat::Csv
+    }
+}
+
+fn output_format(path: Option<&Path>) -> OutputFormat {
+    match path.and_then(|p| p.extension()).and_then(|e| e.to_str()) {
+        Some("csv") => OutputFormat::Csv,
+        Some("jsonl" | "json" | "ndjson") => OutputFormat::Jsonl,
+        Some("lp" | "influx") => OutputFormat::Influx,
+        _ => OutputFormat::Text,
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConvertStats {
+    pub written: usize,
+    pub skipped: usize,
+    /// Meter ids of the written readings.
+    pub meters: BTreeSet<String>,
+}
+
+/// Selects the readings in the time range, of the selected functions and of the selected meter.
+pub struct Slice {
+    since: Option<TimeBound>,
+    until: Option<TimeBound>,
+    functions: Vec<Function>,
+    meter: Option<String>,
+    first: Option<SystemTime>,
+    pub stats: ConvertStats,
+}
+
+impl Slice {
+    pub fn new(since: Option<TimeBound>, until: Option<TimeBound>, functions: Vec<Function>) -> Self {
+        Self { since, until, functions, meter: None, first: None, stats: ConvertStats::default() }
+    }
+
+    /// Keep only the readings of `meter`, and tag readings without a meter id with it.
+    pub fn with_meter(mut self, meter: Option<String>) -> Self {
+        self.meter = meter;
+        self
+    }
+
+    fn resolve(&self, bound: TimeBound) -> SystemTime {
+        match bound {
+            TimeBound::At(t) => t,
+            TimeBound::After(offset) => self.first.unwrap_or(SystemTime::UNIX_EPOCH) + offset,
+        }
+    }
+
+    /// The meter id to write `reading` with, or `None` if it is skipped.
+    pub fn select(&mut self, meter: Option<String>, reading: &Reading) -> Option<Option<String>> {
+        let meter = meter.or_else(|| self.meter.clone());
+        if self.meter.is_some() && meter != self.meter {
+            self.stats.skipped += 1;
+            return None;
+        }
+        self.first.get_or_insert(reading.timestamp);
+        let keeps = self.since.is_none_or(|b| self.resolve(b) <= reading.timestamp)
+            && self.until.is_none_or(|b| reading.timestamp < self.resolve(b))
+            && (self.functions.is_empty() || self.functions.contains(&reading.output.function));
+        if !keeps {
+            self.stats.skipped += 1;
+            return None;
+        }
+        self.stats.written += 1;
+        self.stats.meters.extend(meter.clone());
+        Some(meter)
+    }
+}
+
+/// Where converted readings go. JSON Lines and InfluxDB line protocol keep the meter id of each reading.
+pub enum Target<'a> {
+    /// Text or CSV, which have no meter column.
+    Sink(Box<dyn Sink + 'a>),
+    Jsonl(Box<dyn Write + 'a>),
+    Influx { writer: Box<dyn Write + 'a>, measurement: String },
+}
+
+impl Target<'_> {
+    fn write(&mut self, meter: Option<&str>, reading: &Reading) -> io::Result<()> {
+        match self {
+            Self::Sink(sink) => sink.write(reading),
+            Self::Jsonl(w) => writeln!(w, "{}", Record::new(reading, meter).to_json()),
+            Self::Influx { writer, measurement } => writeln!(writer, "{}", influx::format_line(reading, measurement, meter)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Sink(sink) => sink.flush(),
+            Self::Jsonl(w) | Self::Influx { writer: w, .. } => w.flush(),
+        }
+    }
+}
+
+/// Call `f` with each reading of `input` and its meter id, or with a description of a frame or record that could not be decoded.
+/// Only JSON Lines records carry a meter id.
+pub fn decode<R: BufRead, F>(mut input: R, format: Option<InputFormat>, mut f: F) -> io::Result<()>
+    where F: FnMut(Result<(Option<String>, Reading), String>) -> io::Result<()>
+{
+    let format = match format {
+        Some(format) => format,
+        None => detect(input.fill_buf()?),
+    };
+    match format {
+        InputFormat::Capture => {
+            let replayer = Replayer::new(input, Speed::AsFastAsPossible).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            let mut parser = Parser::new();
+            replayer.for_each(|chunk| {
+                parser.parse(&chunk.data).into_iter().try_for_each(|result| f(match result {
+                    Ok(output) => Ok((None, Reading::new(chunk.timestamp, output))),
+                    Err(e) => Err(format!("{}: invalid frame: {:?}", timestamp::rfc3339(chunk.timestamp), e)),
+                }))
+            }).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        }
+        InputFormat::Jsonl => {
+            for (i, line) in input.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = serde_json::from_str::<Record>(&line).map_err(|e| e.to_string());
+                let reading = record.and_then(|r| r.to_reading().map(|reading| (r.meter, reading)).map_err(|e| e.to_string()));
+                f(reading.map_err(|e| format!("line {}: {}", i + 1, e)))?;
+            }
+        }
+        InputFormat::Csv => {
+            for reading in CsvReader::new(input)? {
+                match reading {
+                    Ok(reading) => f(Ok((None, reading)))?,
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => f(Err(e.to_string()))?,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the readings of `input` selected by `slice` to `target`. Returns the number of frames or records that could not be decoded.
+pub fn convert<R: BufRead>(input: R, format: Option<InputFormat>, slice: &mut Slice, target: &mut Target) -> io::Result<usize> {
+    let mut errors = 0;
+    decode(input, format, |result| match result {
+        Ok((meter, reading)) => match slice.select(meter, &reading) {
+            Some(meter) => target.write(meter.as_deref(), &reading),
+            None => Ok(()),
+        },
+        Err(e) => {
+            eprintln!("es51986: {}", e);
+            errors += 1;
+            Ok(())
+        }
+    })?;
+    target.flush()?;
+    Ok(errors)
+}
+
+fn target<'a, W: Write + 'a>(w: W, format: OutputFormat, measurement: &str) -> Target<'a> {
+    match format {
+        OutputFormat::Text => Target::Sink(Box::new(TextWriter(w))),
+        OutputFormat::Csv => Target::Sink(Box::new(CsvWriter::new(w))),
+        OutputFormat::Jsonl => Target::Jsonl(Box::new(w)),
+        OutputFormat::Influx => Target::Influx { writer: Box::new(w), measurement: measurement.to_owned() },
+    }
+}
+
+pub fn run(args: ConvertArgs) -> io::Result<()> {
+    let input: Box<dyn BufRead> = if args.input.as_os_str() == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.input).map_err(|e| with_path(args.input.display(), e))?))
+    };
+    let format = args.to.unwrap_or_else(|| output_format(args.output.as_deref()));
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path).map_err(|e| with_path(path.display(), e))?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut target = target(output, format, &args.measurement);
+    let mut slice = Slice::new(args.since, args.until, args.functions.clone()).with_meter(args.meter.clone());
+    let errors = convert(input, args.from, &mut slice, &mut target)?;
+    eprintln!("es51986: converted {} readings, skipped {}, {} invalid", slice.stats.written, slice.stats.skipped, errors);
+    let meters = &slice.stats.meters;
+    if matches!(target, Target::Sink(_)) && 1 < meters.len() {
+        let meters: Vec<&str> = meters.iter().map(|m| m.as_str()).collect();
+        eprintln!("es51986: readings of meters {} are merged; select one with --meter", meters.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use es51986::{Output, capture::{CaptureHeader, CaptureWriter, LineSettings}};
+
+    use super::*;
+
+    fn capture() -> Vec<u8> {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_296_496);
+        let mut w = CaptureWriter::new(vec![], CaptureHeader::new("test", LineSettings::default(), start)).unwrap();
+        for (i, frame) in ["11000;80:\r\n", "109853802\r\n", "11200;80:\r\n", "X\r\n"].iter().enumerate() {
+            w.write_chunk(start + Duration::from_secs(i as u64), frame.as_bytes()).unwrap();
+        }
+        w.into_inner()
+    }
+
+    #[test]
+    fn capture_to_csv_sliced() {
+        let mut out = vec![];
+        let mut csv = target(&mut out, OutputFormat::Csv, DEFAULT_MEASUREMENT);
+        let mut slice = Slice::new(Some(TimeBound::After(Duration::from_secs(1))), None, vec![Function::Voltage]);
+        let errors = convert(&capture()[..], None, &mut slice, &mut csv).unwrap();
+        drop(csv);
+        assert_eq!(errors, 1);
+        assert_eq!(slice.stats, ConvertStats { written: 1, skipped: 2, meters: BTreeSet::new() });
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,function,coupling,range,value,unit,overflow,battery\r\n2024-03-01T12:34:58.000Z,voltage,DC,1,12.00,V,0,0\r\n"
+        );
+    }
+
+    fn jsonl() -> String {
+        let reading = |millis, frame: &str| Reading::new(SystemTime::UNIX_EPOCH + Duration::from_millis(millis), Output::parse(frame.as_bytes()).unwrap());
+        format!(
+            "{}\n\n{}\n{{\"bad\":1}}\n{}\n",
+            Record::new(&reading(1500, "20989;806"), Some("bench")).to_json(),
+            Record::new(&reading(1600, "11000;80:"), Some("psu")).to_json(),
+            Record::new(&reading(2500, "20989;806"), Some("bench")).to_json(),
+        )
+    }
+
+    #[test]
+    fn jsonl_to_influx() {
+        let jsonl = jsonl();
+        assert_eq!(detect(jsonl.as_bytes()), InputFormat::Jsonl);
+        let mut out = vec![];
+        let mut influx = target(&mut out, OutputFormat::Influx, DEFAULT_MEASUREMENT);
+        let until = parse_time_bound("1970-01-01T00:00:02Z").unwrap();
+        let mut slice = Slice::new(None, Some(until), vec![]).with_meter(Some("bench".to_owned()));
+        assert_eq!(convert(jsonl.as_bytes(), None, &mut slice, &mut influx).unwrap(), 1);
+        drop(influx);
+        assert_eq!(slice.stats.skipped, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "es51986,function=voltage,coupling=AC,range=2,unit=V,device=bench value=98.9,overflow=false,battery_low=false,auto=true 1500000000\n"
+        );
+    }
+
+    #[test]
+    fn jsonl_keeps_meter_ids() {
+        let mut out = vec![];
+        let mut jsonl_target = target(&mut out, OutputFormat::Jsonl, DEFAULT_MEASUREMENT);
+        let mut slice = Slice::new(None, None, vec![]);
+        convert(jsonl().as_bytes(), None, &mut slice, &mut jsonl_target).unwrap();
+        drop(jsonl_target);
+        let meters: Vec<Option<String>> = String::from_utf8(out).unwrap().lines().map(|l| serde_json::from_str::<Record>(l).unwrap().meter).collect();
+        assert_eq!(meters, [Some("bench"), Some("psu"), Some("bench")].map(|m| m.map(str::to_owned)));
+        assert_eq!(slice.stats.meters.len(), 2);
+    }
+}
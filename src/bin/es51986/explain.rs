@@ -0,0 +1,182 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead},
+};
+
+use clap::Args;
+use es51986::{Function, Option2, Output, Range, Status, TemperatureUnit, parser::ParseError, reading::Reading};
+
+#[derive(Debug, Args)]
+pub struct ExplainArgs {
+    /// Frame as ASCII such as "00002?<0:", or hex such as "30 30 30 30 32 3f 3c 30 3a".
+    /// Frames are read from standard input, one per line, when omitted.
+    pub frame: Option<String>,
+
+    /// Treat the input as hex even if it could be ASCII.
+    #[arg(long)]
+    pub hex: bool,
+}
+
+const ROLES: [&str; 9] = ["range", "digit 1", "digit 2", "digit 3", "digit 4", "function", "status", "option1", "option2"];
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: String = s.split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .map(|t| t.trim_start_matches("0x").trim_start_matches("0X")).collect();
+    if digits.is_empty() || digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok()).collect()
+}
+
+/// Frame bytes of `input`. Hex is assumed when forced, or when the input is only hex digits and too long to be an ASCII frame.
+/// A trailing CR/LF, literal or written as `\r\n`, is removed.
+pub fn parse_input(input: &str, hex: bool) -> Result<Vec<u8>, String> {
+    let input = input.trim_end_matches(['\r', '\n']).trim_end_matches("\\n").trim_end_matches("\\r");
+    let compact: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    let looks_hex = 18 <= compact.len() && compact.chars().all(|c| c.is_ascii_hexdigit());
+    let mut bytes = if hex || looks_hex {
+        decode_hex(input).ok_or_else(|| format!("invalid hex '{}'", input))?
+    } else {
+        input.as_bytes().to_vec()
+    };
+    if bytes.ends_with(b"\r\n") {
+        bytes.truncate(bytes.len() - 2);
+    }
+    Ok(bytes)
+}
+
+fn bits(b: u8) -> String {
+    format!("{:04b} {:04b}", b >> 4, b & 0x0f)
+}
+
+fn flag(b: u8, bit: u8, name: &str) -> String {
+    format!("bit{} {}={}", bit, name, (b >> bit) & 1)
+}
+
+fn decode_byte(index: usize, b: u8) -> String {
+    match index {
+        0 => match Range::parse(b) {
+            Ok(range) => format!("{:?}", range),
+            Err(e) => format!("error: {:?}", e),
+        },
+        1..=4 => if b.is_ascii_digit() { (b - b'0').to_string() } else { format!("error: {:?}", ParseError::InvalidDigit(b)) },
+        5 => match Function::parse(b) {
+            Ok(function) => format!("{:?}", function),
+            Err(e) => format!("error: {:?}", e),
+        },
+        6 => {
+            let status = Status::parse(b);
+            let unit = match status.temperature_unit {
+                TemperatureUnit::Celsius => "Celsius",
+                TemperatureUnit::Fahrenheit => "Fahrenheit",
+            };
+            format!(
+                "{} ({}), {} ({}), {}, {}",
+                flag(b, 3, "celsius"), unit, flag(b, 2, "sign"), if status.sign.is_minus() { "minus" } else { "plus" },
+                flag(b, 1, "battery_low"), flag(b, 0, "overflow")
+            )
+        }
+        7 => "not used".to_owned(),
+        8 => {
+            let option2 = Option2::parse(b);
+            format!("{}, {}, {} ({})", flag(b, 3, "dc"), flag(b, 2, "ac"), flag(b, 1, "auto"), match option2.coupling() {
+                "" => "no coupling",
+                c => c,
+            })
+        }
+        _ => "extra byte".to_owned(),
+    }
+}
+
+/// Annotated dissection of `frame` (without CR/LF).
+pub fn explain(frame: &[u8]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "byte  hex   char  bits       role      decoded");
+    for (i, &b) in frame.iter().enumerate() {
+        let c = if b.is_ascii_graphic() || b == b' ' { format!("'{}'", b as char) } else { "   ".to_owned() };
+        let role = ROLES.get(i).copied().unwrap_or("-");
+        let _ = writeln!(out, "{:<4}  0x{:02x}  {:<4}  {}  {:<8}  {}", i, b, c, bits(b), role, decode_byte(i, b));
+    }
+    let output = match Output::parse(frame) {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = writeln!(out, "\nOutput::parse: error: {:?}", e);
+            return out;
+        }
+    };
+    out.push('\n');
+    match output.get_value() {
+        Some(value) => {
+            let decimals = value.digits.find('.').map_or(0, |p| value.digits.len() - p - 1);
+            let _ = writeln!(
+                out, "get_value: {:?} with {:?} -> {} decimal places -> \"{}\" {}",
+                output.range, output.function, decimals, value.digits, value.value_unit.symbol()
+            );
+            let reading = Reading::new(std::time::SystemTime::now(), output.clone());
+            match reading.si_value() {
+                Some(si) => {
+                    let _ = writeln!(
+                        out, "value: {} {} = {} {} (x10^{})",
+                        reading.format_value().unwrap_or_default(), value.value_unit.symbol(), si, value.value_unit.base_unit.symbol(),
+                        value.value_unit.prefix_unit.exponent()
+                    );
+                }
+                None => {
+                    let _ = writeln!(out, "value: none, the overflow flag is set");
+                }
+            }
+            let _ = writeln!(out, "reading: {}", reading);
+        }
+        None => {
+            let _ = writeln!(out, "get_value: none, {:?} has no defined scaling in {:?}", output.function, output.range);
+        }
+    }
+    out
+}
+
+pub fn run(args: ExplainArgs) -> io::Result<()> {
+    let frames: Vec<String> = match args.frame {
+        Some(frame) => vec![frame],
+        None => io::stdin().lock().lines().collect::<io::Result<_>>()?,
+    };
+    for (i, frame) in frames.iter().filter(|f| !f.trim().is_empty()).enumerate() {
+        if i != 0 {
+            println!();
+        }
+        match parse_input(frame, args.hex) {
+            Ok(bytes) => print!("{}", explain(&bytes)),
+            Err(e) => eprintln!("es51986: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input() {
+        assert_eq!(parse_input("00002?<0:", false).unwrap(), b"00002?<0:");
+        assert_eq!(parse_input("00002?<0:\\r\\n", false).unwrap(), b"00002?<0:");
+        assert_eq!(parse_input("30 30 30 30 32 3f 3c 30 3a 0d 0a", false).unwrap(), b"00002?<0:");
+        assert_eq!(parse_input("0x30,0x31", true).unwrap(), b"01");
+        assert!(parse_input("3g", true).is_err());
+        assert!(parse_input("aé0", true).is_err());
+    }
+
+    #[test]
+    fn dissection() {
+        let text = explain(b"00002?<0:");
+        assert!(text.contains("5     0x3f  '?'   0011 1111  function  MilliAmpere\n"), "{}", text);
+        assert!(text.contains("status    bit3 celsius=1 (Celsius), bit2 sign=1 (minus), bit1 battery_low=0, bit0 overflow=0\n"));
+        assert!(text.contains("option2   bit3 dc=1, bit2 ac=0, bit1 auto=1 (DC)\n"));
+        assert!(text.contains("get_value: Range0 with MilliAmpere -> 2 decimal places -> \"0.02\" mA\n"));
+        assert!(text.contains("value: -0.02 mA = -0.00002 A (x10^-3)\n"));
+        assert!(text.contains("reading: -0.02 mA DC AUTO\n"));
+
+        let text = explain(b"X0002?<0:");
+        assert!(text.contains("range     error: InvalidRange(88)\n"));
+        assert!(text.contains("Output::parse: error: InvalidRange(88)\n"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, Write},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::RecvTimeoutError},
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use es51986::{
+    capture::{self, LineSettings},
+    hub::{Aligner, Hub, Message, Slot},
+    jsonl::Record,
+    replay::Speed,
+    timestamp,
+};
+
+use crate::{args::{parse_duration, parse_positive_duration}, source::{SourceArgs, with_path}};
+
+/// Parse "ID=PATH".
+fn parse_meter(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((id, path)) if !id.is_empty() && !path.is_empty() => Ok((id.to_owned(), path.to_owned())),
+        _ => Err(format!("invalid meter '{}' (use ID=DEVICE or ID=CAPTURE, e.g. in=/dev/ttyUSB0)", s)),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct HubArgs {
+    /// Meter id and serial device, or a capture written by `es51986 record` (replayed in real time), e.g. in=/dev/ttyUSB0. Repeat for each meter.
+    #[arg(short, long = "meter", required = true, value_parser = parse_meter)]
+    pub meters: Vec<(String, String)>,
+
+    /// Baud rate of the serial devices.
+    #[arg(long, default_value_t = LineSettings::default().baud_rate)]
+    pub baud: u32,
+
+    /// Width of the time slots.
+    #[arg(long, default_value = "500ms", value_parser = parse_positive_duration)]
+    pub slot: Duration,
+
+    /// Leave a meter empty in a slot when its latest reading is older than this.
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub max_age: Duration,
+
+    /// Print JSON Lines, one object per slot, instead of text.
+    #[arg(long, conflicts_with = "csv")]
+    pub json: bool,
+
+    /// Print CSV with a value and a unit column per meter instead of text.
+    #[arg(long)]
+    pub csv: bool,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Pass the slots of `hub` to `on_slot` until every source ends or `stop` is set.
+pub fn merge<F>(hub: &mut Hub, aligner: &mut Aligner, stop: &AtomicBool, mut on_slot: F) -> io::Result<()>
+    where F: FnMut(&Slot) -> io::Result<()>
+{
+    // Lag of reading timestamps behind the clock, so that quiet slots of a replayed capture close at the right time.
+    let mut lag = Duration::ZERO;
+    while !stop.load(Ordering::SeqCst) {
+        let slots = match hub.recv_timeout(POLL_INTERVAL) {
+            Ok(Message::Reading(tagged)) => {
+                lag = SystemTime::now().duration_since(tagged.reading.timestamp).unwrap_or_default();
+                aligner.push(&tagged)
+            }
+            Ok(Message::Error { meter, error }) => {
+                eprintln!("es51986: {}: invalid frame: {:?}", meter, error);
+                continue;
+            }
+            Ok(Message::Closed { meter, error }) => {
+                if let Some(e) = error {
+                    eprintln!("es51986: {}: {}", meter, e);
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => aligner.tick(SystemTime::now() - lag),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        slots.iter().try_for_each(&mut on_slot)?;
+    }
+    aligner.finish().iter().try_for_each(on_slot)
+}
+
+fn write_text<W: Write>(w: &mut W, slot: &Slot) -> io::Result<()> {
+    write!(w, "{}", timestamp::rfc3339(slot.end()))?;
+    for (meter, reading) in &slot.readings {
+        match reading {
+            Some(reading) => write!(w, "  {}={}", meter, reading)?,
+            None => write!(w, "  {}=-", meter)?,
+        }
+    }
+    writeln!(w)
+}
+
+fn write_csv<W: Write>(w: &mut W, slot: &Slot) -> io::Result<()> {
+    write!(w, "{}", timestamp::rfc3339(slot.end()))?;
+    for (_, reading) in &slot.readings {
+        let value = reading.as_ref().and_then(|r| r.format_value()).unwrap_or_default();
+        let unit = reading.as_ref().and_then(|r| r.value_unit()).map(|u| u.symbol()).unwrap_or_default();
+        write!(w, ",{},{}", value, unit)?;
+    }
+    write!(w, "\r\n")
+}
+
+fn write_json<W: Write>(w: &mut W, slot: &Slot) -> io::Result<()> {
+    let readings: Vec<Record> = slot.readings.iter().filter_map(|(meter, r)| r.as_ref().map(|r| Record::new(r, Some(meter)))).collect();
+    let json = serde_json::json!({ "timestamp": timestamp::rfc3339(slot.end()), "readings": readings });
+    writeln!(w, "{}", json)
+}
+
+pub fn run(args: HubArgs, stop: &AtomicBool) -> io::Result<()> {
+    for (i, (id, _)) in args.meters.iter().enumerate() {
+        if args.meters[..i].iter().any(|(other, _)| other == id) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("meter id '{}' is given more than once", id)));
+        }
+    }
+    let mut hub = Hub::new();
+    for (id, path) in &args.meters {
+        if fs::metadata(path).is_ok_and(|m| m.is_file()) {
+            // Raw output in a file has no timestamps to align, so only captures are accepted.
+            let mut file = File::open(path).map_err(|e| with_path(path, e))?;
+            let mut magic = [0u8; 8];
+            if file.read_exact(&mut magic).is_err() || &magic != capture::MAGIC {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: not a capture (record one with es51986 record)", path)));
+            }
+            file.rewind()?;
+            hub.add_capture(id, BufReader::new(file), Speed::RealTime)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))?;
+        } else {
+            hub.add(id, SourceArgs { device: Some(path.clone()), file: None, baud: args.baud }.open()?);
+        }
+    }
+    let mut aligner = Aligner::new(hub.meters(), args.slot).with_max_age(args.max_age);
+    let mut out = io::stdout().lock();
+    if args.csv {
+        let columns: Vec<String> = args.meters.iter().map(|(id, _)| format!("{0},{0}_unit", id)).collect();
+        write!(out, "timestamp,{}\r\n", columns.join(","))?;
+    }
+    merge(&mut hub, &mut aligner, stop, |slot| {
+        if args.json {
+            write_json(&mut out, slot)?;
+        } else if args.csv {
+            write_csv(&mut out, slot)?;
+        } else {
+            write_text(&mut out, slot)?;
+        }
+        out.flush()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use es51986::capture::{CaptureHeader, CaptureWriter};
+
+    use super::*;
+
+    fn capture(chunks: &[(u64, &[u8])]) -> Vec<u8> {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut w = CaptureWriter::new(vec![], CaptureHeader::new("test", LineSettings::default(), start)).unwrap();
+        for (millis, data) in chunks {
+            w.write_chunk(start + Duration::from_millis(*millis), data).unwrap();
+        }
+        w.into_inner()
+    }
+
+    #[test]
+    fn merges_sources() {
+        let mut hub = Hub::new();
+        hub.add_capture("in", io::Cursor::new(capture(&[(100, b"11200;80:\r\n"), (700, b"11000;80:\r\n")])), Speed::RealTime).unwrap();
+        hub.add_capture("out", io::Cursor::new(capture(&[(200, b"X\r\n01000;80:\r\n")])), Speed::RealTime).unwrap();
+        let mut aligner = Aligner::new(hub.meters(), Duration::from_millis(500));
+        let mut out = vec![];
+        merge(&mut hub, &mut aligner, &AtomicBool::new(false), |slot| write_csv(&mut out, slot)).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "2023-11-14T22:13:20.500Z,12.00,V,1.000,V\r\n2023-11-14T22:13:21.000Z,10.00,V,1.000,V\r\n");
+        assert_eq!(parse_meter("in=/dev/ttyUSB0"), Ok(("in".to_owned(), "/dev/ttyUSB0".to_owned())));
+        assert!(parse_meter("/dev/ttyUSB0").is_err());
+    }
+}
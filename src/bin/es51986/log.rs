@@ -0,0 +1,122 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc},
+    time::Duration,
+};
+
+use clap::{Args, ValueEnum};
+use es51986::{logger::{LogFormat, RotatingLogger}, parser::Parser, reading::Reading, sink::Sink};
+
+use crate::{args::{parse_duration, parse_size}, source::{Chunk, SourceArgs, spawn_reader}};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Directory of the log files. Created if missing.
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    #[arg(long, value_enum, default_value = "jsonl")]
+    pub format: Format,
+
+    /// File name prefix.
+    #[arg(long, default_value = RotatingLogger::DEFAULT_PREFIX)]
+    pub prefix: String,
+
+    /// Start a new file at this size, e.g. 500k, 100M or 1G.
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Start a new file every day (UTC).
+    #[arg(long)]
+    pub daily: bool,
+
+    /// Maximum time between fsyncs.
+    #[arg(long, value_parser = parse_duration, default_value = "10s")]
+    pub sync_interval: Duration,
+
+    /// Meter id put in the JSON records.
+    #[arg(long)]
+    pub meter: Option<String>,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Log the readings decoded from `chunks` until the channel closes or `stop` is set. Returns the number of readings.
+pub fn log(chunks: mpsc::Receiver<io::Result<Chunk>>, logger: &mut RotatingLogger, stop: &AtomicBool) -> io::Result<usize> {
+    let mut parser = Parser::new();
+    let mut count = 0;
+    while !stop.load(Ordering::SeqCst) {
+        let (timestamp, data) = match chunks.recv_timeout(POLL_INTERVAL) {
+            Ok(chunk) => chunk?,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        for result in parser.parse(&data) {
+            match result {
+                Ok(output) => {
+                    let before = logger.current_path().map(|p| p.to_owned());
+                    logger.write(&Reading::new(timestamp, output))?;
+                    let after = logger.current_path();
+                    if before.as_deref() != after {
+                        eprintln!("es51986: logging to {}", after.unwrap().display());
+                    }
+                    count += 1;
+                }
+                Err(e) => eprintln!("es51986: invalid frame: {:?}", e),
+            }
+        }
+    }
+    logger.flush()?;
+    Ok(count)
+}
+
+pub fn run(args: LogArgs, stop: &AtomicBool) -> io::Result<()> {
+    let reader = args.source.open()?;
+    let format = match args.format {
+        Format::Jsonl => LogFormat::Jsonl,
+        Format::Csv => LogFormat::Csv,
+    };
+    let mut logger = RotatingLogger::new(&args.output, format)
+        .with_prefix(&args.prefix)
+        .with_daily(args.daily)
+        .with_sync_interval(args.sync_interval);
+    if let Some(max_size) = args.max_size {
+        logger = logger.with_max_size(max_size);
+    }
+    if let Some(meter) = &args.meter {
+        logger = logger.with_meter(meter);
+    }
+    let count = log(spawn_reader(reader), &mut logger, stop)?;
+    logger.finish()?;
+    eprintln!("es51986: logged {} readings", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn log_until_end_of_input() {
+        let dir = std::env::temp_dir().join(format!("es51986-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Csv);
+        let count = log(spawn_reader(&b"11000;80:\r\nX\r\n11200;80:\r\n"[..]), &mut logger, &AtomicBool::new(false)).unwrap();
+        assert_eq!(count, 2);
+        let text = fs::read_to_string(logger.current_path().unwrap()).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
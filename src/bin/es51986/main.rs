@@ -0,0 +1,93 @@
+//! `es51986` command-line tool.
+
+use std::{
+    process::ExitCode,
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+};
+
+use clap::{Parser, Subcommand};
+
+mod analyze;
+mod args;
+mod convert;
+mod explain;
+mod hub;
+mod log;
+mod read;
+mod record;
+mod replay;
+mod run;
+mod source;
+mod tui;
+mod watch;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Read and decode ES51986 multimeter output.")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Per-segment statistics of a capture or decoded log.
+    Analyze(analyze::AnalyzeArgs),
+    /// Print live readings as text, JSON Lines or CSV.
+    Read(read::ReadArgs),
+    /// Capture raw meter bytes with timestamps to a file.
+    Record(record::RecordArgs),
+    /// Replay a capture decoded to stdout or raw to a pseudo-terminal.
+    Replay(replay::ReplayArgs),
+    /// Convert a capture or decoded log to CSV, JSON Lines, InfluxDB line protocol or text.
+    Convert(convert::ConvertArgs),
+    /// Dissect a frame byte by byte.
+    Explain(explain::ExplainArgs),
+    /// Read several meters at once and print their readings side by side per time slot.
+    Hub(hub::HubArgs),
+    /// Log readings to rotating files until interrupted.
+    Log(log::LogArgs),
+    /// Run the meters, profiles and outputs of a configuration file.
+    Run(run::RunArgs),
+    /// Full-screen dashboard with large digits, bargraph and sparkline.
+    Tui(tui::TuiArgs),
+    /// Run hooks when readings meet conditions such as "DCV > 14.4".
+    Watch(watch::WatchArgs),
+}
+
+/// Flag set by Ctrl-C, for subcommands that run until interrupted.
+fn stop_flag() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = stop.clone();
+    if let Err(e) = ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst)) {
+        eprintln!("es51986: cannot handle Ctrl-C: {}", e);
+    }
+    stop
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Analyze(args) => analyze::run(args),
+        Command::Read(args) => read::run(args),
+        Command::Record(args) => record::run(args, &stop_flag()),
+        Command::Replay(args) => replay::run(args),
+        Command::Convert(args) => convert::run(args),
+        Command::Explain(args) => explain::run(args),
+        Command::Hub(args) => hub::run(args, &stop_flag()),
+        Command::Log(args) => log::run(args, &stop_flag()),
+        Command::Run(args) => run::run(args, &stop_flag()),
+        Command::Tui(args) => tui::run(args, &stop_flag()),
+        Command::Watch(args) => match watch::run(args, &stop_flag()) {
+            Ok(Some(status)) => return ExitCode::from(status),
+            result => result.map(|_| ()),
+        },
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("es51986: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Read, Write};
+
+use clap::Args;
+use es51986::{csv::CsvWriter, jsonl::JsonlWriter, reading::Reading, sink::Sink};
+
+use crate::source::{SourceArgs, for_each_reading};
+
+#[derive(Debug, Args)]
+pub struct ReadArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+/// Output format of decoded readings.
+#[derive(Debug, Clone, Args)]
+pub struct OutputArgs {
+    /// Print JSON Lines (see `es51986::jsonl`) instead of text.
+    #[arg(long, conflicts_with = "csv")]
+    pub json: bool,
+
+    /// Print CSV instead of text.
+    #[arg(long)]
+    pub csv: bool,
+
+    /// Meter id put in the JSON output.
+    #[arg(long)]
+    pub meter: Option<String>,
+}
+
+/// Human readable lines such as "-12.34 mV DC AUTO".
+pub struct TextWriter<W: Write>(pub W);
+
+impl<W: Write> Sink for TextWriter<W> {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        writeln!(self.0, "{}", reading)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl OutputArgs {
+    pub fn sink<'a, W: Write + 'a>(&self, w: W) -> Box<dyn Sink + 'a> {
+        if self.json {
+            let writer = JsonlWriter::new(w);
+            Box::new(match &self.meter {
+                Some(meter) => writer.with_meter(meter),
+                None => writer,
+            })
+        } else if self.csv {
+            Box::new(CsvWriter::new(w))
+        } else {
+            Box::new(TextWriter(w))
+        }
+    }
+}
+
+/// Print the readings of `reader` to `sink`. Frames that fail to decode are reported to stderr and skipped.
+pub fn print<R: Read>(reader: R, sink: &mut dyn Sink) -> io::Result<()> {
+    for_each_reading(reader, |result| {
+        match result {
+            Ok(reading) => {
+                sink.write(&reading)?;
+                sink.flush()?;
+            }
+            Err(e) => eprintln!("es51986: invalid frame: {:?}", e),
+        }
+        Ok(())
+    })
+}
+
+pub fn run(args: ReadArgs) -> io::Result<()> {
+    let reader = args.source.open()?;
+    let mut sink = args.output.sink(io::stdout().lock());
+    print(reader, &mut *sink)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    const INPUT: &[u8] = b"00002?<0:\r\n560003902\r\nX0000;<0:\r\n20989;806\r\n";
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: ReadArgs,
+    }
+
+    fn output(args: &[&str]) -> String {
+        let args = Cli::parse_from(args).args;
+        let mut out = vec![];
+        print(INPUT, &mut *args.output.sink(&mut out)).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(output(&["read"]), "-0.02 mA DC AUTO\nOL MΩ AUTO\n98.9 V AC AUTO\n");
+    }
+
+    #[test]
+    fn json() {
+        let out = output(&["read", "--json", "--meter", "bench"]);
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.starts_with("{\"schema\":1,"));
+        assert!(out.contains("\"meter\":\"bench\""));
+    }
+
+    #[test]
+    fn csv() {
+        let out = output(&["read", "--csv"]);
+        assert_eq!(out.lines().count(), 4);
+        assert!(out.starts_with("timestamp,"));
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc},
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::Args;
+use es51986::capture::{CaptureHeader, CaptureWriter};
+
+use crate::{args::parse_duration, source::{Chunk, SourceArgs, spawn_reader, with_path}};
+
+#[derive(Debug, Args)]
+pub struct RecordArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Capture file to write.
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Stop after this long, e.g. 30s, 10m or 2h. Without it, recording continues until Ctrl-C or the end of input.
+    #[arg(long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RecordStats {
+    pub chunks: usize,
+    pub bytes: usize,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Write the chunks from `chunks` to `writer` until the channel closes, `duration` elapses or `stop` is set.
+pub fn record<W: Write>(
+    chunks: mpsc::Receiver<io::Result<Chunk>>, writer: &mut CaptureWriter<W>, duration: Option<Duration>, stop: &AtomicBool
+) -> io::Result<RecordStats> {
+    let deadline = duration.map(|d| Instant::now() + d);
+    let mut stats = RecordStats::default();
+    while !stop.load(Ordering::SeqCst) && deadline.is_none_or(|d| Instant::now() < d) {
+        let (timestamp, data) = match chunks.recv_timeout(POLL_INTERVAL) {
+            Ok(chunk) => chunk?,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        writer.write_chunk(timestamp, &data)?;
+        // Keep the file usable if the process is killed.
+        writer.flush()?;
+        stats.chunks += 1;
+        stats.bytes += data.len();
+    }
+    Ok(stats)
+}
+
+pub fn run(args: RecordArgs, stop: &AtomicBool) -> io::Result<()> {
+    let reader = args.source.open()?;
+    let file = File::create(&args.output).map_err(|e| with_path(args.output.display(), e))?;
+    let header = CaptureHeader::new(&args.source.name(), args.source.line_settings(), SystemTime::now());
+    let mut writer = CaptureWriter::new(BufWriter::new(file), header)?;
+    eprintln!("es51986: recording to {}, press Ctrl-C to stop", args.output.display());
+    let stats = record(spawn_reader(reader), &mut writer, args.duration, stop)?;
+    writer.flush()?;
+    eprintln!("es51986: recorded {} bytes in {} chunks", stats.bytes, stats.chunks);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use es51986::{capture::{CaptureReader, LineSettings}, replay::{Replayer, Speed}};
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let header = CaptureHeader::new("test", LineSettings::default(), SystemTime::now());
+        let mut writer = CaptureWriter::new(vec![], header).unwrap();
+        let stats = record(spawn_reader(&b"00002?<0:\r\n20989;806\r\n"[..]), &mut writer, None, &AtomicBool::new(false)).unwrap();
+        assert_eq!(stats.bytes, 22);
+        let file = writer.into_inner();
+
+        assert_eq!(CaptureReader::new(&file[..]).unwrap().header().source, "test");
+        let mut out = vec![];
+        let replayed = Replayer::new(&file[..], Speed::AsFastAsPossible).unwrap().to_sink(&mut crate::read::TextWriter(&mut out)).unwrap();
+        assert_eq!(replayed.frames, 2);
+        assert_eq!(String::from_utf8(out).unwrap(), "-0.02 mA DC AUTO\n98.9 V AC AUTO\n");
+    }
+
+    #[test]
+    fn stop() {
+        let (_tx, rx) = mpsc::channel();
+        let header = CaptureHeader::new("test", LineSettings::default(), SystemTime::now());
+        let mut writer = CaptureWriter::new(vec![], header).unwrap();
+        let started = Instant::now();
+        record(rx, &mut writer, Some(Duration::from_millis(200)), &AtomicBool::new(false)).unwrap();
+        assert!(Duration::from_millis(200) <= started.elapsed());
+        assert_eq!(record(mpsc::channel().1, &mut writer, None, &AtomicBool::new(true)).unwrap(), RecordStats::default());
+    }
+}
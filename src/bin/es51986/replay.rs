@@ -0,0 +1,72 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::PathBuf,
+    time::Duration,
+};
+
+use clap::Args;
+use es51986::{capture::CaptureError, replay::{ReplayError, ReplayStats, Replayer, Speed}};
+
+use crate::{args::{parse_duration, parse_speed}, read::OutputArgs, source::with_path};
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Capture file written by `record`.
+    pub capture: PathBuf,
+
+    /// Playback speed factor, or "max" for no delay. Default is max for decoded output and 1 for --pty.
+    #[arg(long, value_parser = parse_speed)]
+    pub speed: Option<Speed>,
+
+    /// Re-emit the raw bytes to a new pseudo-terminal instead of decoding them. Open the printed path from the other program.
+    #[arg(long, conflicts_with_all = ["json", "csv"])]
+    pub pty: bool,
+
+    /// With --pty, wait this long after printing the path so that the other program can open it.
+    #[arg(long, default_value = "3s", value_parser = parse_duration)]
+    pub delay: Duration,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+fn to_io(e: ReplayError) -> io::Error {
+    match e {
+        ReplayError::Capture(CaptureError::Io(e)) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+#[cfg(unix)]
+fn replay_to_pty(replayer: Replayer<BufReader<File>>, delay: Duration) -> Result<ReplayStats, ReplayError> {
+    let mut pty = es51986::replay::Pty::open()?;
+    eprintln!("es51986: replaying to {}", pty.slave_path().display());
+    std::thread::sleep(delay);
+    replayer.to_writer(&mut pty)
+}
+
+#[cfg(not(unix))]
+fn replay_to_pty(_replayer: Replayer<BufReader<File>>, _delay: Duration) -> Result<ReplayStats, ReplayError> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--pty is only supported on Unix").into())
+}
+
+pub fn run(args: ReplayArgs) -> io::Result<()> {
+    let file = File::open(&args.capture).map_err(|e| with_path(args.capture.display(), e))?;
+    let default_speed = if args.pty { Speed::RealTime } else { Speed::AsFastAsPossible };
+    let replayer = Replayer::new(BufReader::new(file), args.speed.unwrap_or(default_speed))
+        .map_err(|e| with_path(args.capture.display(), to_io(e)))?;
+    let header = replayer.header();
+    eprintln!("es51986: {} recorded from {} with es51986 {}", args.capture.display(), header.source, header.crate_version);
+    let stats = if args.pty {
+        replay_to_pty(replayer, args.delay)
+    } else {
+        replayer.to_sink(&mut *args.output.sink(io::stdout().lock()))
+    }.map_err(to_io)?;
+    if args.pty {
+        eprintln!("es51986: replayed {} bytes in {} chunks", stats.bytes, stats.chunks);
+    } else {
+        eprintln!("es51986: replayed {} frames, {} invalid", stats.frames, stats.parse_errors);
+    }
+    Ok(())
+}
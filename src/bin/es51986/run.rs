@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::RecvTimeoutError},
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use es51986::{
+    config::{Config, MeterConfig, OutputConfig},
+    csv::{Column, CsvWriter},
+    filter::{Filter, FilterChain},
+    hub::{Hub, Message, Tagged},
+    jsonl::Record,
+    mqtt::MqttPublisher,
+    parser::ParseError,
+    reading::Reading,
+    replay::Speed,
+    sink::Sink,
+    watch::{Event, Watch},
+};
+
+use crate::source::{SourceArgs, with_path};
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Configuration file (TOML) with [[meter]], [profile.<name>] and [[output]] sections.
+    #[arg(short, long)]
+    pub config: PathBuf,
+
+    /// Only check the configuration.
+    #[arg(long)]
+    pub check: bool,
+}
+
+pub fn load(path: &Path) -> io::Result<Config> {
+    Config::load(path).map_err(|e| match e {
+        es51986::config::ConfigError::Io(e) => with_path(path.display(), e),
+        e => io::Error::new(io::ErrorKind::InvalidData, format!("{}:\n{}", path.display(), e)),
+    })
+}
+
+/// Start reading `meter` in `hub`.
+fn add_meter(hub: &mut Hub, meter: &MeterConfig) -> io::Result<()> {
+    match &meter.capture {
+        Some(capture) => {
+            let file = File::open(capture).map_err(|e| with_path(capture.display(), e))?;
+            let speed = meter.speed.as_ref().and_then(|s| s.speed().ok()).unwrap_or(Speed::RealTime);
+            hub.add_capture(&meter.id, io::BufReader::new(file), speed)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", capture.display(), e)))
+        }
+        None => {
+            let source = SourceArgs { device: meter.device.clone(), file: meter.file.clone(), baud: meter.line_settings().baud_rate };
+            hub.add(&meter.id, source.open()?);
+            Ok(())
+        }
+    }
+}
+
+fn open_output(path: &Path) -> io::Result<(Box<dyn Write>, bool)> {
+    if path.as_os_str() == "-" {
+        return Ok((Box::new(io::stdout()), true));
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| with_path(path.display(), e))?;
+    let empty = file.metadata()?.len() == 0;
+    Ok((Box::new(BufWriter::new(file)), empty))
+}
+
+enum Target {
+    Csv(Box<dyn Sink>),
+    Jsonl(Box<dyn Write>),
+    Mqtt(HashMap<String, MqttPublisher>, bool),
+    #[cfg(feature = "prometheus")]
+    Prometheus {
+        metrics: es51986::prometheus::Metrics,
+        /// Serves until dropped.
+        _server: es51986::server::Server,
+    },
+}
+
+struct Output {
+    meters: Vec<String>,
+    target: Target,
+}
+
+impl Output {
+    fn open(config: &Config, output: &OutputConfig) -> io::Result<Self> {
+        let meters: Vec<String> = config.meters_of(output).into_iter().map(|s| s.to_owned()).collect();
+        let target = match output {
+            OutputConfig::Csv { path, columns, .. } => {
+                let (w, empty) = open_output(path)?;
+                Target::Csv(Box::new(CsvWriter::new(w).with_columns(columns.as_deref().unwrap_or(&Column::ALL)).with_header(empty)))
+            }
+            OutputConfig::Jsonl { path, .. } => Target::Jsonl(open_output(path)?.0),
+            OutputConfig::Mqtt { host, port, prefix, username, password, discovery, retain, .. } => {
+                let publishers = meters.iter().map(|id| {
+                    let mut publisher = MqttPublisher::new(host, port.unwrap_or(1883), id).with_retain(*retain).with_availability(true);
+                    if let Some(prefix) = prefix {
+                        publisher = publisher.with_prefix(prefix);
+                    }
+                    if let (Some(username), Some(password)) = (username, password) {
+                        publisher = publisher.with_credentials(username, password);
+                    }
+                    if let Some(discovery) = discovery {
+                        publisher = publisher.with_discovery(discovery);
+                    }
+                    (id.clone(), publisher)
+                }).collect();
+                Target::Mqtt(publishers, false)
+            }
+            #[cfg(feature = "prometheus")]
+            OutputConfig::Prometheus { listen, .. } => {
+                let metrics = es51986::prometheus::Metrics::new();
+                let server = es51986::prometheus::serve(listen.as_str(), metrics.clone()).map_err(|e| with_path(listen, e))?;
+                eprintln!("es51986: serving metrics at http://{}/metrics", server.local_addr());
+                Target::Prometheus { metrics, _server: server }
+            }
+            #[cfg(not(feature = "prometheus"))]
+            OutputConfig::Prometheus { .. } => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "prometheus output needs es51986 built with the prometheus feature"));
+            }
+        };
+        Ok(Self { meters, target })
+    }
+
+    fn write(&mut self, meter: &str, reading: &Reading) -> io::Result<()> {
+        if !self.meters.iter().any(|m| m == meter) {
+            return Ok(());
+        }
+        match &mut self.target {
+            Target::Csv(sink) => {
+                sink.write(reading)?;
+                sink.flush()
+            }
+            Target::Jsonl(w) => {
+                writeln!(w, "{}", Record::new(reading, Some(meter)).to_json())?;
+                w.flush()
+            }
+            Target::Mqtt(publishers, failing) => {
+                let Some(publisher) = publishers.get_mut(meter) else { return Ok(()) };
+                // The broker may come and go. Report the change only, not every reading.
+                match publisher.write(reading) {
+                    Ok(()) if *failing => {
+                        eprintln!("es51986: mqtt: connected");
+                        *failing = false;
+                    }
+                    Err(e) if !*failing => {
+                        eprintln!("es51986: mqtt: {}", e);
+                        *failing = true;
+                    }
+                    _ => {}
+                }
+                Ok(())
+            }
+            #[cfg(feature = "prometheus")]
+            Target::Prometheus { metrics, .. } => {
+                metrics.record_reading(meter, reading);
+                Ok(())
+            }
+        }
+    }
+
+    /// Keep idle MQTT connections open.
+    fn keep_alive(&mut self) {
+        if let Target::Mqtt(publishers, _) = &mut self.target {
+            for publisher in publishers.values_mut() {
+                let _ = publisher.keep_alive();
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "prometheus"), allow(unused_variables))]
+    fn error(&mut self, meter: &str, err: &ParseError) {
+        #[cfg(feature = "prometheus")]
+        if let Target::Prometheus { metrics, .. } = &mut self.target {
+            if self.meters.iter().any(|m| m == meter) {
+                metrics.record_error(meter, err);
+            }
+        }
+    }
+}
+
+struct Meter {
+    id: String,
+    filters: FilterChain,
+    limits: Vec<Watch>,
+    /// How far reading timestamps lag behind the clock. Replayed captures keep their recorded times.
+    lag: Duration,
+}
+
+fn limit_event(event: Event) -> &'static str {
+    if event == Event::Fired { "exceeded" } else { "back within" }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn run(args: RunArgs, stop: &AtomicBool) -> io::Result<()> {
+    let config = load(&args.config)?;
+    if args.check {
+        println!("{}: {} meters, {} profiles, {} outputs", args.config.display(), config.meters.len(), config.profiles.len(), config.outputs.len());
+        return Ok(());
+    }
+    let mut outputs = config.outputs.iter().map(|o| Output::open(&config, o)).collect::<io::Result<Vec<_>>>()?;
+    let mut meters: Vec<Meter> = config.meters.iter().map(|m| {
+        let profile = config.profile(m);
+        Meter { id: m.id.clone(), filters: profile.filter_chain(), limits: profile.watches(), lag: Duration::ZERO }
+    }).collect();
+
+    let mut hub = Hub::new();
+    for meter in &config.meters {
+        if let Err(e) = add_meter(&mut hub, meter) {
+            eprintln!("es51986: {}: {}", meter.id, e);
+        }
+    }
+
+    while !stop.load(Ordering::SeqCst) {
+        match hub.recv_timeout(POLL_INTERVAL) {
+            Ok(Message::Reading(Tagged { meter, reading })) => {
+                let Some(meter) = meters.iter_mut().find(|m| m.id == meter) else { continue };
+                meter.lag = SystemTime::now().duration_since(reading.timestamp).unwrap_or_default();
+                let reading = meter.filters.apply(reading);
+                for watch in &mut meter.limits {
+                    if let Some(event) = watch.update(&reading) {
+                        eprintln!("es51986: {}: {} limit {}: {}", meter.id, limit_event(event), watch.condition(), reading);
+                    }
+                }
+                for output in &mut outputs {
+                    output.write(&meter.id, &reading)?;
+                }
+            }
+            Ok(Message::Error { meter, error }) => {
+                eprintln!("es51986: {}: invalid frame: {:?}", meter, error);
+                for output in &mut outputs {
+                    output.error(&meter, &error);
+                }
+            }
+            Ok(Message::Closed { meter, error }) => {
+                if let Some(e) = error {
+                    eprintln!("es51986: {}: {}", meter, e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        // "no data" limits and debounce complete without readings.
+        let now = SystemTime::now();
+        for meter in &mut meters {
+            for watch in &mut meter.limits {
+                if let Some(event) = watch.tick(now - meter.lag) {
+                    eprintln!("es51986: {}: {} limit {}", meter.id, limit_event(event), watch.condition());
+                }
+            }
+        }
+        for output in &mut outputs {
+            output.keep_alive();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn file_to_jsonl() {
+        let dir = std::env::temp_dir().join(format!("es51986-run-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("raw.txt"), "11000;80:\r\n11200;80:\r\n").unwrap();
+        let config = format!(
+            "[[meter]]\nid = \"m\"\nfile = \"{0}/raw.txt\"\nprofile = \"p\"\n\n[profile.p]\nrel = \"first\"\n\n[[output]]\ntype = \"jsonl\"\npath = \"{0}/out.jsonl\"\n",
+            dir.display()
+        );
+        fs::write(dir.join("bench.toml"), config).unwrap();
+        run(RunArgs { config: dir.join("bench.toml"), check: false }, &AtomicBool::new(false)).unwrap();
+        let out = fs::read_to_string(dir.join("out.jsonl")).unwrap();
+        let values: Vec<Option<f64>> = out.lines().map(|l| serde_json::from_str::<Record>(l).unwrap().value).collect();
+        assert_eq!(values, vec![Some(0.0), Some(2.0)]);
+        assert!(out.contains(r#""meter":"m""#));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use es51986::{
+    capture::{LineSettings, Parity},
+    parser::{ParseError, Parser},
+    reading::Reading,
+};
+
+/// Where the meter bytes come from. Standard input is used when neither `--device` nor `--file` is given.
+#[derive(Debug, Clone, Args)]
+pub struct SourceArgs {
+    /// Serial device of the meter, e.g. /dev/ttyUSB0 or COM3.
+    #[arg(short, long, conflicts_with = "file")]
+    pub device: Option<String>,
+
+    /// File with raw meter output. "-" is standard input.
+    #[arg(short, long)]
+    pub file: Option<PathBuf>,
+
+    /// Baud rate of the serial device.
+    #[arg(long, default_value_t = LineSettings::default().baud_rate)]
+    pub baud: u32,
+}
+
+impl SourceArgs {
+    /// Description of the source for capture headers.
+    pub fn name(&self) -> String {
+        match (&self.device, &self.file) {
+            (Some(device), _) => device.clone(),
+            (None, Some(file)) => file.display().to_string(),
+            (None, None) => "-".to_owned(),
+        }
+    }
+
+    pub fn line_settings(&self) -> LineSettings {
+        LineSettings { baud_rate: self.baud, ..LineSettings::default() }
+    }
+
+    pub fn open(&self) -> io::Result<Box<dyn Read + Send>> {
+        match (&self.device, &self.file) {
+            (Some(device), _) => open_serial(device, &self.line_settings()).map_err(|e| with_path(device, e)),
+            (None, Some(file)) if file.as_os_str() != "-" => Ok(Box::new(File::open(file).map_err(|e| with_path(file.display(), e))?)),
+            _ => Ok(Box::new(io::stdin())),
+        }
+    }
+}
+
+/// Prefix the error message with the path it relates to.
+pub fn with_path<P: std::fmt::Display>(path: P, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+/// Serial port that keeps waiting instead of failing when no data arrives within the port timeout.
+struct SerialReader(Box<dyn serialport::SerialPort>);
+
+impl Read for SerialReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                r => return r,
+            }
+        }
+    }
+}
+
+fn open_serial(path: &str, line: &LineSettings) -> io::Result<Box<dyn Read + Send>> {
+    let data_bits = match line.data_bits {
+        5 => serialport::DataBits::Five,
+        6 => serialport::DataBits::Six,
+        7 => serialport::DataBits::Seven,
+        _ => serialport::DataBits::Eight,
+    };
+    let parity = match line.parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Odd => serialport::Parity::Odd,
+        Parity::Even => serialport::Parity::Even,
+    };
+    let stop_bits = if line.stop_bits == 2 { serialport::StopBits::Two } else { serialport::StopBits::One };
+    let mut port = serialport::new(path, line.baud_rate)
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .timeout(Duration::from_secs(1))
+        .open()?;
+    // Opto-isolated cables take their power from DTR.
+    port.write_data_terminal_ready(true)?;
+    port.write_request_to_send(false)?;
+    Ok(Box::new(SerialReader(port)))
+}
+
+/// Decode `reader` until the end of input, calling `f` for every frame. Readings are stamped with the arrival time.
+pub fn for_each_reading<R, F>(mut reader: R, mut f: F) -> io::Result<()>
+    where R: Read, F: FnMut(Result<Reading, ParseError>) -> io::Result<()>
+{
+    let mut parser = Parser::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for result in parser.parse(&buf[..len]) {
+            f(result.map(Reading::now))?;
+        }
+    }
+}
+
+/// Bytes read at one time, stamped with the arrival time.
+pub type Chunk = (SystemTime, Vec<u8>);
+
+/// Read `reader` in a background thread, so that the caller can wait with a timeout and stop on Ctrl-C.
+/// The channel is closed at the end of input. A read error is sent as the last item.
+pub fn spawn_reader<R: Read + Send + 'static>(mut reader: R) -> mpsc::Receiver<io::Result<Chunk>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 256];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return,
+                Ok(len) => {
+                    if tx.send(Ok((SystemTime::now(), buf[..len].to_vec()))).is_err() {
+                        return;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
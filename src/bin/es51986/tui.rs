@@ -0,0 +1,380 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc},
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    terminal::{self, ClearType},
+};
+use es51986::{parser::Parser, reading::{MeasurementKey, Reading}, timestamp};
+
+use crate::source::{SourceArgs, spawn_reader};
+
+#[derive(Debug, Args)]
+pub struct TuiArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+}
+
+const HISTORY: usize = 200;
+const FULL_SCALE_COUNTS: f64 = 10000.0;
+const KEYS: &str = "q quit  r reset stats  z REL  h hold  m mark";
+
+/// Segments a-g of a seven-segment digit.
+fn segments(c: char) -> [bool; 7] {
+    let bits: u8 = match c {
+        '0' | 'O' => 0b0111111,
+        '1' => 0b0000110,
+        '2' => 0b1011011,
+        '3' => 0b1001111,
+        '4' => 0b1100110,
+        '5' => 0b1101101,
+        '6' => 0b1111101,
+        '7' => 0b0000111,
+        '8' => 0b1111111,
+        '9' => 0b1101111,
+        '-' => 0b1000000,
+        'L' => 0b0111000,
+        _ => 0,
+    };
+    std::array::from_fn(|i| bits & (1 << i) != 0)
+}
+
+/// `text` drawn as five rows of seven-segment characters.
+pub fn big_text(text: &str) -> [String; 5] {
+    let mut rows: [String; 5] = Default::default();
+    for c in text.chars() {
+        if c == '.' {
+            for (i, row) in rows.iter_mut().enumerate() {
+                row.push_str(if i == 4 { "█ " } else { "  " });
+            }
+            continue;
+        }
+        let [a, b, c, d, e, f, g] = segments(c);
+        let h = |on: bool| if on { "██" } else { "  " };
+        let v = |on: bool| if on { '█' } else { ' ' };
+        rows[0].push_str(&format!(" {}  ", h(a)));
+        rows[1].push_str(&format!("{}  {} ", v(f), v(b)));
+        rows[2].push_str(&format!(" {}  ", h(g)));
+        rows[3].push_str(&format!("{}  {} ", v(e), v(c)));
+        rows[4].push_str(&format!(" {}  ", h(d)));
+    }
+    rows
+}
+
+fn sparkline(values: &VecDeque<f64>, width: usize) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let shown: Vec<f64> = values.iter().rev().take(width).rev().copied().collect();
+    let min = shown.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = shown.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    shown.iter().map(|v| {
+        if max <= min { BARS[0] } else { BARS[(((v - min) / (max - min)) * 7.0).round() as usize] }
+    }).collect()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Stats {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: usize,
+}
+
+impl Stats {
+    fn push(&mut self, v: f64) {
+        if self.count == 0 {
+            self.min = v;
+            self.max = v;
+        }
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v;
+        self.count += 1;
+    }
+}
+
+/// Everything shown on the screen. Kept apart from the terminal so that it can be tested.
+#[derive(Debug, Default)]
+pub struct Screen {
+    source: String,
+    latest: Option<Reading>,
+    key: Option<MeasurementKey>,
+    history: VecDeque<f64>,
+    stats: Stats,
+    rel: Option<f64>,
+    hold: bool,
+    marks: Vec<(SystemTime, String)>,
+    parse_errors: usize,
+    ended: bool,
+}
+
+impl Screen {
+    pub fn new(source: &str) -> Self {
+        Self { source: source.to_owned(), ..Self::default() }
+    }
+
+    pub fn push(&mut self, reading: Reading) {
+        if self.hold {
+            return;
+        }
+        let key = reading.key();
+        if self.key != Some(key) {
+            // Values of another function or range are not comparable.
+            self.key = Some(key);
+            self.history.clear();
+            self.stats = Stats::default();
+            self.rel = None;
+        }
+        if let Some(v) = reading.value {
+            self.history.push_back(v);
+            if HISTORY < self.history.len() {
+                self.history.pop_front();
+            }
+            self.stats.push(v);
+        }
+        self.latest = Some(reading);
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+        self.history.clear();
+    }
+
+    /// Set the current value as the reference, or clear the reference if set.
+    pub fn toggle_rel(&mut self) {
+        self.rel = match self.rel {
+            Some(_) => None,
+            None => self.latest.as_ref().and_then(|r| r.value),
+        };
+    }
+
+    pub fn toggle_hold(&mut self) {
+        self.hold = !self.hold;
+    }
+
+    pub fn mark(&mut self) {
+        let text = self.latest.as_ref().map_or_else(|| "no reading".to_owned(), |r| r.to_string());
+        self.marks.push((SystemTime::now(), text));
+    }
+
+    pub fn marks(&self) -> &[(SystemTime, String)] {
+        &self.marks
+    }
+
+    fn decimals(&self) -> usize {
+        self.latest.as_ref().and_then(|r| r.output.get_value())
+            .map_or(0, |v| v.digits.find('.').map_or(0, |p| v.digits.len() - p - 1))
+    }
+
+    fn format(&self, v: f64) -> String {
+        format!("{:.*}", self.decimals(), v)
+    }
+
+    fn unit(&self) -> String {
+        self.latest.as_ref().map_or_else(String::new, |r| r.value_unit().map_or_else(|| r.output.function.name().to_owned(), |u| u.symbol()))
+    }
+
+    /// The screen as lines of at most `width` columns.
+    pub fn render(&self, width: usize) -> Vec<String> {
+        let mut lines = vec![format!("ES51986  {}", self.source)];
+        let reading = self.latest.as_ref();
+        let value = reading.map_or("----".to_owned(), |r| {
+            if r.output.status.is_overflow {
+                "OL".to_owned()
+            } else {
+                r.value.map_or("----".to_owned(), |v| self.format(v - self.rel.unwrap_or(0.0)))
+            }
+        });
+        lines.push(String::new());
+        let mut big = big_text(&value);
+        big[2].push_str(&format!("  {}", self.unit()));
+        lines.extend(big);
+        lines.push(String::new());
+
+        let flag = |on: bool, name: &str| if on { name.to_owned() } else { " ".repeat(name.len()) };
+        let (status, option2) = match reading {
+            Some(r) => ((r.output.status.is_overflow, r.output.status.is_battery_depleted), (r.output.option2.is_auto, r.output.option2.is_dc, r.output.option2.is_ac)),
+            None => ((false, false), (false, false, false)),
+        };
+        lines.push([
+            flag(option2.0, "AUTO"), flag(option2.1, "DC"), flag(option2.2, "AC"), flag(self.hold, "HOLD"),
+            flag(self.rel.is_some(), "REL"), flag(status.1, "BAT"), flag(status.0, "OL"),
+        ].join("  "));
+
+        let bar_width = width.saturating_sub(20).max(10);
+        let fraction = reading.and_then(|r| {
+            if r.output.status.is_overflow { return Some(1.0) }
+            r.value.map(|v| (v.abs() * 10f64.powi(self.decimals() as i32) / FULL_SCALE_COUNTS).min(1.0))
+        }).unwrap_or(0.0);
+        let filled = (fraction * bar_width as f64).round() as usize;
+        lines.push(format!("[{}{}] {:>3.0}% FS", "█".repeat(filled), "░".repeat(bar_width - filled), fraction * 100.0));
+        lines.push(sparkline(&self.history, width));
+        lines.push(String::new());
+
+        if self.stats.count == 0 {
+            lines.push("min ----  max ----  avg ----  n 0".to_owned());
+        } else {
+            let unit = self.unit();
+            lines.push(format!(
+                "min {} {}  max {} {}  avg {} {}  n {}",
+                self.format(self.stats.min), unit, self.format(self.stats.max), unit,
+                self.format(self.stats.sum / self.stats.count as f64), unit, self.stats.count
+            ));
+        }
+        if let Some(rel) = self.rel {
+            lines.push(format!("REL reference {} {}", self.format(rel), self.unit()));
+        }
+        if let Some((time, text)) = self.marks.last() {
+            lines.push(format!("mark #{} at {}: {}", self.marks.len(), timestamp::rfc3339(*time), text));
+        }
+        if 0 < self.parse_errors {
+            lines.push(format!("{} invalid frames", self.parse_errors));
+        }
+        if self.ended {
+            lines.push("end of input".to_owned());
+        }
+        lines.push(String::new());
+        lines.push(KEYS.to_owned());
+        lines.into_iter().map(|l| l.chars().take(width).collect()).collect()
+    }
+}
+
+/// Restores the terminal even when the loop fails.
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self)
+    }
+
+    fn draw(&self, lines: &[String]) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        queue!(out, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+        for (i, line) in lines.iter().enumerate() {
+            queue!(out, cursor::MoveTo(0, i as u16))?;
+            write!(out, "{}", line)?;
+        }
+        out.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub fn run(args: TuiArgs, stop: &AtomicBool) -> io::Result<()> {
+    let chunks = spawn_reader(args.source.open()?);
+    let mut screen = Screen::new(&args.source.name());
+    let mut parser = Parser::new();
+    {
+        let terminal = Terminal::enter()?;
+        let mut dirty = true;
+        while !stop.load(Ordering::SeqCst) {
+            loop {
+                match chunks.try_recv() {
+                    Ok(chunk) => {
+                        let (timestamp, data) = chunk?;
+                        for result in parser.parse(&data) {
+                            match result {
+                                Ok(output) => screen.push(Reading::new(timestamp, output)),
+                                Err(_) => screen.parse_errors += 1,
+                            }
+                        }
+                        dirty = true;
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        dirty |= !screen.ended;
+                        screen.ended = true;
+                        break;
+                    }
+                }
+            }
+            if event::poll(Duration::from_millis(50))? {
+                match event::read()? {
+                    Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => break,
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                        KeyCode::Char('r') => screen.reset_stats(),
+                        KeyCode::Char('z') => screen.toggle_rel(),
+                        KeyCode::Char('h') => screen.toggle_hold(),
+                        KeyCode::Char('m') => screen.mark(),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                dirty = true;
+            }
+            if dirty {
+                let (width, _) = terminal::size()?;
+                terminal.draw(&screen.render(width as usize))?;
+                dirty = false;
+            }
+        }
+    }
+    for (i, (time, text)) in screen.marks().iter().enumerate() {
+        println!("mark #{} at {}: {}", i + 1, timestamp::rfc3339(*time), text);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use es51986::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn digits() {
+        assert_eq!(big_text("-1.0"), [
+            "             ██  ",
+            "        █   █  █ ",
+            " ██              ",
+            "        █   █  █ ",
+            "          █  ██  ",
+        ].map(|s| s.to_owned()));
+    }
+
+    #[test]
+    fn screen() {
+        let mut screen = Screen::new("test");
+        screen.push(reading("11000;80:"));
+        screen.push(reading("11200;80:"));
+        let lines = screen.render(80);
+        assert!(lines[4].ends_with("  V"));
+        assert!(lines[8].starts_with("AUTO  DC"));
+        assert!(lines[9].ends_with(" 12% FS"));
+        assert_eq!(lines[12], "min 10.00 V  max 12.00 V  avg 11.00 V  n 2");
+
+        screen.toggle_rel();
+        screen.toggle_hold();
+        screen.push(reading("11000;80:"));
+        let lines = screen.render(80);
+        assert!(lines[8].contains("HOLD  REL"));
+        assert!(lines.contains(&"REL reference 12.00 V".to_owned()));
+
+        screen.toggle_hold();
+        screen.mark();
+        screen.push(reading("00002?<0:"));
+        // A new function starts new statistics and clears REL.
+        let lines = screen.render(80);
+        assert_eq!(lines[12], "min -0.02 mA  max -0.02 mA  avg -0.02 mA  n 1");
+        assert!(!lines[8].contains("REL"));
+        assert_eq!(screen.marks()[0].1, "12.00 V DC AUTO");
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use es51986::{parser::Parser, reading::Reading, timestamp, watch::{Condition, Event, Watch}};
+
+use crate::{args::parse_duration, source::{Chunk, SourceArgs, spawn_reader}};
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Condition such as "DCV > 14.4", "A <= -2m", "overflow", "battery_low" or "no data for 10s". Repeat for several.
+    #[arg(short, long = "condition", required = true, value_parser = parse_condition)]
+    pub conditions: Vec<Condition>,
+
+    /// Shell command run when a condition fires. ES51986_EVENT, ES51986_CONDITION and ES51986_READING are set.
+    #[arg(long)]
+    pub exec: Option<String>,
+
+    /// FIFO or file to append event lines to. Events are dropped while a FIFO has no reader.
+    #[arg(long)]
+    pub fifo: Option<PathBuf>,
+
+    /// Exit when a condition fires, with status 10 for the first condition, 11 for the second and so on.
+    #[arg(long)]
+    pub exit: bool,
+
+    /// Also run the hooks when a condition clears.
+    #[arg(long)]
+    pub on_clear: bool,
+
+    /// How long a condition has to hold, or stop holding, before it fires or clears.
+    #[arg(long, value_parser = parse_duration, default_value = "0s")]
+    pub debounce: Duration,
+
+    /// Distance in the threshold's unit that the value has to move back before a threshold clears.
+    #[arg(long, default_value_t = 0.0)]
+    pub hysteresis: f64,
+}
+
+fn parse_condition(s: &str) -> Result<Condition, String> {
+    s.parse().map_err(|e: es51986::watch::ConditionError| e.to_string())
+}
+
+/// Exit status for the condition at `index`.
+pub fn exit_status(index: usize) -> u8 {
+    10u8.saturating_add(index.min(u8::MAX as usize) as u8)
+}
+
+/// A state change of one condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fired {
+    pub index: usize,
+    pub event: Event,
+    pub timestamp: SystemTime,
+    /// The reading that caused the change. None for "no data".
+    pub reading: Option<String>,
+}
+
+fn event_name(event: Event) -> &'static str {
+    match event {
+        Event::Fired => "fired",
+        Event::Cleared => "cleared",
+    }
+}
+
+pub struct Watcher {
+    watches: Vec<Watch>,
+}
+
+impl Watcher {
+    pub fn new(conditions: &[Condition], debounce: Duration, hysteresis: f64) -> Self {
+        Self { watches: conditions.iter().map(|c| Watch::new(c.clone()).with_debounce(debounce).with_hysteresis(hysteresis)).collect() }
+    }
+
+    pub fn update(&mut self, reading: &Reading) -> Vec<Fired> {
+        let text = reading.to_string();
+        self.watches.iter_mut().enumerate().filter_map(|(index, watch)| {
+            watch.update(reading).map(|event| Fired { index, event, timestamp: reading.timestamp, reading: Some(text.clone()) })
+        }).collect()
+    }
+
+    pub fn tick(&mut self, now: SystemTime) -> Vec<Fired> {
+        self.watches.iter_mut().enumerate().filter_map(|(index, watch)| {
+            watch.tick(now).map(|event| Fired { index, event, timestamp: now, reading: None })
+        }).collect()
+    }
+
+    /// Line such as "2024-03-01T12:34:56.789Z fired DCV > 14.4: 14.52 V DC AUTO".
+    pub fn describe(&self, fired: &Fired) -> String {
+        let mut line = format!("{} {} {}", timestamp::rfc3339(fired.timestamp), event_name(fired.event), self.watches[fired.index].condition());
+        if let Some(reading) = &fired.reading {
+            line.push_str(": ");
+            line.push_str(reading);
+        }
+        line
+    }
+}
+
+struct Hooks<'a> {
+    args: &'a WatchArgs,
+}
+
+impl Hooks<'_> {
+    fn run(&self, watcher: &Watcher, fired: &Fired) {
+        let line = watcher.describe(fired);
+        println!("{}", line);
+        if fired.event == Event::Cleared && !self.args.on_clear {
+            return;
+        }
+        if let Some(command) = &self.args.exec {
+            let child = Command::new("sh").arg("-c").arg(command)
+                .env("ES51986_EVENT", event_name(fired.event))
+                .env("ES51986_CONDITION", watcher.watches[fired.index].condition().to_string())
+                .env("ES51986_READING", fired.reading.as_deref().unwrap_or(""))
+                .spawn();
+            // Started right away so that it runs even if we exit next, but waited for in the background so as not to hold up the readings.
+            match child {
+                Ok(mut child) => {
+                    thread::spawn(move || match child.wait() {
+                        Ok(status) if !status.success() => eprintln!("es51986: hook exited with {}", status),
+                        Ok(_) => {}
+                        Err(e) => eprintln!("es51986: hook: {}", e),
+                    });
+                }
+                Err(e) => eprintln!("es51986: cannot run hook: {}", e),
+            }
+        }
+        if let Some(path) = &self.args.fifo {
+            if let Err(e) = append_line(path, &line) {
+                eprintln!("es51986: cannot write to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn append_line(path: &Path, line: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // Fail with ENXIO instead of blocking when a FIFO has no reader.
+        options.custom_flags(libc::O_NONBLOCK);
+    }
+    writeln!(options.open(path)?, "{}", line)
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Watch the readings decoded from `chunks` until the channel closes or `stop` is set.
+/// `on_event` returns true to stop watching. The index of the condition that stopped it is returned.
+pub fn watch<F: FnMut(&Watcher, &Fired) -> bool>(
+    chunks: mpsc::Receiver<io::Result<Chunk>>, watcher: &mut Watcher, stop: &AtomicBool, mut on_event: F
+) -> io::Result<Option<usize>> {
+    let mut parser = Parser::new();
+    while !stop.load(Ordering::SeqCst) {
+        let mut events = match chunks.recv_timeout(POLL_INTERVAL) {
+            Ok(chunk) => {
+                let (timestamp, data) = chunk?;
+                let mut events = vec![];
+                for result in parser.parse(&data) {
+                    match result {
+                        Ok(output) => events.extend(watcher.update(&Reading::new(timestamp, output))),
+                        Err(e) => eprintln!("es51986: invalid frame: {:?}", e),
+                    }
+                }
+                events
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => vec![],
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        events.extend(watcher.tick(SystemTime::now()));
+        for fired in events {
+            if on_event(watcher, &fired) {
+                return Ok(Some(fired.index));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the exit status when a condition fired with --exit.
+pub fn run(args: WatchArgs, stop: &AtomicBool) -> io::Result<Option<u8>> {
+    let reader = args.source.open()?;
+    let mut watcher = Watcher::new(&args.conditions, args.debounce, args.hysteresis);
+    let hooks = Hooks { args: &args };
+    let stopped = watch(spawn_reader(reader), &mut watcher, stop, |watcher, fired| {
+        hooks.run(watcher, fired);
+        args.exit && fired.event == Event::Fired
+    })?;
+    Ok(stopped.map(exit_status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_on_first_event() {
+        let conditions = ["battery_low".parse().unwrap(), "V > 11".parse().unwrap()];
+        let mut watcher = Watcher::new(&conditions, Duration::ZERO, 0.0);
+        let mut lines = vec![];
+        let input = &b"11000;80:\r\n11200;80:\r\n11000;:0:\r\n"[..];
+        let stopped = watch(spawn_reader(input), &mut watcher, &AtomicBool::new(false), |watcher, fired| {
+            lines.push(watcher.describe(fired));
+            fired.event == Event::Fired
+        }).unwrap();
+        assert_eq!(stopped, Some(1));
+        assert_eq!(exit_status(1), 11);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with(" fired V > 11: 12.00 V DC AUTO"), "{}", lines[0]);
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BaseUnit, Function, reading::Reading};
+
+const E6: [u16; 6] = [10, 15, 22, 33, 47, 68];
+const E12: [u16; 12] = [10, 12, 15, 18, 22, 27, 33, 39, 47, 56, 68, 82];
+const E24: [u16; 24] = [
+    10, 11, 12, 13, 15, 16, 18, 20, 22, 24, 27, 30, 33, 36, 39, 43, 47, 51, 56, 62, 68, 75, 82, 91,
+];
+const E96: [u16; 96] = [
+    100, 102, 105, 107, 110, 113, 115, 118, 121, 124, 127, 130, 133, 137, 140, 143, 147, 150, 154, 158, 162, 165, 169, 174,
+    178, 182, 187, 191, 196, 200, 205, 210, 215, 221, 226, 232, 237, 243, 249, 255, 261, 267, 274, 280, 287, 294, 301, 309,
+    316, 324, 332, 340, 348, 357, 365, 374, 383, 392, 402, 412, 422, 432, 442, 453, 464, 475, 487, 499, 511, 523, 536, 549,
+    562, 576, 590, 604, 619, 634, 649, 665, 681, 698, 715, 732, 750, 768, 787, 806, 825, 845, 866, 887, 909, 931, 953, 976,
+];
+
+/// IEC 60063 preferred number series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ESeries {
+    E6,
+    E12,
+    E24,
+    E96,
+}
+
+impl ESeries {
+    /// Significands of one decade. E6, E12 and E24 have two digits, E96 has three.
+    pub fn values(&self) -> &'static [u16] {
+        match self {
+            Self::E6 => &E6,
+            Self::E12 => &E12,
+            Self::E24 => &E24,
+            Self::E96 => &E96,
+        }
+    }
+
+    fn digits(&self) -> i32 {
+        match self {
+            Self::E96 => 3,
+            _ => 2,
+        }
+    }
+
+    /// Nominal tolerance of the series in percent.
+    pub fn tolerance(&self) -> f64 {
+        match self {
+            Self::E6 => 20.0,
+            Self::E12 => 10.0,
+            Self::E24 => 5.0,
+            Self::E96 => 1.0,
+        }
+    }
+}
+
+/// Nominal value `significand * 10^exponent` in the base unit (Ohm or Farad).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Nominal {
+    pub significand: u16,
+    pub exponent: i32,
+}
+
+impl Nominal {
+    pub fn value(&self) -> f64 {
+        self.significand as f64 * 10f64.powi(self.exponent)
+    }
+}
+
+/// Smallest standard tolerance that covers the deviation from the nominal value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ToleranceClass {
+    Percent1,
+    Percent2,
+    Percent5,
+    Percent10,
+    Percent20,
+    /// Deviation exceeds 20%.
+    OutOfTolerance,
+}
+
+impl ToleranceClass {
+    pub fn of(deviation_percent: f64) -> Self {
+        let d = deviation_percent.abs();
+        if d <= 1.0 {
+            Self::Percent1
+        } else if d <= 2.0 {
+            Self::Percent2
+        } else if d <= 5.0 {
+            Self::Percent5
+        } else if d <= 10.0 {
+            Self::Percent10
+        } else if d <= 20.0 {
+            Self::Percent20
+        } else {
+            Self::OutOfTolerance
+        }
+    }
+}
+
+/// Binning errors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BinError {
+    /// Only `Function::Ohm` and `Function::Capacitor` can be binned.
+    UnsupportedFunction(Function),
+    /// The reading has no value (overflow frame, open lead).
+    NoValue,
+    /// Zero or negative value has no nominal value.
+    NotPositive(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bin {
+    pub base_unit: BaseUnit,
+    pub nominal: Nominal,
+    /// Measured value in the base unit.
+    pub measured: f64,
+    /// `(measured - nominal) / nominal` in percent.
+    pub deviation_percent: f64,
+    pub tolerance_class: ToleranceClass,
+}
+
+/// Nearest nominal value of `series` for a positive `value`.
+pub fn nearest(series: ESeries, value: f64) -> Nominal {
+    let digits = series.digits();
+    let decade = value.log10().floor() as i32 - (digits - 1);
+    // Look at the neighbouring decades as well, since floor(log10) may be off by one near decade boundaries.
+    (decade - 1..=decade + 1)
+        .flat_map(|exponent| series.values().iter().map(move |&significand| Nominal { significand, exponent }))
+        .min_by(|a, b| (value / a.value()).ln().abs().total_cmp(&(value / b.value()).ln().abs()))
+        .unwrap()
+}
+
+/// Sorts components by nominal value and keeps per-bin counts for the session.
+///
+/// ```
+/// use std::time::SystemTime;
+/// use es51986::{Output, binning::{Binner, ESeries, ToleranceClass}, reading::Reading};
+///
+/// let mut binner = Binner::new(ESeries::E12);
+/// // 0.985 kOhm
+/// let reading = Reading::new(SystemTime::UNIX_EPOCH, Output::parse(b"109853802").unwrap());
+/// let bin = binner.classify(&reading).unwrap();
+/// assert_eq!(bin.nominal.value(), 1000.0);
+/// assert_eq!(bin.tolerance_class, ToleranceClass::Percent2);
+/// assert_eq!(binner.count(&bin), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Binner {
+    series: ESeries,
+    counts: HashMap<(BaseUnit, Nominal, ToleranceClass), usize>,
+}
+
+impl Binner {
+    pub fn new(series: ESeries) -> Self {
+        Self { series, counts: HashMap::new() }
+    }
+
+    pub fn series(&self) -> ESeries {
+        self.series
+    }
+
+    /// Bin a reading without counting it.
+    pub fn bin(&self, reading: &Reading) -> Result<Bin, BinError> {
+        let base_unit = match reading.output.function {
+            Function::Ohm => BaseUnit::Ohm,
+            Function::Capacitor => BaseUnit::Farad,
+            f => return Err(BinError::UnsupportedFunction(f)),
+        };
+        let measured = reading.si_value().ok_or(BinError::NoValue)?;
+        if measured <= 0.0 {
+            return Err(BinError::NotPositive(measured));
+        }
+        let nominal = nearest(self.series, measured);
+        let deviation_percent = (measured - nominal.value()) / nominal.value() * 100.0;
+        Ok(Bin { base_unit, nominal, measured, deviation_percent, tolerance_class: ToleranceClass::of(deviation_percent) })
+    }
+
+    /// Bin a reading and count it.
+    pub fn classify(&mut self, reading: &Reading) -> Result<Bin, BinError> {
+        let bin = self.bin(reading)?;
+        *self.counts.entry((bin.base_unit, bin.nominal, bin.tolerance_class)).or_insert(0) += 1;
+        Ok(bin)
+    }
+
+    pub fn count(&self, bin: &Bin) -> usize {
+        self.counts.get(&(bin.base_unit, bin.nominal, bin.tolerance_class)).copied().unwrap_or(0)
+    }
+
+    /// Counts of the session ordered by unit, nominal value and tolerance class.
+    pub fn counts(&self) -> Vec<(BaseUnit, Nominal, ToleranceClass, usize)> {
+        let mut counts: Vec<_> = self.counts.iter().map(|(&(u, n, t), &c)| (u, n, t, c)).collect();
+        counts.sort_by(|a, b| {
+            (a.0 as u8).cmp(&(b.0 as u8)).then(a.1.value().total_cmp(&b.1.value())).then(a.2.cmp(&b.2))
+        });
+        counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn nearest_value() {
+        assert_eq!(nearest(ESeries::E12, 4650.0), Nominal { significand: 47, exponent: 2 });
+        assert_eq!(nearest(ESeries::E6, 9.5), Nominal { significand: 10, exponent: 0 });
+        assert_eq!(nearest(ESeries::E24, 0.0000001), Nominal { significand: 10, exponent: -8 });
+        assert_eq!(nearest(ESeries::E96, 1000.0), Nominal { significand: 100, exponent: 1 });
+        assert_eq!(nearest(ESeries::E96, 4990.0), Nominal { significand: 499, exponent: 1 });
+    }
+
+    #[test]
+    fn capacitor() {
+        let binner = Binner::new(ESeries::E6);
+        // 116.5 nF
+        let bin = binner.bin(&reading("211656802")).unwrap();
+        assert_eq!(bin.base_unit, BaseUnit::Farad);
+        assert_eq!(bin.nominal, Nominal { significand: 10, exponent: -8 });
+        assert!((bin.deviation_percent - 16.5).abs() < 1e-9);
+        assert_eq!(bin.tolerance_class, ToleranceClass::Percent20);
+    }
+
+    #[test]
+    fn errors() {
+        let binner = Binner::new(ESeries::E24);
+        assert_eq!(binner.bin(&reading("00000;<0:")), Err(BinError::UnsupportedFunction(Function::Voltage)));
+        assert_eq!(binner.bin(&reading("560003902")), Err(BinError::NoValue));
+        assert_eq!(binner.bin(&reading("000003802")), Err(BinError::NotPositive(0.0)));
+    }
+
+    #[test]
+    fn session_counts() {
+        let mut binner = Binner::new(ESeries::E12);
+        binner.classify(&reading("109853802")).unwrap();
+        binner.classify(&reading("109883802")).unwrap();
+        binner.classify(&reading("146803802")).unwrap();
+        assert_eq!(binner.total(), 3);
+        let counts = binner.counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0], (BaseUnit::Ohm, Nominal { significand: 10, exponent: 2 }, ToleranceClass::Percent2, 2));
+        assert_eq!(counts[1], (BaseUnit::Ohm, Nominal { significand: 47, exponent: 2 }, ToleranceClass::Percent1, 1));
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::{Duration, SystemTime};
+
+use crate::reading::{MeasurementKey, Reading};
+
+/// Aggregate of the readings that fell in one time bucket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// Start of the bucket, aligned to a multiple of the bucket width since the UNIX epoch.
+    pub start: SystemTime,
+    pub width: Duration,
+    pub key: MeasurementKey,
+    /// Number of readings including overflow frames.
+    pub count: usize,
+    pub overflow_count: usize,
+    /// `None` if no reading in the bucket had a value.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub last: Option<f64>,
+    /// True if the interval between readings exceeded the gap threshold inside this bucket.
+    pub has_gap: bool,
+    sum: f64,
+    value_count: usize,
+}
+
+impl Bucket {
+    fn new(start: SystemTime, width: Duration, key: MeasurementKey) -> Self {
+        Self {
+            start, width, key, count: 0, overflow_count: 0,
+            min: None, max: None, mean: None, last: None, has_gap: false, sum: 0.0, value_count: 0,
+        }
+    }
+
+    pub fn end(&self) -> SystemTime {
+        self.start + self.width
+    }
+
+    pub fn has_overflow(&self) -> bool {
+        self.overflow_count != 0
+    }
+
+    fn add(&mut self, reading: &Reading) {
+        self.count += 1;
+        if reading.output.status.is_overflow {
+            self.overflow_count += 1;
+        }
+        if let Some(v) = reading.value {
+            self.min = Some(self.min.map_or(v, |m| m.min(v)));
+            self.max = Some(self.max.map_or(v, |m| m.max(v)));
+            self.sum += v;
+            self.value_count += 1;
+            self.mean = Some(self.sum / self.value_count as f64);
+            self.last = Some(v);
+        }
+    }
+}
+
+/// Groups timestamped readings into fixed time buckets.
+///
+/// A bucket is closed when a reading belongs to a later time slot, or when the function, range or unit changes. In the latter case two buckets may share the same `start`.
+/// Time slots without any reading produce no bucket; the buckets around them are marked with `has_gap` instead.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use es51986::{Output, bucket::Bucketizer, reading::Reading};
+///
+/// let mut bucketizer = Bucketizer::new(Duration::from_secs(60));
+/// let output = Output::parse(b"01000;80:").unwrap();
+/// assert_eq!(bucketizer.push(&Reading::new(SystemTime::UNIX_EPOCH, output.clone())), None);
+/// let closed = bucketizer.push(&Reading::new(SystemTime::UNIX_EPOCH + Duration::from_secs(61), output)).unwrap();
+/// assert_eq!(closed.count, 1);
+/// assert_eq!(closed.mean, Some(1.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Bucketizer {
+    width: Duration,
+    gap_threshold: Duration,
+    current: Option<Bucket>,
+    last_timestamp: Option<SystemTime>,
+}
+
+impl Bucketizer {
+    /// Default gap threshold. The meter sends about 2 frames per second.
+    pub const DEFAULT_GAP_THRESHOLD: Duration = Duration::from_secs(2);
+
+    /// # Panics
+    ///
+    /// Panics if `width` is zero.
+    pub fn new(width: Duration) -> Self {
+        assert!(!width.is_zero(), "width should not be zero");
+        Self { width, gap_threshold: Self::DEFAULT_GAP_THRESHOLD, current: None, last_timestamp: None }
+    }
+
+    /// Interval between readings regarded as missing data.
+    pub fn with_gap_threshold(mut self, gap_threshold: Duration) -> Self {
+        self.gap_threshold = gap_threshold;
+        self
+    }
+
+    fn slot_start(&self, timestamp: SystemTime) -> SystemTime {
+        let since_epoch = timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let width = self.width.as_nanos();
+        let start = since_epoch / width * width;
+        SystemTime::UNIX_EPOCH + Duration::new((start / 1_000_000_000) as u64, (start % 1_000_000_000) as u32)
+    }
+
+    /// Add a reading. Returns the bucket closed by this reading, if any.
+    pub fn push(&mut self, reading: &Reading) -> Option<Bucket> {
+        let key = reading.key();
+        let gap = self.last_timestamp.is_some_and(|last| {
+            reading.timestamp.duration_since(last).is_ok_and(|d| d > self.gap_threshold)
+        });
+
+        let closed = match self.current.take() {
+            Some(mut bucket) if bucket.key == key && reading.timestamp < bucket.end() => {
+                bucket.has_gap |= gap;
+                self.current = Some(bucket);
+                None
+            }
+            Some(mut bucket) => {
+                if gap && self.last_timestamp.is_some_and(|last| last + self.gap_threshold < bucket.end()) {
+                    bucket.has_gap = true;
+                }
+                Some(bucket)
+            }
+            None => None,
+        };
+
+        if self.current.is_none() {
+            let start = self.slot_start(reading.timestamp);
+            let mut bucket = Bucket::new(start, self.width, key);
+            bucket.has_gap = gap && start + self.gap_threshold < reading.timestamp;
+            self.current = Some(bucket);
+        }
+        if let Some(current) = self.current.as_mut() {
+            current.add(reading);
+        }
+        self.last_timestamp = Some(reading.timestamp);
+        closed
+    }
+
+    /// Close the bucket in progress.
+    pub fn finish(&mut self) -> Option<Bucket> {
+        self.last_timestamp = None;
+        self.current.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(secs: f64, frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_secs_f64(secs), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn aggregate() {
+        let mut b = Bucketizer::new(Duration::from_secs(1));
+        assert_eq!(b.push(&reading(10.0, "01000;80:")), None);
+        assert_eq!(b.push(&reading(10.5, "03000;80:")), None);
+        let bucket = b.push(&reading(11.0, "02000;80:")).unwrap();
+        assert_eq!(bucket.start, SystemTime::UNIX_EPOCH + Duration::from_secs(10));
+        assert_eq!(bucket.count, 2);
+        assert_eq!(bucket.min, Some(1.0));
+        assert_eq!(bucket.max, Some(3.0));
+        assert_eq!(bucket.mean, Some(2.0));
+        assert_eq!(bucket.last, Some(3.0));
+        assert!(!bucket.has_gap);
+        assert!(!bucket.has_overflow());
+
+        let bucket = b.finish().unwrap();
+        assert_eq!(bucket.count, 1);
+        assert_eq!(bucket.last, Some(2.0));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut b = Bucketizer::new(Duration::from_secs(60));
+        b.push(&reading(0.0, "01000;80:"));
+        b.push(&reading(0.5, "01000;90:"));
+        let bucket = b.finish().unwrap();
+        assert_eq!(bucket.count, 2);
+        assert_eq!(bucket.overflow_count, 1);
+        assert_eq!(bucket.mean, Some(1.0));
+    }
+
+    #[test]
+    fn gap() {
+        let mut b = Bucketizer::new(Duration::from_secs(60));
+        b.push(&reading(0.0, "01000;80:"));
+        b.push(&reading(0.5, "01000;80:"));
+        b.push(&reading(30.0, "01000;80:"));
+        let bucket = b.push(&reading(60.0, "01000;80:")).unwrap();
+        assert!(bucket.has_gap);
+        assert!(!b.finish().unwrap().has_gap);
+
+        // Readings stop in the middle of the first bucket and restart in the middle of the third.
+        let mut b = Bucketizer::new(Duration::from_secs(60));
+        b.push(&reading(0.0, "01000;80:"));
+        b.push(&reading(1.0, "01000;80:"));
+        let first = b.push(&reading(150.0, "01000;80:")).unwrap();
+        assert!(first.has_gap);
+        let third = b.finish().unwrap();
+        assert_eq!(third.start, SystemTime::UNIX_EPOCH + Duration::from_secs(120));
+        assert!(third.has_gap);
+    }
+
+    #[test]
+    fn split_on_range_change() {
+        let mut b = Bucketizer::new(Duration::from_secs(60));
+        b.push(&reading(0.0, "01000;80:"));
+        let bucket = b.push(&reading(0.5, "11000;80:")).unwrap();
+        assert_eq!(bucket.count, 1);
+        let bucket = b.finish().unwrap();
+        assert_eq!(bucket.start, SystemTime::UNIX_EPOCH);
+        assert_eq!(bucket.mean, Some(10.0));
+    }
+}
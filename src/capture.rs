@@ -0,0 +1,351 @@
+//! Raw capture file format.
+//!
+//! A capture keeps the bytes exactly as they arrived from the meter, before decoding, together with their arrival time.
+//! This makes problems of `Parser` reproducible.
+//!
+//! Layout (all integers are little endian):
+//!
+//! ```text
+//! magic          8 bytes  "ES51986C"
+//! version        u16      FORMAT_VERSION
+//! header length  u32
+//! header         JSON     CaptureHeader
+//! chunk*         delta    LEB128 microseconds since the previous chunk (the start time for the first chunk)
+//!                length   LEB128 byte count
+//!                data     bytes
+//! ```
+
+use std::{fmt, io::{self, Read, Write}, time::{Duration, SystemTime}};
+
+use serde::{Deserialize, Serialize};
+
+pub const MAGIC: &[u8; 8] = b"ES51986C";
+pub const FORMAT_VERSION: u16 = 1;
+/// Longest header JSON accepted by `CaptureReader`.
+pub const MAX_HEADER_LEN: u64 = 64 * 1024;
+/// Longest chunk accepted by `CaptureReader`. Chunks are what one read returned, normally a few bytes.
+pub const MAX_CHUNK_LEN: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// Serial line settings the capture was taken with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineSettings {
+    pub baud_rate: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8,
+}
+
+impl Default for LineSettings {
+    /// ES51986 sends 2400 baud, 7 data bits, odd parity, 1 stop bit.
+    fn default() -> Self {
+        Self { baud_rate: 2400, data_bits: 7, parity: Parity::Odd, stop_bits: 1 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    /// Version of this crate that wrote the capture.
+    pub crate_version: String,
+    /// Where the bytes came from, e.g. "/dev/ttyUSB0".
+    pub source: String,
+    pub line: LineSettings,
+    /// Start of the capture in microseconds since the UNIX epoch.
+    pub start_unix_micros: u64,
+}
+
+impl CaptureHeader {
+    pub fn new(source: &str, line: LineSettings, start: SystemTime) -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+            source: source.to_owned(),
+            line,
+            start_unix_micros: start.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_micros() as u64,
+        }
+    }
+
+    pub fn start(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_micros(self.start_unix_micros)
+    }
+}
+
+/// Capture errors
+#[derive(Debug)]
+pub enum CaptureError {
+    /// I/O error of the underlying reader.
+    Io(io::Error),
+    /// The file does not start with `MAGIC`.
+    BadMagic,
+    /// The file was written with a newer format.
+    UnsupportedVersion(u16),
+    /// The header is not valid JSON of `CaptureHeader`.
+    InvalidHeader(String),
+    /// The file ends in the middle of a chunk.
+    Truncated,
+    /// A header or chunk length above `MAX_HEADER_LEN` or `MAX_CHUNK_LEN`, usually a corrupt file.
+    TooLong(u64),
+    /// A chunk time beyond what `SystemTime` can hold, usually a corrupt file.
+    TimeOverflow,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::BadMagic => write!(f, "not a capture file"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported capture format version {}", v),
+            Self::InvalidHeader(msg) => write!(f, "invalid capture header: {}", msg),
+            Self::Truncated => write!(f, "capture file is truncated"),
+            Self::TooLong(len) => write!(f, "capture file is corrupt: length {} is too long", len),
+            Self::TimeOverflow => write!(f, "capture file is corrupt: chunk time is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<io::Error> for CaptureError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Bytes that arrived at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub timestamp: SystemTime,
+    pub data: Vec<u8>,
+}
+
+/// `read_exact` that fails with `eof` when the reader ends early.
+fn read_exact_or<R: Read>(reader: &mut R, buf: &mut [u8], eof: CaptureError) -> Result<(), CaptureError> {
+    match reader.read_exact(buf) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(eof),
+        result => Ok(result?),
+    }
+}
+
+/// Read exactly `len` bytes, allocating no more than what the reader provides.
+fn read_bytes<R: Read>(reader: &mut R, len: u64, max: u64) -> Result<Vec<u8>, CaptureError> {
+    if max < len {
+        return Err(CaptureError::TooLong(len));
+    }
+    let mut data = vec![];
+    reader.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(CaptureError::Truncated);
+    }
+    Ok(data)
+}
+
+fn write_varint<W: Write>(w: &mut W, mut v: u64) -> io::Result<()> {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            return w.write_all(&[b]);
+        }
+        w.write_all(&[b | 0x80])?;
+    }
+}
+
+/// Returns `Ok(None)` at the end of input before the first byte.
+fn read_varint<R: Read>(r: &mut R) -> Result<Option<u64>, CaptureError> {
+    let mut v: u64 = 0;
+    for i in 0..10 {
+        let mut b = [0u8];
+        if r.read(&mut b)? == 0 {
+            return if i == 0 { Ok(None) } else { Err(CaptureError::Truncated) };
+        }
+        v |= ((b[0] & 0x7f) as u64) << (7 * i);
+        if b[0] & 0x80 == 0 {
+            return Ok(Some(v));
+        }
+    }
+    Err(CaptureError::Truncated)
+}
+
+/// Writes a capture file.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use es51986::capture::{CaptureHeader, CaptureReader, CaptureWriter, LineSettings};
+///
+/// let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+/// let mut writer = CaptureWriter::new(vec![], CaptureHeader::new("/dev/ttyUSB0", LineSettings::default(), start)).unwrap();
+/// writer.write_chunk(start + Duration::from_millis(10), b"00000;<0:\r\n").unwrap();
+/// let file = writer.into_inner();
+///
+/// let mut reader = CaptureReader::new(&file[..]).unwrap();
+/// assert_eq!(reader.header().source, "/dev/ttyUSB0");
+/// let chunk = reader.next().unwrap().unwrap();
+/// assert_eq!(chunk.timestamp, start + Duration::from_millis(10));
+/// assert_eq!(chunk.data, b"00000;<0:\r\n");
+/// ```
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    last: SystemTime,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W, header: CaptureHeader) -> io::Result<Self> {
+        let json = serde_json::to_vec(&header).map_err(io::Error::other)?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(&json)?;
+        Ok(Self { writer, last: header.start() })
+    }
+
+    /// Record bytes received at `timestamp`. A timestamp earlier than the previous one is recorded as no delay.
+    pub fn write_chunk(&mut self, timestamp: SystemTime, data: &[u8]) -> io::Result<()> {
+        let delta = timestamp.duration_since(self.last).unwrap_or_default();
+        let micros = u64::try_from(delta.as_micros()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "chunk time is too far from the previous one"))?;
+        // Advance by the recorded amount so that rounding errors do not accumulate.
+        self.last = self.last.checked_add(Duration::from_micros(micros)).unwrap_or(timestamp);
+        write_varint(&mut self.writer, micros)?;
+        write_varint(&mut self.writer, data.len() as u64)?;
+        self.writer.write_all(data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads a capture file. Iterates over the chunks.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    header: CaptureHeader,
+    last: SystemTime,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, CaptureError> {
+        let mut magic = [0u8; 8];
+        read_exact_or(&mut reader, &mut magic, CaptureError::BadMagic)?;
+        if &magic != MAGIC {
+            return Err(CaptureError::BadMagic);
+        }
+        let mut buf2 = [0u8; 2];
+        read_exact_or(&mut reader, &mut buf2, CaptureError::Truncated)?;
+        let version = u16::from_le_bytes(buf2);
+        if FORMAT_VERSION < version {
+            return Err(CaptureError::UnsupportedVersion(version));
+        }
+        let mut buf4 = [0u8; 4];
+        read_exact_or(&mut reader, &mut buf4, CaptureError::Truncated)?;
+        let json = read_bytes(&mut reader, u32::from_le_bytes(buf4) as u64, MAX_HEADER_LEN)?;
+        let header: CaptureHeader = serde_json::from_slice(&json).map_err(|e| CaptureError::InvalidHeader(e.to_string()))?;
+        let last = header.start();
+        Ok(Self { reader, header, last })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Chunk>, CaptureError> {
+        let Some(micros) = read_varint(&mut self.reader)? else {
+            return Ok(None);
+        };
+        let len = read_varint(&mut self.reader)?.ok_or(CaptureError::Truncated)?;
+        let data = read_bytes(&mut self.reader, len, MAX_CHUNK_LEN)?;
+        self.last = self.last.checked_add(Duration::from_micros(micros)).ok_or(CaptureError::TimeOverflow)?;
+        Ok(Some(Chunk { timestamp: self.last, data }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<Chunk, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_chunk().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = CaptureHeader::new("stdin", LineSettings::default(), start());
+        let mut w = CaptureWriter::new(vec![], header.clone()).unwrap();
+        w.write_chunk(start() + Duration::from_micros(300), b"0000").unwrap();
+        w.write_chunk(start() + Duration::from_secs(1000), b"0;<0:\r\n").unwrap();
+        w.write_chunk(start(), b"").unwrap();
+        let file = w.into_inner();
+
+        let mut r = CaptureReader::new(&file[..]).unwrap();
+        assert_eq!(r.header(), &header);
+        assert_eq!(r.header().crate_version, env!("CARGO_PKG_VERSION"));
+        let chunks: Vec<Chunk> = r.by_ref().map(|c| c.unwrap()).collect();
+        assert_eq!(chunks, vec![
+            Chunk { timestamp: start() + Duration::from_micros(300), data: b"0000".to_vec() },
+            Chunk { timestamp: start() + Duration::from_secs(1000), data: b"0;<0:\r\n".to_vec() },
+            Chunk { timestamp: start() + Duration::from_secs(1000), data: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(CaptureReader::new(&b"not a capture"[..]), Err(CaptureError::BadMagic)));
+
+        let mut file = CaptureWriter::new(vec![], CaptureHeader::new("x", LineSettings::default(), start())).unwrap().into_inner();
+        file[8] = 99;
+        assert!(matches!(CaptureReader::new(&file[..]), Err(CaptureError::UnsupportedVersion(99))));
+
+        let mut w = CaptureWriter::new(vec![], CaptureHeader::new("x", LineSettings::default(), start())).unwrap();
+        w.write_chunk(start(), b"0123456789").unwrap();
+        let mut file = w.into_inner();
+        file.truncate(file.len() - 1);
+        let mut r = CaptureReader::new(&file[..]).unwrap();
+        assert!(matches!(r.next(), Some(Err(CaptureError::Truncated))));
+
+        // Delta 0, then a length varint of u64::MAX / 2.
+        let mut file = CaptureWriter::new(vec![], CaptureHeader::new("x", LineSettings::default(), start())).unwrap().into_inner();
+        file.extend([0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+        let mut r = CaptureReader::new(&file[..]).unwrap();
+        assert!(matches!(r.next(), Some(Err(CaptureError::TooLong(0x7fff_ffff_ffff_ffff)))));
+
+        let mut file = MAGIC.to_vec();
+        file.extend(FORMAT_VERSION.to_le_bytes());
+        file.extend(u32::MAX.to_le_bytes());
+        assert!(matches!(CaptureReader::new(&file[..]), Err(CaptureError::TooLong(0xffff_ffff))));
+
+        // Deltas of u64::MAX microseconds.
+        let mut file = CaptureWriter::new(vec![], CaptureHeader::new("x", LineSettings::default(), start())).unwrap().into_inner();
+        for _ in 0..1_000_000 {
+            file.extend([0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0]);
+        }
+        let mut r = CaptureReader::new(&file[..]).unwrap();
+        assert!(r.any(|c| matches!(c, Err(CaptureError::TimeOverflow))));
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::PermissionDenied.into())
+            }
+        }
+        assert!(matches!(CaptureReader::new(Failing), Err(CaptureError::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied));
+    }
+}
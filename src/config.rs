@@ -0,0 +1,495 @@
+//! TOML configuration of meters, processing profiles and outputs.
+//!
+//! ```toml
+//! [[meter]]
+//! id = "bench-1"
+//! device = "/dev/ttyUSB0"      # or file = "raw.txt", or capture = "run.cap"
+//! profile = "charger"
+//!
+//! [profile.charger]
+//! filters = [{ type = "median", window = 3 }, { type = "ema", alpha = 0.2 }]
+//! rel = "first"                # or a reference in the base unit such as 0.5
+//! limits = ["DCV > 14.4", "battery_low"]
+//!
+//! [[output]]
+//! type = "csv"
+//! path = "bench-1.csv"
+//! meters = ["bench-1"]
+//!
+//! [[output]]
+//! type = "mqtt"
+//! host = "localhost"
+//!
+//! [[output]]
+//! type = "prometheus"
+//! listen = "0.0.0.0:9186"
+//! ```
+//!
+//! `Config::parse()` checks the whole file and reports every problem it finds, not only the first one.
+//! `Config::load()` resolves relative paths against the directory of the configuration file, not the working directory.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    capture::LineSettings,
+    csv::Column,
+    filter::{Ema, FilterChain, Median, MovingAverage, Relative},
+    replay::Speed,
+    watch::{Condition, Watch},
+};
+
+/// Configuration errors
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file cannot be read.
+    Io(io::Error),
+    /// The file is not valid TOML or has unknown keys or wrong types. The message tells the line and column.
+    Syntax(String),
+    /// The settings are inconsistent, one message per problem.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Syntax(msg) => write!(f, "{}", msg.trim_end()),
+            Self::Invalid(problems) => write!(f, "{}", problems.join("\n")),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "meter")]
+    pub meters: Vec<MeterConfig>,
+    #[serde(default, rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default, rename = "output")]
+    pub outputs: Vec<OutputConfig>,
+}
+
+/// One meter. Exactly one of `device`, `file` and `capture` is set.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeterConfig {
+    pub id: String,
+    /// Serial device such as "/dev/ttyUSB0".
+    pub device: Option<String>,
+    /// Raw meter output.
+    pub file: Option<PathBuf>,
+    /// Capture written by `es51986 record`, replayed at `speed`.
+    pub capture: Option<PathBuf>,
+    /// Baud rate of `device`. Default is 2400.
+    pub baud: Option<u32>,
+    /// Replay speed of `capture`: a factor or "max". Default is real time.
+    pub speed: Option<SpeedConfig>,
+    /// Name of a `[profile.<name>]`.
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum SpeedConfig {
+    Factor(f64),
+    Name(String),
+}
+
+/// Processing of the readings of a meter.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Applied in order.
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+    /// Reference subtracted from the values, after the filters.
+    pub rel: Option<RelConfig>,
+    /// Conditions (see `watch::Condition`) reported when they fire and clear.
+    #[serde(default)]
+    pub limits: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FilterConfig {
+    Ema { alpha: f64 },
+    MovingAverage { window: usize },
+    Median { window: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum RelConfig {
+    /// Reference in the base unit.
+    Value(f64),
+    /// "first" to take the first reading as the reference.
+    Mode(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OutputConfig {
+    /// CSV appended to `path`, or standard output for "-". The header is written to new files only.
+    Csv {
+        path: PathBuf,
+        columns: Option<Vec<Column>>,
+        meters: Option<Vec<String>>,
+    },
+    /// `jsonl::Record` lines, with the meter id, appended to `path` or standard output for "-".
+    Jsonl {
+        path: PathBuf,
+        meters: Option<Vec<String>>,
+    },
+    /// `mqtt::MqttPublisher` for each meter.
+    Mqtt {
+        host: String,
+        port: Option<u16>,
+        prefix: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+        /// Home Assistant discovery prefix such as "homeassistant".
+        discovery: Option<String>,
+        #[serde(default)]
+        retain: bool,
+        meters: Option<Vec<String>>,
+    },
+    /// Prometheus metrics served at `listen`. Needs the `prometheus` feature at run time.
+    Prometheus {
+        listen: String,
+        meters: Option<Vec<String>>,
+    },
+}
+
+impl Config {
+    /// Read and check `path`. Relative `file`, `capture` and output paths are taken relative to the directory of `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut config = Self::parse(&fs::read_to_string(path)?)?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    /// Prefix relative paths with `dir`. "-" (standard input or output) is left as is.
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() && path.as_os_str() != "-" {
+                *path = dir.join(&*path);
+            }
+        };
+        for meter in &mut self.meters {
+            meter.file.iter_mut().chain(meter.capture.iter_mut()).for_each(resolve);
+        }
+        for output in &mut self.outputs {
+            if let OutputConfig::Csv { path, .. } | OutputConfig::Jsonl { path, .. } = output {
+                resolve(path);
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(text).map_err(|e| ConfigError::Syntax(e.to_string()))?;
+        let problems = config.problems();
+        if problems.is_empty() { Ok(config) } else { Err(ConfigError::Invalid(problems)) }
+    }
+
+    pub fn profile(&self, meter: &MeterConfig) -> Profile {
+        meter.profile.as_ref().and_then(|name| self.profiles.get(name)).cloned().unwrap_or_default()
+    }
+
+    /// Ids of the meters that `output` receives.
+    pub fn meters_of<'a>(&'a self, output: &'a OutputConfig) -> Vec<&'a str> {
+        match output.meters() {
+            Some(ids) => ids.iter().map(|s| s.as_str()).collect(),
+            None => self.meters.iter().map(|m| m.id.as_str()).collect(),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.meters.is_empty() {
+            problems.push("no [[meter]] is configured".to_owned());
+        }
+        let mut ids = HashSet::new();
+        for (i, meter) in self.meters.iter().enumerate() {
+            let name = if meter.id.is_empty() { format!("meter #{}", i + 1) } else { format!("meter '{}'", meter.id) };
+            if meter.id.is_empty() {
+                problems.push(format!("{}: id is empty", name));
+            } else if !ids.insert(meter.id.as_str()) {
+                problems.push(format!("{}: id is used by another meter", name));
+            }
+            let sources = [meter.device.is_some(), meter.file.is_some(), meter.capture.is_some()].iter().filter(|s| **s).count();
+            if sources != 1 {
+                problems.push(format!("{}: set exactly one of device, file and capture", name));
+            }
+            if meter.baud.is_some() && meter.device.is_none() {
+                problems.push(format!("{}: baud is only for device", name));
+            }
+            if meter.baud == Some(0) {
+                problems.push(format!("{}: baud must be positive", name));
+            }
+            match &meter.speed {
+                Some(_) if meter.capture.is_none() => problems.push(format!("{}: speed is only for capture", name)),
+                Some(speed) => if let Err(e) = speed.speed() {
+                    problems.push(format!("{}: {}", name, e));
+                },
+                None => {}
+            }
+            if let Some(profile) = &meter.profile {
+                if !self.profiles.contains_key(profile) {
+                    problems.push(format!("{}: profile '{}' is not defined", name, profile));
+                }
+            }
+        }
+        for (name, profile) in &self.profiles {
+            for filter in &profile.filters {
+                let problem = match filter {
+                    FilterConfig::Ema { alpha } if !(0.0 < *alpha && *alpha <= 1.0) => Some(format!("ema alpha must be in (0, 1] but {}", alpha)),
+                    FilterConfig::MovingAverage { window: 0 } | FilterConfig::Median { window: 0 } => Some("window must be positive".to_owned()),
+                    _ => None,
+                };
+                if let Some(problem) = problem {
+                    problems.push(format!("profile '{}': {}", name, problem));
+                }
+            }
+            if let Some(RelConfig::Mode(mode)) = &profile.rel {
+                if mode != "first" {
+                    problems.push(format!("profile '{}': rel must be a number or \"first\" but \"{}\"", name, mode));
+                }
+            }
+            for limit in &profile.limits {
+                if let Err(e) = limit.parse::<Condition>() {
+                    problems.push(format!("profile '{}': {}", name, e));
+                }
+            }
+        }
+        for (i, output) in self.outputs.iter().enumerate() {
+            let name = format!("output #{} ({})", i + 1, output.kind());
+            for id in output.meters().into_iter().flatten() {
+                if !ids.contains(id.as_str()) {
+                    problems.push(format!("{}: meter '{}' is not defined", name, id));
+                }
+            }
+            match output {
+                OutputConfig::Csv { .. } if 1 < self.meters_of(output).len() => {
+                    problems.push(format!("{}: CSV has no meter column, so set meters to one meter", name));
+                }
+                OutputConfig::Mqtt { host, port, username, password, prefix, discovery, .. } => {
+                    if host.is_empty() {
+                        problems.push(format!("{}: host is empty", name));
+                    }
+                    for id in self.meters_of(output).into_iter().filter(|id| id.contains(['/', '+', '#'])) {
+                        problems.push(format!("{}: meter id '{}' cannot be an MQTT topic level (no '/', '+' or '#')", name, id));
+                    }
+                    for prefix in [prefix, discovery].into_iter().flatten().filter(|p| p.contains(['+', '#'])) {
+                        problems.push(format!("{}: topic prefix '{}' must not contain '+' or '#'", name, prefix));
+                    }
+                    if *port == Some(0) {
+                        problems.push(format!("{}: port must be positive", name));
+                    }
+                    if username.is_some() != password.is_some() {
+                        problems.push(format!("{}: set both username and password", name));
+                    }
+                }
+                OutputConfig::Prometheus { listen, .. } if listen.parse::<SocketAddr>().is_err() => {
+                    problems.push(format!("{}: listen must be an address such as \"0.0.0.0:9186\" but \"{}\"", name, listen));
+                }
+                _ => {}
+            }
+        }
+        problems
+    }
+}
+
+impl MeterConfig {
+    pub fn line_settings(&self) -> LineSettings {
+        let default = LineSettings::default();
+        LineSettings { baud_rate: self.baud.unwrap_or(default.baud_rate), ..default }
+    }
+}
+
+impl SpeedConfig {
+    pub fn speed(&self) -> Result<Speed, String> {
+        match self {
+            Self::Factor(f) if *f == 1.0 => Ok(Speed::RealTime),
+            Self::Factor(f) if 0.0 < *f && f.is_finite() => Ok(Speed::Scaled(*f)),
+            Self::Name(name) if name == "max" => Ok(Speed::AsFastAsPossible),
+            Self::Factor(f) => Err(format!("speed must be positive but {}", f)),
+            Self::Name(name) => Err(format!("speed must be a factor or \"max\" but \"{}\"", name)),
+        }
+    }
+}
+
+impl Profile {
+    /// Filters followed by REL.
+    pub fn filter_chain(&self) -> FilterChain {
+        let mut chain = FilterChain::new();
+        for filter in &self.filters {
+            match filter {
+                FilterConfig::Ema { alpha } => chain.push(Box::new(Ema::new(*alpha))),
+                FilterConfig::MovingAverage { window } => chain.push(Box::new(MovingAverage::new(*window))),
+                FilterConfig::Median { window } => chain.push(Box::new(Median::new(*window))),
+            }
+        }
+        match &self.rel {
+            Some(RelConfig::Value(reference)) => chain.push(Box::new(Relative::new(*reference))),
+            Some(RelConfig::Mode(_)) => chain.push(Box::new(Relative::first())),
+            None => {}
+        }
+        chain
+    }
+
+    /// Watches of `limits`. Limits that do not parse are skipped, which `Config::parse()` does not let through.
+    pub fn watches(&self) -> Vec<Watch> {
+        self.limits.iter().filter_map(|l| l.parse().ok()).map(Watch::new).collect()
+    }
+}
+
+impl OutputConfig {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Csv { .. } => "csv",
+            Self::Jsonl { .. } => "jsonl",
+            Self::Mqtt { .. } => "mqtt",
+            Self::Prometheus { .. } => "prometheus",
+        }
+    }
+
+    pub fn meters(&self) -> Option<&Vec<String>> {
+        match self {
+            Self::Csv { meters, .. } | Self::Jsonl { meters, .. } | Self::Mqtt { meters, .. } | Self::Prometheus { meters, .. } => meters.as_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BENCH: &str = r#"
+[[meter]]
+id = "bench-1"
+device = "/dev/ttyUSB0"
+profile = "charger"
+
+[[meter]]
+id = "bench-2"
+capture = "run.cap"
+speed = "max"
+
+[profile.charger]
+filters = [{ type = "median", window = 3 }, { type = "ema", alpha = 0.2 }]
+rel = "first"
+limits = ["DCV > 14.4", "battery_low"]
+
+[[output]]
+type = "csv"
+path = "bench-1.csv"
+meters = ["bench-1"]
+
+[[output]]
+type = "jsonl"
+path = "-"
+
+[[output]]
+type = "prometheus"
+listen = "127.0.0.1:9186"
+"#;
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(BENCH).unwrap();
+        assert_eq!(config.meters.len(), 2);
+        assert_eq!(config.meters[0].line_settings().baud_rate, 2400);
+        assert_eq!(config.meters[1].speed.as_ref().unwrap().speed(), Ok(Speed::AsFastAsPossible));
+        let profile = config.profile(&config.meters[0]);
+        assert_eq!(profile.filters, vec![FilterConfig::Median { window: 3 }, FilterConfig::Ema { alpha: 0.2 }]);
+        assert_eq!(profile.watches().len(), 2);
+        assert!(!profile.filter_chain().is_empty());
+        assert_eq!(config.meters_of(&config.outputs[1]), vec!["bench-1", "bench-2"]);
+    }
+
+    #[test]
+    fn paths_relative_to_config() {
+        let mut config = Config::parse(BENCH).unwrap();
+        config.resolve_paths(Path::new("bench"));
+        assert_eq!(config.meters[1].capture.as_deref(), Some(Path::new("bench/run.cap")));
+        let paths: Vec<&Path> = config.outputs.iter().filter_map(|o| match o {
+            OutputConfig::Csv { path, .. } | OutputConfig::Jsonl { path, .. } => Some(path.as_path()),
+            _ => None,
+        }).collect();
+        assert_eq!(paths, [Path::new("bench/bench-1.csv"), Path::new("-")]);
+    }
+
+    #[test]
+    fn syntax_error_has_location() {
+        let e = Config::parse("[[meter]]\nid = \"a\"\ndevise = \"/dev/ttyUSB0\"\n").unwrap_err();
+        let message = e.to_string();
+        assert!(message.contains("line 3"), "{}", message);
+        assert!(message.contains("unknown field `devise`"), "{}", message);
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let text = r#"
+[[meter]]
+id = "a"
+device = "/dev/ttyUSB0"
+file = "raw.txt"
+profile = "missing"
+
+[[meter]]
+id = "b/1"
+file = "raw.txt"
+
+[profile.p]
+filters = [{ type = "ema", alpha = 2.0 }]
+rel = "last"
+limits = ["DCX > 1"]
+
+[[output]]
+type = "csv"
+path = "all.csv"
+
+[[output]]
+type = "prometheus"
+listen = "localhost"
+meters = ["c"]
+
+[[output]]
+type = "mqtt"
+host = "localhost"
+prefix = "lab/#"
+"#;
+        let Err(ConfigError::Invalid(problems)) = Config::parse(text) else { panic!() };
+        assert_eq!(problems, vec![
+            "meter 'a': set exactly one of device, file and capture",
+            "meter 'a': profile 'missing' is not defined",
+            "profile 'p': ema alpha must be in (0, 1] but 2",
+            "profile 'p': rel must be a number or \"first\" but \"last\"",
+            "profile 'p': unknown quantity 'DCX' in 'DCX > 1' (use value or an optional DC/AC followed by V, A, ohm, Hz or F)",
+            "output #1 (csv): CSV has no meter column, so set meters to one meter",
+            "output #2 (prometheus): meter 'c' is not defined",
+            "output #2 (prometheus): listen must be an address such as \"0.0.0.0:9186\" but \"localhost\"",
+            "output #3 (mqtt): meter id 'b/1' cannot be an MQTT topic level (no '/', '+' or '#')",
+            "output #3 (mqtt): topic prefix 'lab/#' must not contain '+' or '#'",
+        ]);
+    }
+}
@@ -0,0 +1,324 @@
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{jsonl::{Flags, Record, SCHEMA_VERSION}, reading::Reading, sink::Sink, timestamp};
+
+/// CSV columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Column {
+    /// RFC 3339 in UTC.
+    Timestamp,
+    /// `Function::name()`.
+    Function,
+    /// "AC", "DC" or empty.
+    Coupling,
+    /// Range index 0-6.
+    Range,
+    /// Signed value in `Unit`. Empty if the frame has no reading (overflow or unsupported function).
+    Value,
+    /// Unit symbol such as "mV". Empty if the frame has no unit.
+    Unit,
+    /// 1 if the frame is overflow (OL), 0 otherwise.
+    Overflow,
+    /// 1 if the battery is low, 0 otherwise.
+    Battery,
+}
+
+impl Column {
+    pub const ALL: [Column; 8] = [
+        Column::Timestamp, Column::Function, Column::Coupling, Column::Range,
+        Column::Value, Column::Unit, Column::Overflow, Column::Battery,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Timestamp => "timestamp",
+            Self::Function => "function",
+            Self::Coupling => "coupling",
+            Self::Range => "range",
+            Self::Value => "value",
+            Self::Unit => "unit",
+            Self::Overflow => "overflow",
+            Self::Battery => "battery",
+        }
+    }
+}
+
+/// Field delimiter and decimal separator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dialect {
+    pub delimiter: char,
+    pub decimal_separator: char,
+}
+
+impl Dialect {
+    /// `1.234,mV`
+    pub const STANDARD: Dialect = Dialect { delimiter: ',', decimal_separator: '.' };
+    /// `1,234;mV` as expected by spreadsheets in many European locales.
+    pub const DECIMAL_COMMA: Dialect = Dialect { delimiter: ';', decimal_separator: ',' };
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// Writes readings as CSV.
+///
+/// The ES51986 frame has no HOLD flag (a held reading is sent as an ordinary frame), so there is no column for it.
+///
+/// ```
+/// use std::time::SystemTime;
+/// use es51986::{Output, csv::{Column, CsvWriter, Dialect}, reading::Reading, sink::Sink};
+///
+/// let mut writer = CsvWriter::new(vec![])
+///     .with_columns(&[Column::Value, Column::Unit, Column::Coupling, Column::Overflow])
+///     .with_dialect(Dialect::DECIMAL_COMMA);
+/// writer.write(&Reading::new(SystemTime::UNIX_EPOCH, Output::parse(b"00002?<0:").unwrap())).unwrap();
+/// assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), "value;unit;coupling;overflow\r\n-0,02;mA;DC;0\r\n");
+/// ```
+pub struct CsvWriter<W: Write> {
+    writer: W,
+    columns: Vec<Column>,
+    dialect: Dialect,
+    header: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, columns: Column::ALL.to_vec(), dialect: Dialect::default(), header: true }
+    }
+
+    pub fn with_columns(mut self, columns: &[Column]) -> Self {
+        self.columns = columns.to_vec();
+        self
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Set false not to write the header line, e.g. when appending to an existing file.
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    fn field(&self, column: Column, reading: &Reading) -> String {
+        let output = &reading.output;
+        let flag = |b: bool| if b { "1" } else { "0" }.to_owned();
+        match column {
+            Column::Timestamp => timestamp::rfc3339(reading.timestamp),
+            Column::Function => output.function.name().to_owned(),
+            Column::Coupling => output.option2.coupling().to_owned(),
+            Column::Range => output.range.index().to_string(),
+            Column::Value => reading.format_value()
+                .map(|v| v.replace('.', &self.dialect.decimal_separator.to_string()))
+                .unwrap_or_default(),
+            Column::Unit => reading.value_unit().map(|u| u.symbol()).unwrap_or_default(),
+            Column::Overflow => flag(output.status.is_overflow),
+            Column::Battery => flag(output.status.is_battery_depleted),
+        }
+    }
+
+    fn write_record<I: Iterator<Item = String>>(&mut self, fields: I) -> io::Result<()> {
+        let delimiter = self.dialect.delimiter;
+        let line: Vec<String> = fields.map(|f| {
+            if f.contains([delimiter, '"', '\r', '\n']) {
+                format!("\"{}\"", f.replace('"', "\"\""))
+            } else {
+                f
+            }
+        }).collect();
+        write!(self.writer, "{}\r\n", line.join(&delimiter.to_string()))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for CsvWriter<W> {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        if self.header {
+            self.header = false;
+            let names: Vec<String> = self.columns.iter().map(|c| c.name().to_owned()).collect();
+            self.write_record(names.into_iter())?;
+        }
+        let fields: Vec<String> = self.columns.iter().map(|c| self.field(*c, reading)).collect();
+        self.write_record(fields.into_iter())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Split a line at `delimiter`, honoring double quotes.
+fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Reads CSV written by `CsvWriter` back as readings.
+///
+/// The header line is required and needs the `timestamp`, `function` and `range` columns. The other columns are optional and `unit` is not read.
+/// The dialect is taken from the header: `;` means `Dialect::DECIMAL_COMMA`. The auto flag is not in the CSV and comes back false.
+///
+/// ```
+/// use es51986::csv::CsvReader;
+///
+/// let csv = "timestamp,function,coupling,range,value,unit,overflow,battery\r\n1970-01-01T00:00:01.500Z,voltage,AC,2,98.9,V,0,0\r\n";
+/// let readings: Vec<_> = CsvReader::new(csv.as_bytes()).unwrap().collect::<Result<_, _>>().unwrap();
+/// assert_eq!(readings[0].value, Some(98.9));
+/// ```
+pub struct CsvReader<R: BufRead> {
+    lines: io::Lines<R>,
+    columns: Vec<Option<Column>>,
+    dialect: Dialect,
+    line: usize,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let dialect = if header.contains(';') { Dialect::DECIMAL_COMMA } else { Dialect::STANDARD };
+        let columns: Vec<Option<Column>> = split_fields(header.trim_start_matches('\u{feff}').trim_end(), dialect.delimiter).iter()
+            .map(|name| Column::ALL.iter().copied().find(|c| c.name() == name.trim()))
+            .collect();
+        for required in [Column::Timestamp, Column::Function, Column::Range] {
+            if !columns.contains(&Some(required)) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("CSV header has no {} column", required.name())));
+            }
+        }
+        Ok(Self { lines, columns, dialect, line: 1 })
+    }
+
+    fn record(&self, line: &str) -> Result<Record, String> {
+        let mut record = Record {
+            schema: SCHEMA_VERSION, timestamp: String::new(), meter: None, function: String::new(), coupling: None, range: 0,
+            value: None, unit: None, base_unit: None, exponent: None, flags: Flags { overflow: false, battery_low: false, auto: false },
+        };
+        for (column, field) in self.columns.iter().zip(split_fields(line, self.dialect.delimiter)) {
+            let field = field.trim();
+            match column {
+                Some(Column::Timestamp) => record.timestamp = field.to_owned(),
+                Some(Column::Function) => record.function = field.to_owned(),
+                Some(Column::Coupling) => record.coupling = (!field.is_empty()).then(|| field.to_owned()),
+                Some(Column::Range) => record.range = field.parse().map_err(|_| format!("invalid range '{}'", field))?,
+                Some(Column::Value) if !field.is_empty() => {
+                    let value = field.replace(self.dialect.decimal_separator, ".");
+                    record.value = Some(value.parse().map_err(|_| format!("invalid value '{}'", field))?);
+                }
+                Some(Column::Overflow) => record.flags.overflow = field == "1",
+                Some(Column::Battery) => record.flags.battery_low = field == "1",
+                _ => {}
+            }
+        }
+        Ok(record)
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = io::Result<Reading>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = self.record(line.trim_end()).and_then(|r| r.to_reading().map_err(|e| e.to_string()));
+            return Some(result.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", self.line, e))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_millis(1500), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn all_columns() {
+        let mut w = CsvWriter::new(vec![]);
+        w.write(&reading("20989;806")).unwrap();
+        w.write(&reading("560003902")).unwrap();
+        w.write(&reading("00136>800")).unwrap();
+        assert_eq!(
+            String::from_utf8(w.into_inner()).unwrap(),
+            "timestamp,function,coupling,range,value,unit,overflow,battery\r\n\
+             1970-01-01T00:00:01.500Z,voltage,AC,2,98.9,V,0,0\r\n\
+             1970-01-01T00:00:01.500Z,ohm,,5,,MΩ,1,0\r\n\
+             1970-01-01T00:00:01.500Z,adp0,,0,,,0,0\r\n"
+        );
+    }
+
+    #[test]
+    fn read_back() {
+        let frames = ["20989;806", "560003902", "00136>800", "109853802"];
+        for dialect in [Dialect::STANDARD, Dialect::DECIMAL_COMMA] {
+            let mut w = CsvWriter::new(vec![]).with_dialect(dialect);
+            frames.iter().for_each(|f| w.write(&reading(f)).unwrap());
+            let csv = w.into_inner();
+            let readings: Vec<Reading> = CsvReader::new(&csv[..]).unwrap().collect::<io::Result<_>>().unwrap();
+            let records: Vec<Record> = readings.iter().map(|r| Record::new(r, None)).collect();
+            let expected: Vec<Record> = frames.iter().map(|f| {
+                let mut record = Record::new(&reading(f), None);
+                record.flags.auto = false;
+                record
+            }).collect();
+            assert_eq!(records, expected);
+        }
+        assert!(CsvReader::new(&b"value,unit\r\n"[..]).is_err());
+        let e = CsvReader::new(&b"timestamp,function,range\r\nx,voltage,1\r\n"[..]).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(e.to_string(), "line 2: invalid timestamp 'x'");
+    }
+
+    #[test]
+    fn decimal_comma_without_header() {
+        let mut w = CsvWriter::new(vec![]).with_dialect(Dialect::DECIMAL_COMMA).with_header(false)
+            .with_columns(&[Column::Value, Column::Unit]);
+        w.write(&reading("109853802")).unwrap();
+        assert_eq!(String::from_utf8(w.into_inner()).unwrap(), "0,985;kΩ\r\n");
+    }
+
+    #[test]
+    fn quote() {
+        let mut w = CsvWriter::new(vec![]).with_header(false).with_columns(&[Column::Value])
+            .with_dialect(Dialect { delimiter: ',', decimal_separator: ',' });
+        w.write(&reading("109853802")).unwrap();
+        assert_eq!(String::from_utf8(w.into_inner()).unwrap(), "\"0,985\"\r\n");
+    }
+}
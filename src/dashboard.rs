@@ -0,0 +1,199 @@
+//! Browser dashboard with a live feed.
+//!
+//! `serve()` exposes:
+//!
+//! * `/` - A static page showing the value, unit, annunciators and a scrolling chart. Nothing has to be installed on the viewing PC.
+//! * `/events` - Server-Sent Events stream. Each reading is sent in the `data` field as `{"record": <jsonl::Record>, "display": "<value>"}`, where `display` is the value formatted with the meter's decimals.
+//!
+//! A new client receives the latest reading immediately, then every reading as it arrives.
+//! A client that falls `QUEUE_LEN` readings behind is disconnected, and the browser reconnects.
+
+use std::{
+    io::{self, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, mpsc},
+    time::Duration,
+};
+
+use crate::{http, jsonl::Record, reading::Reading, server::Server, sink::Sink};
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Interval of the keep-alive comments, which also detect clients that went away.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Readings queued for a client before it is dropped as lagging.
+pub const QUEUE_LEN: usize = 64;
+
+#[derive(Debug, Default)]
+struct State {
+    latest: Option<String>,
+    subscribers: Vec<mpsc::SyncSender<String>>,
+}
+
+/// Feed of the dashboard. Clones share the same state, so one clone can be fed by the reader while another is served.
+#[derive(Debug, Clone, Default)]
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+    meter: Option<String>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Meter id shown on the page and put in the records.
+    pub fn with_meter(mut self, meter: &str) -> Self {
+        self.meter = Some(meter.to_owned());
+        self
+    }
+
+    /// Send `reading` to every connected client.
+    pub fn publish(&self, reading: &Reading) {
+        let record = Record::new(reading, self.meter.as_deref());
+        let json = serde_json::json!({ "record": record, "display": reading.format_value() }).to_string();
+        let mut state = self.state.lock().unwrap();
+        // A full queue means the client is not keeping up.
+        state.subscribers.retain(|s| s.try_send(json.clone()).is_ok());
+        state.latest = Some(json);
+    }
+
+    /// Number of connected event stream clients.
+    pub fn clients(&self) -> usize {
+        self.state.lock().unwrap().subscribers.len()
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let mut state = self.state.lock().unwrap();
+        if let Some(latest) = &state.latest {
+            let _ = tx.try_send(latest.clone());
+        }
+        state.subscribers.push(tx);
+        rx
+    }
+}
+
+impl Sink for Dashboard {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        self.publish(reading);
+        Ok(())
+    }
+}
+
+fn stream_events(stream: &mut TcpStream, dashboard: &Dashboard) -> io::Result<()> {
+    // A client that stops reading must not block this thread forever.
+    stream.set_write_timeout(Some(KEEP_ALIVE))?;
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+    stream.flush()?;
+    let events = dashboard.subscribe();
+    loop {
+        match events.recv_timeout(KEEP_ALIVE) {
+            Ok(json) => write!(stream, "data: {}\n\n", json)?,
+            Err(mpsc::RecvTimeoutError::Timeout) => write!(stream, ": keep-alive\n\n")?,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
+
+fn handle(stream: TcpStream, dashboard: &Dashboard) -> io::Result<()> {
+    stream.set_read_timeout(Some(http::REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = http::read_request(&mut reader)?;
+    let mut stream = stream;
+    match (request.method.as_str(), request.route()) {
+        ("GET", "/") => http::write_response(&mut stream, 200, "text/html; charset=utf-8", PAGE.as_bytes()),
+        ("GET", "/events") => stream_events(&mut stream, dashboard),
+        (_, "/" | "/events") => http::write_response(&mut stream, 405, "text/plain", b"method not allowed\n"),
+        _ => http::write_response(&mut stream, 404, "text/plain", b"not found\n"),
+    }
+}
+
+/// Serve the dashboard at `http://<addr>/` in a background thread.
+///
+/// ```no_run
+/// use es51986::dashboard::{serve, Dashboard};
+///
+/// let dashboard = Dashboard::new().with_meter("bench");
+/// let server = serve("0.0.0.0:8080", dashboard.clone()).unwrap();
+/// // Feed readings with dashboard.publish(&reading) ...
+/// ```
+pub fn serve<A: ToSocketAddrs>(addr: A, dashboard: Dashboard) -> io::Result<Server> {
+    Server::spawn(addr, move |stream| {
+        let _ = handle(stream, &dashboard);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, Read}, time::SystemTime};
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    fn get(server: &Server, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        stream
+    }
+
+    #[test]
+    fn page() {
+        let server = serve("127.0.0.1:0", Dashboard::new()).unwrap();
+        let mut response = String::new();
+        get(&server, "/").read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("new EventSource(\"events\")"));
+        response.clear();
+        get(&server, "/missing").read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn events() {
+        let dashboard = Dashboard::new().with_meter("bench");
+        dashboard.publish(&reading("00002?<0:"));
+        let server = serve("127.0.0.1:0", dashboard.clone()).unwrap();
+        let mut reader = BufReader::new(get(&server, "/events"));
+        let mut next_data = || loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(json) = line.strip_prefix("data: ") {
+                return serde_json::from_str::<serde_json::Value>(json).unwrap();
+            }
+        };
+
+        let latest = next_data();
+        assert_eq!(latest["display"], "-0.02");
+        let latest: Record = serde_json::from_value(latest["record"].clone()).unwrap();
+        assert_eq!(latest.value, Some(-0.02));
+        assert_eq!(latest.meter.as_deref(), Some("bench"));
+
+        // The client is subscribed once the latest reading has been received.
+        assert_eq!(dashboard.clients(), 1);
+        dashboard.publish(&reading("20989;806"));
+        let record = next_data();
+        assert_eq!(record["display"], "98.9");
+        assert_eq!(record["record"]["coupling"], "AC");
+    }
+
+    #[test]
+    fn lagging_client_dropped() {
+        let dashboard = Dashboard::new();
+        let events = dashboard.subscribe();
+        for _ in 0..QUEUE_LEN {
+            dashboard.publish(&reading("11000;80:"));
+        }
+        assert_eq!(dashboard.clients(), 1);
+        dashboard.publish(&reading("11000;80:"));
+        assert_eq!(dashboard.clients(), 0);
+        assert_eq!(events.iter().count(), QUEUE_LEN);
+    }
+}
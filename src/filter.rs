@@ -0,0 +1,309 @@
+use std::collections::VecDeque;
+
+use crate::{BaseUnit, reading::{MeasurementKey, Reading}};
+
+/// Digital filter over the numeric value of readings.
+///
+/// Readings without a value (overflow frames, functions that `get_value()` does not support) pass through untouched and do not disturb the filter state.
+/// Filters reset themselves when the function, range or unit of the incoming readings changes, so values of different measurements are never mixed.
+pub trait Filter {
+    /// Feed a reading and return it with the filtered value.
+    fn apply(&mut self, reading: Reading) -> Reading;
+
+    /// Discard the filter state.
+    fn reset(&mut self);
+}
+
+/// Tracks the measurement key and tells when a filter has to be reset.
+#[derive(Debug, Clone, Default)]
+struct KeyTracker {
+    key: Option<MeasurementKey>,
+}
+
+impl KeyTracker {
+    fn changed(&mut self, reading: &Reading) -> bool {
+        let key = reading.key();
+        let changed = self.key.is_some_and(|k| k != key);
+        self.key = Some(key);
+        changed
+    }
+
+    fn reset(&mut self) {
+        self.key = None;
+    }
+}
+
+/// Exponential moving average: `y = alpha * x + (1 - alpha) * y'`.
+#[derive(Debug, Clone)]
+pub struct Ema {
+    alpha: f64,
+    current: Option<f64>,
+    tracker: KeyTracker,
+}
+
+impl Ema {
+    /// # Panics
+    ///
+    /// Panics if `alpha` is not in `(0, 1]`.
+    pub fn new(alpha: f64) -> Self {
+        assert!(0.0 < alpha && alpha <= 1.0, "alpha should be in (0, 1] but {}", alpha);
+        Self { alpha, current: None, tracker: KeyTracker::default() }
+    }
+}
+
+impl Filter for Ema {
+    fn apply(&mut self, mut reading: Reading) -> Reading {
+        if self.tracker.changed(&reading) {
+            self.current = None;
+        }
+        if let Some(x) = reading.value {
+            let y = match self.current {
+                Some(prev) => self.alpha * x + (1.0 - self.alpha) * prev,
+                None => x,
+            };
+            self.current = Some(y);
+            reading.value = Some(y);
+        }
+        reading
+    }
+
+    fn reset(&mut self) {
+        self.current = None;
+        self.tracker.reset();
+    }
+}
+
+/// Arithmetic mean over the last `window` values.
+#[derive(Debug, Clone)]
+pub struct MovingAverage {
+    window: usize,
+    values: VecDeque<f64>,
+    tracker: KeyTracker,
+}
+
+impl MovingAverage {
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn new(window: usize) -> Self {
+        assert!(window != 0, "window should not be zero");
+        Self { window, values: VecDeque::with_capacity(window), tracker: KeyTracker::default() }
+    }
+}
+
+impl Filter for MovingAverage {
+    fn apply(&mut self, mut reading: Reading) -> Reading {
+        if self.tracker.changed(&reading) {
+            self.values.clear();
+        }
+        if let Some(x) = reading.value {
+            if self.values.len() == self.window {
+                self.values.pop_front();
+            }
+            self.values.push_back(x);
+            reading.value = Some(self.values.iter().sum::<f64>() / self.values.len() as f64);
+        }
+        reading
+    }
+
+    fn reset(&mut self) {
+        self.values.clear();
+        self.tracker.reset();
+    }
+}
+
+/// Median of the last `window` values. Rejects isolated spikes caused by glitch frames.
+#[derive(Debug, Clone)]
+pub struct Median {
+    window: usize,
+    values: VecDeque<f64>,
+    tracker: KeyTracker,
+}
+
+impl Median {
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn new(window: usize) -> Self {
+        assert!(window != 0, "window should not be zero");
+        Self { window, values: VecDeque::with_capacity(window), tracker: KeyTracker::default() }
+    }
+}
+
+impl Filter for Median {
+    fn apply(&mut self, mut reading: Reading) -> Reading {
+        if self.tracker.changed(&reading) {
+            self.values.clear();
+        }
+        if let Some(x) = reading.value {
+            if self.values.len() == self.window {
+                self.values.pop_front();
+            }
+            self.values.push_back(x);
+            let mut sorted: Vec<f64> = self.values.iter().copied().collect();
+            sorted.sort_by(f64::total_cmp);
+            let mid = sorted.len() / 2;
+            reading.value = Some(
+                if sorted.len() % 2 == 0 { (sorted[mid - 1] + sorted[mid]) / 2.0 } else { sorted[mid] }
+            );
+        }
+        reading
+    }
+
+    fn reset(&mut self) {
+        self.values.clear();
+        self.tracker.reset();
+    }
+}
+
+/// Subtracts a reference value like the REL button of a meter.
+///
+/// The reference is kept in the base unit (V, A, ...) so that it stays valid across range changes.
+#[derive(Debug, Clone)]
+pub struct Relative {
+    reference: Option<f64>,
+    fixed: bool,
+    base_unit: Option<BaseUnit>,
+}
+
+impl Relative {
+    /// Subtract `reference` given in the base unit.
+    pub fn new(reference: f64) -> Self {
+        Self { reference: Some(reference), fixed: true, base_unit: None }
+    }
+
+    /// Take the first value as the reference. It is taken again when the base unit changes or on `reset()`.
+    pub fn first() -> Self {
+        Self { reference: None, fixed: false, base_unit: None }
+    }
+}
+
+impl Filter for Relative {
+    fn apply(&mut self, mut reading: Reading) -> Reading {
+        let (Some(x), Some(unit)) = (reading.value, reading.value_unit()) else { return reading };
+        if !self.fixed && self.base_unit.is_some_and(|u| u != unit.base_unit) {
+            self.reference = None;
+        }
+        self.base_unit = Some(unit.base_unit);
+        let scale = 10f64.powi(unit.prefix_unit.exponent());
+        let reference = *self.reference.get_or_insert(x * scale);
+        reading.value = Some(x - reference / scale);
+        reading
+    }
+
+    fn reset(&mut self) {
+        if !self.fixed {
+            self.reference = None;
+            self.base_unit = None;
+        }
+    }
+}
+
+/// Applies filters one after another.
+///
+/// ```
+/// use es51986::filter::{Ema, FilterChain, Median};
+///
+/// let chain = FilterChain::new().with(Median::new(3)).with(Ema::new(0.2));
+/// ```
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter + Send>>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<F: Filter + Send + 'static>(mut self, filter: F) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn push(&mut self, filter: Box<dyn Filter + Send>) {
+        self.filters.push(filter);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl Filter for FilterChain {
+    fn apply(&mut self, reading: Reading) -> Reading {
+        self.filters.iter_mut().fold(reading, |r, f| f.apply(r))
+    }
+
+    fn reset(&mut self) {
+        self.filters.iter_mut().for_each(|f| f.reset());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    fn values<F: Filter>(filter: &mut F, frames: &[&str]) -> Vec<Option<f64>> {
+        frames.iter().map(|f| filter.apply(reading(f)).value).collect()
+    }
+
+    #[test]
+    fn ema() {
+        let mut ema = Ema::new(0.5);
+        assert_eq!(values(&mut ema, &["01000;80:", "03000;80:", "03000;80:"]), vec![Some(1.0), Some(2.0), Some(2.5)]);
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut avg = MovingAverage::new(2);
+        assert_eq!(values(&mut avg, &["01000;80:", "03000;80:", "05000;80:"]), vec![Some(1.0), Some(2.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn median_rejects_spike() {
+        let mut median = Median::new(3);
+        assert_eq!(
+            values(&mut median, &["01000;80:", "01000;80:", "09000;80:", "01000;80:"]),
+            vec![Some(1.0), Some(1.0), Some(1.0), Some(1.0)]
+        );
+    }
+
+    #[test]
+    fn relative() {
+        let mut rel = Relative::first();
+        assert_eq!(values(&mut rel, &["01000;80:", "01250;80:", "560003902"]), vec![Some(0.0), Some(0.25), None]);
+        let mut rel = Relative::new(0.5);
+        assert_eq!(values(&mut rel, &["11200;80:"]), vec![Some(11.5)]);
+    }
+
+    #[test]
+    fn overflow_passes_through() {
+        let mut avg = MovingAverage::new(4);
+        assert_eq!(values(&mut avg, &["01000;80:", "01000;90:", "03000;80:"]), vec![Some(1.0), None, Some(2.0)]);
+    }
+
+    #[test]
+    fn reset_on_range_change() {
+        let mut avg = MovingAverage::new(4);
+        // 1.000 V then 10.00 V range.
+        assert_eq!(values(&mut avg, &["01000;80:", "11000;80:"]), vec![Some(1.0), Some(10.0)]);
+    }
+
+    #[test]
+    fn chain() {
+        let mut chain = FilterChain::new().with(Median::new(3)).with(MovingAverage::new(2));
+        assert_eq!(
+            values(&mut chain, &["01000;80:", "09000;80:", "01000;80:"]),
+            vec![Some(1.0), Some(3.0), Some(3.0)]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Minimal HTTP/1.1 handling for the built-in endpoints and publishers. Only plain `http://` and what they need is supported.
+
+use std::{io::{self, BufRead, BufReader, Read, Write}, net::{TcpStream, ToSocketAddrs}, time::Duration};
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+#[cfg(any(feature = "prometheus", feature = "dashboard"))]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+}
+
+#[cfg(any(feature = "prometheus", feature = "dashboard"))]
+impl Request {
+    /// Path without the query string.
+    pub fn route(&self) -> &str {
+        self.path.split('?').next().unwrap_or("")
+    }
+}
+
+const MAX_HEADER_LINES: usize = 100;
+
+/// Longest request, status or header line accepted.
+const MAX_LINE_LEN: u64 = 8192;
+
+/// Time a client has to send its request.
+#[cfg(any(feature = "prometheus", feature = "dashboard"))]
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `read_line` that fails instead of buffering a line longer than `MAX_LINE_LEN`.
+fn read_line<R: Read>(reader: &mut BufReader<R>, line: &mut String) -> io::Result<usize> {
+    let len = reader.take(MAX_LINE_LEN).read_line(line)?;
+    if len as u64 == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Err(invalid_data("HTTP line too long"));
+    }
+    Ok(len)
+}
+
+/// Read header lines up to the empty line. Returns (lower case name, value) pairs.
+fn read_headers<R: Read>(reader: &mut BufReader<R>) -> io::Result<Vec<(String, String)>> {
+    let mut headers = vec![];
+    let mut line = String::new();
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if read_line(reader, &mut line)? == 0 {
+            return Err(invalid_data("unexpected end of HTTP header"));
+        }
+        let l = line.trim_end();
+        if l.is_empty() {
+            return Ok(headers);
+        }
+        if let Some((name, value)) = l.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+    }
+    Err(invalid_data("too many HTTP header lines"))
+}
+
+/// Read the request line and skip the headers. The body is not read.
+#[cfg(any(feature = "prometheus", feature = "dashboard"))]
+pub(crate) fn read_request<R: Read>(reader: &mut BufReader<R>) -> io::Result<Request> {
+    let mut line = String::new();
+    read_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(|| invalid_data("invalid HTTP request"))?.to_owned();
+    let path = parts.next().ok_or_else(|| invalid_data("invalid HTTP request"))?.to_owned();
+    read_headers(reader)?;
+    Ok(Request { method, path })
+}
+
+#[cfg(any(feature = "prometheus", feature = "dashboard"))]
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "",
+    }
+}
+
+#[cfg(any(feature = "prometheus", feature = "dashboard"))]
+pub(crate) fn write_response<W: Write>(w: &mut W, status: u16, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        w,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, reason(status), content_type, body.len()
+    )?;
+    w.write_all(body)?;
+    w.flush()
+}
+
+/// Parsed `http://host[:port]/path?query`. An IPv6 host is given in brackets, e.g. `http://[::1]:8086/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Url {
+    /// Host without the brackets of an IPv6 address.
+    pub host: String,
+    pub port: u16,
+    /// Path with the query string.
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> io::Result<Self> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| invalid_data("only http:// URLs are supported"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(p) => (&rest[..p], &rest[p..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(rest) => {
+                let (host, port) = rest.split_once(']').ok_or_else(|| invalid_data("missing ] in IPv6 host"))?;
+                match port {
+                    "" => (host, None),
+                    _ => (host, Some(port.strip_prefix(':').ok_or_else(|| invalid_data("invalid port"))?)),
+                }
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid_data("invalid port"))?,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(invalid_data("missing host"));
+        }
+        Ok(Self { host: host.to_owned(), port, path: path.to_owned() })
+    }
+
+    /// "host:port" for the Host header.
+    pub fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Connect to each address of `host` in turn, waiting at most `timeout` for each.
+pub(crate) fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", host));
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// POST `body` and return the status code and the response body.
+pub(crate) fn post(url: &Url, headers: &[(&str, &str)], body: &[u8], timeout: Duration) -> io::Result<(u16, Vec<u8>)> {
+    let mut stream = connect(&url.host, url.port, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path, url.authority(), body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    read_line(&mut reader, &mut status_line)?;
+    let status: u16 = status_line.split_whitespace().nth(1).and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_data("invalid HTTP status line"))?;
+    let headers = read_headers(&mut reader)?;
+    let mut response = vec![];
+    match headers.iter().find(|(n, _)| n == "content-length").and_then(|(_, v)| v.parse::<u64>().ok()) {
+        Some(len) => {
+            reader.take(len).read_to_end(&mut response)?;
+        }
+        None => {
+            reader.read_to_end(&mut response)?;
+        }
+    }
+    Ok((status, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url() {
+        assert_eq!(
+            Url::parse("http://localhost:8086/api/v2/write?bucket=b").unwrap(),
+            Url { host: "localhost".to_owned(), port: 8086, path: "/api/v2/write?bucket=b".to_owned() }
+        );
+        assert_eq!(Url::parse("http://example.com").unwrap().port, 80);
+        assert!(Url::parse("https://example.com").is_err());
+        assert!(Url::parse("http://:80/").is_err());
+        let v6 = Url::parse("http://[::1]:8086/write").unwrap();
+        assert_eq!((v6.host.as_str(), v6.port, v6.authority()), ("::1", 8086, "[::1]:8086".to_owned()));
+        assert_eq!(Url::parse("http://[fe80::1]").unwrap().port, 80);
+        assert!(Url::parse("http://[::1/").is_err());
+        assert!(Url::parse("http://[::1]8086/").is_err());
+    }
+
+    #[test]
+    fn long_line() {
+        let mut header = "X-Long: ".to_owned() + &"a".repeat(MAX_LINE_LEN as usize) + "\r\n\r\n";
+        assert!(read_headers(&mut BufReader::new(header.as_bytes())).is_err());
+        header.replace_range(10.., "\r\n\r\n");
+        assert_eq!(read_headers(&mut BufReader::new(header.as_bytes())).unwrap(), [("x-long".to_owned(), "aa".to_owned())]);
+    }
+}
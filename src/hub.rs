@@ -0,0 +1,360 @@
+//! Several meters read concurrently and merged into one time-aligned stream.
+
+use std::{
+    io::{self, Read},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    capture::CaptureError,
+    parser::{ParseError, Parser},
+    reading::Reading,
+    replay::{ReplayError, Replayer, Speed},
+};
+
+/// A reading with the id of the meter it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tagged {
+    pub meter: String,
+    pub reading: Reading,
+}
+
+/// What the sources of a `Hub` report.
+#[derive(Debug)]
+pub enum Message {
+    Reading(Tagged),
+    Error { meter: String, error: ParseError },
+    /// The source ended, with the read error if any.
+    Closed { meter: String, error: Option<io::Error> },
+}
+
+/// Reads each source in a background thread and merges the decoded readings into one channel.
+///
+/// Readings are stamped with the arrival time, except for replayed captures.
+///
+/// ```
+/// use std::time::Duration;
+/// use es51986::hub::{Hub, Message};
+///
+/// let mut hub = Hub::new();
+/// hub.add("in", &b"11200;80:\r\n"[..]);
+/// hub.add("out", &b"01000;80:\r\n"[..]);
+/// let mut meters = vec![];
+/// while let Ok(message) = hub.recv_timeout(Duration::from_secs(1)) {
+///     if let Message::Reading(tagged) = message {
+///         meters.push(tagged.meter);
+///     }
+/// }
+/// meters.sort();
+/// assert_eq!(meters, ["in", "out"]);
+/// ```
+#[derive(Debug)]
+pub struct Hub {
+    meters: Vec<String>,
+    open: usize,
+    tx: mpsc::Sender<Message>,
+    rx: mpsc::Receiver<Message>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { meters: vec![], open: 0, tx, rx }
+    }
+
+    fn feed(&mut self, meter: &str) -> Feed {
+        self.meters.push(meter.to_owned());
+        self.open += 1;
+        Feed { meter: meter.to_owned(), parser: Parser::new(), tx: self.tx.clone() }
+    }
+
+    /// Start reading `reader` as meter `meter`. Meter ids should be unique, as `Aligner::new()` panics otherwise.
+    pub fn add<R: Read + Send + 'static>(&mut self, meter: &str, mut reader: R) {
+        let mut feed = self.feed(meter);
+        thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            let error = loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break None,
+                    Ok(len) => {
+                        if feed.send(SystemTime::now(), &buf[..len]).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => break Some(e),
+                }
+            };
+            feed.close(error);
+        });
+    }
+
+    /// Start replaying a capture as meter `meter`. Readings keep their recorded timestamps.
+    pub fn add_capture<R: Read + Send + 'static>(&mut self, meter: &str, reader: R, speed: Speed) -> Result<(), ReplayError> {
+        let replayer = Replayer::new(reader, speed)?;
+        let mut feed = self.feed(meter);
+        thread::spawn(move || {
+            let result = replayer.for_each(|chunk| feed.send(chunk.timestamp, &chunk.data));
+            match result {
+                Err(ReplayError::Capture(CaptureError::Io(e))) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                result => feed.close(result.err().map(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))),
+            }
+        });
+        Ok(())
+    }
+
+    /// Meter ids in the order they were added.
+    pub fn meters(&self) -> &[String] {
+        &self.meters
+    }
+
+    /// Wait for the next message. Returns `Disconnected` once every source has been closed.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        if self.open == 0 {
+            return Err(RecvTimeoutError::Disconnected);
+        }
+        let message = self.rx.recv_timeout(timeout)?;
+        if let Message::Closed { .. } = message {
+            self.open -= 1;
+        }
+        Ok(message)
+    }
+}
+
+/// Decodes the bytes of one source and sends the results to the `Hub`.
+struct Feed {
+    meter: String,
+    parser: Parser,
+    tx: mpsc::Sender<Message>,
+}
+
+impl Feed {
+    /// Fails with `BrokenPipe` when the `Hub` is gone.
+    fn send(&mut self, timestamp: SystemTime, data: &[u8]) -> io::Result<()> {
+        for result in self.parser.parse(data) {
+            let message = match result {
+                Ok(output) => Message::Reading(Tagged { meter: self.meter.clone(), reading: Reading::new(timestamp, output) }),
+                Err(error) => Message::Error { meter: self.meter.clone(), error },
+            };
+            self.tx.send(message).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(())
+    }
+
+    fn close(self, error: Option<io::Error>) {
+        let _ = self.tx.send(Message::Closed { meter: self.meter, error });
+    }
+}
+
+/// The latest reading of every meter at the end of a time slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    /// Start of the slot, aligned to a multiple of the slot width since the UNIX epoch.
+    pub start: SystemTime,
+    pub width: Duration,
+    /// In the order of the meters given to `Aligner::new`.
+    /// `None` if the meter has no reading in this slot or within the maximum age before its end.
+    pub readings: Vec<(String, Option<Reading>)>,
+}
+
+impl Slot {
+    pub fn end(&self) -> SystemTime {
+        self.start + self.width
+    }
+
+    pub fn get(&self, meter: &str) -> Option<&Reading> {
+        self.readings.iter().find(|(id, _)| id == meter).and_then(|(_, r)| r.as_ref())
+    }
+}
+
+/// Merges tagged readings into time slots.
+///
+/// A slot is closed by a reading at or after its end, or by `tick`. Slots in which no meter has a reading within the maximum age are skipped.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use es51986::{Output, hub::{Aligner, Tagged}, reading::Reading};
+///
+/// let mut aligner = Aligner::new(&["in", "out"], Duration::from_secs(1));
+/// let tagged = |meter: &str, millis, frame: &str| Tagged {
+///     meter: meter.to_owned(),
+///     reading: Reading::new(SystemTime::UNIX_EPOCH + Duration::from_millis(millis), Output::parse(frame.as_bytes()).unwrap()),
+/// };
+/// assert!(aligner.push(&tagged("in", 100, "11200;80:")).is_empty());
+/// assert!(aligner.push(&tagged("out", 300, "01000;80:")).is_empty());
+/// let slots = aligner.push(&tagged("in", 1100, "11200;80:"));
+/// assert_eq!(slots[0].get("in").unwrap().value, Some(12.0));
+/// assert_eq!(slots[0].get("out").unwrap().value, Some(1.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Aligner {
+    width: Duration,
+    max_age: Duration,
+    latest: Vec<(String, Option<Reading>)>,
+    /// Start of the open slot.
+    current: Option<SystemTime>,
+}
+
+impl Aligner {
+    /// Default maximum age. The meter sends about 2 frames per second.
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(2);
+
+    /// # Panics
+    ///
+    /// Panics if `width` is zero or a meter id appears twice.
+    pub fn new<S: AsRef<str>>(meters: &[S], width: Duration) -> Self {
+        assert!(!width.is_zero(), "width should not be zero");
+        for (i, meter) in meters.iter().enumerate() {
+            assert!(meters[..i].iter().all(|m| m.as_ref() != meter.as_ref()), "meter id '{}' appears twice", meter.as_ref());
+        }
+        let latest = meters.iter().map(|m| (m.as_ref().to_owned(), None)).collect();
+        Self { width, max_age: Self::DEFAULT_MAX_AGE, latest, current: None }
+    }
+
+    /// How old the latest reading of a meter may be at the end of a slot, if it was taken before the slot.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    fn slot_start(&self, timestamp: SystemTime) -> SystemTime {
+        let since_epoch = timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let width = self.width.as_nanos();
+        let start = since_epoch / width * width;
+        SystemTime::UNIX_EPOCH + Duration::new((start / 1_000_000_000) as u64, (start % 1_000_000_000) as u32)
+    }
+
+    /// The slot starting at `start`, or `None` if every reading is too old.
+    fn slot(&self, start: SystemTime) -> Option<Slot> {
+        let end = start + self.width;
+        let readings: Vec<(String, Option<Reading>)> = self.latest.iter().map(|(meter, reading)| {
+            (meter.clone(), reading.clone().filter(|r| start <= r.timestamp || end.duration_since(r.timestamp).is_ok_and(|age| age <= self.max_age)))
+        }).collect();
+        readings.iter().any(|(_, r)| r.is_some()).then_some(Slot { start, width: self.width, readings })
+    }
+
+    fn close_until(&mut self, t: SystemTime) -> Vec<Slot> {
+        let mut closed = vec![];
+        let newest = self.latest.iter().filter_map(|(_, r)| r.as_ref().map(|r| r.timestamp)).max();
+        while let Some(start) = self.current.filter(|s| *s + self.width <= t) {
+            closed.extend(self.slot(start));
+            let next = start + self.width;
+            // Jump over a long silence instead of visiting every empty slot.
+            let stale = newest.is_none_or(|newest| newest + self.max_age < next);
+            self.current = Some(if stale { self.slot_start(t) } else { next });
+        }
+        closed
+    }
+
+    /// Add a reading. Returns the slots closed by this reading. Readings of unknown meters are ignored.
+    pub fn push(&mut self, tagged: &Tagged) -> Vec<Slot> {
+        let Some(index) = self.latest.iter().position(|(meter, _)| *meter == tagged.meter) else { return vec![] };
+        let closed = self.close_until(tagged.reading.timestamp);
+        if self.current.is_none() {
+            self.current = Some(self.slot_start(tagged.reading.timestamp));
+        }
+        self.latest[index].1 = Some(tagged.reading.clone());
+        closed
+    }
+
+    /// Close the slots that end at or before `now`, for live sources that go quiet.
+    pub fn tick(&mut self, now: SystemTime) -> Vec<Slot> {
+        self.close_until(now)
+    }
+
+    /// Close the open slot.
+    pub fn finish(&mut self) -> Option<Slot> {
+        self.current.take().and_then(|start| self.slot(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Output;
+
+    use super::*;
+
+    fn tagged(meter: &str, millis: u64, frame: &str) -> Tagged {
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+        Tagged { meter: meter.to_owned(), reading: Reading::new(timestamp, Output::parse(frame.as_bytes()).unwrap()) }
+    }
+
+    #[test]
+    fn latest_reading_per_slot() {
+        let mut aligner = Aligner::new(&["in", "out"], Duration::from_millis(500));
+        aligner.push(&tagged("in", 100, "11000;80:"));
+        aligner.push(&tagged("in", 400, "11200;80:"));
+        aligner.push(&tagged("out", 450, "01000;80:"));
+        let slots = aligner.push(&tagged("in", 1200, "11000;80:"));
+        // The second slot repeats the latest readings since no meter sent anything newer.
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[0].start, SystemTime::UNIX_EPOCH);
+        assert_eq!(slots[0].get("in").unwrap().value, Some(12.0));
+        assert_eq!(slots[1].end(), SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        assert_eq!(slots[1].get("out").unwrap().value, Some(1.0));
+        let last = aligner.finish().unwrap();
+        assert_eq!(last.get("in").unwrap().value, Some(10.0));
+        assert_eq!(aligner.finish(), None);
+    }
+
+    #[test]
+    fn stale_readings_and_silence() {
+        let mut aligner = Aligner::new(&["in", "out"], Duration::from_secs(1)).with_max_age(Duration::from_millis(1500));
+        aligner.push(&tagged("in", 0, "11000;80:"));
+        aligner.push(&tagged("out", 900, "01000;80:"));
+        assert!(aligner.push(&tagged("other", 5000, "01000;80:")).is_empty());
+        let slots = aligner.tick(SystemTime::UNIX_EPOCH + Duration::from_secs(3600));
+        assert_eq!(slots.len(), 2);
+        assert!(slots[0].get("in").is_some());
+        assert_eq!(slots[1].get("in"), None);
+        assert!(slots[1].get("out").is_some());
+        // After an hour of silence the next slot starts at the new reading.
+        let slots = aligner.push(&tagged("in", 3_600_200, "11000;80:"));
+        assert!(slots.is_empty());
+        assert_eq!(aligner.finish().unwrap().start, SystemTime::UNIX_EPOCH + Duration::from_secs(3600));
+    }
+
+    #[test]
+    #[should_panic(expected = "meter id 'in' appears twice")]
+    fn duplicate_meter_id() {
+        Aligner::new(&["in", "out", "in"], Duration::from_secs(1));
+    }
+
+    #[test]
+    fn hub_reports_errors_and_closing() {
+        let mut hub = Hub::new();
+        hub.add("a", &b"11000;80:\r\nX\r\n"[..]);
+        let mut messages = vec![];
+        while let Ok(message) = hub.recv_timeout(Duration::from_secs(1)) {
+            messages.push(message);
+        }
+        assert_eq!(hub.meters(), ["a"]);
+        assert!(matches!(&messages[0], Message::Reading(t) if t.meter == "a"));
+        assert!(matches!(&messages[1], Message::Error { .. }));
+        assert!(matches!(&messages[2], Message::Closed { error: None, .. }));
+    }
+
+    #[test]
+    fn hub_replays_capture() {
+        use crate::capture::{CaptureHeader, CaptureWriter, LineSettings};
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut w = CaptureWriter::new(vec![], CaptureHeader::new("x", LineSettings::default(), start)).unwrap();
+        w.write_chunk(start + Duration::from_secs(1), b"01000;80:\r\n").unwrap();
+        let mut hub = Hub::new();
+        assert!(hub.add_capture("bad", &b"not a capture"[..], Speed::AsFastAsPossible).is_err());
+        hub.add_capture("cap", std::io::Cursor::new(w.into_inner()), Speed::AsFastAsPossible).unwrap();
+        let Ok(Message::Reading(tagged)) = hub.recv_timeout(Duration::from_secs(1)) else { panic!() };
+        assert_eq!(tagged.reading.timestamp, start + Duration::from_secs(1));
+        assert!(matches!(hub.recv_timeout(Duration::from_secs(1)), Ok(Message::Closed { error: None, .. })));
+        assert_eq!(hub.meters(), ["cap"]);
+    }
+}
@@ -0,0 +1,361 @@
+//! InfluxDB line protocol output.
+//!
+//! ```text
+//! es51986,function=voltage,coupling=DC,range=2,unit=V,device=bench value=12.34,overflow=false,battery_low=false,auto=true 1709296496789000000
+//! ```
+//!
+//! `value` is in the base unit given by the `unit` tag (V, A, Ω, Hz or F), so that series stay continuous across range changes.
+//! It is omitted for overflow frames and functions without a defined unit. The `coupling`, `unit` and `device` tags are omitted when not applicable.
+
+use std::{collections::VecDeque, io::{self, Write}, time::{Duration, Instant}};
+
+use crate::{http, reading::Reading, sink::Sink, timestamp};
+
+pub const DEFAULT_MEASUREMENT: &str = "es51986";
+
+fn escape_key(s: &str) -> String {
+    s.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+fn escape_measurement(s: &str) -> String {
+    s.replace('\\', "\\\\").replace(',', "\\,").replace(' ', "\\ ")
+}
+
+/// One line (without the trailing newline) for `reading`.
+pub fn format_line(reading: &Reading, measurement: &str, device: Option<&str>) -> String {
+    let output = &reading.output;
+    let mut line = format!(
+        "{},function={}",
+        escape_measurement(measurement), output.function.name()
+    );
+    let coupling = output.option2.coupling();
+    if !coupling.is_empty() {
+        line.push_str(&format!(",coupling={}", escape_key(coupling)));
+    }
+    line.push_str(&format!(",range={}", output.range.index()));
+    if let Some(unit) = reading.value_unit() {
+        line.push_str(&format!(",unit={}", escape_key(unit.base_unit.symbol())));
+    }
+    if let Some(device) = device {
+        line.push_str(&format!(",device={}", escape_key(device)));
+    }
+    line.push(' ');
+    if let Some(value) = reading.si_value() {
+        line.push_str(&format!("value={},", value));
+    }
+    line.push_str(&format!(
+        "overflow={},battery_low={},auto={} {}",
+        output.status.is_overflow, output.status.is_battery_depleted, output.option2.is_auto, timestamp::unix_nanos(reading.timestamp)
+    ));
+    line
+}
+
+/// Writes line protocol to a file, stdout or any other writer.
+pub struct InfluxWriter<W: Write> {
+    writer: W,
+    measurement: String,
+    device: Option<String>,
+}
+
+impl<W: Write> InfluxWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, measurement: DEFAULT_MEASUREMENT.to_owned(), device: None }
+    }
+
+    pub fn with_measurement(mut self, measurement: &str) -> Self {
+        self.measurement = measurement.to_owned();
+        self
+    }
+
+    pub fn with_device(mut self, device: &str) -> Self {
+        self.device = Some(device.to_owned());
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for InfluxWriter<W> {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        writeln!(self.writer, "{}", format_line(reading, &self.measurement, self.device.as_deref()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Posts line protocol to an InfluxDB write endpoint, e.g.
+/// `http://localhost:8086/api/v2/write?org=lab&bucket=meter&precision=ns` (v2) or `http://localhost:8086/write?db=meter` (v1).
+///
+/// Lines are buffered and sent when `batch_size` lines have accumulated or on `flush()`.
+/// Unsent lines are kept when the server cannot be reached or answers 5xx, 408 or 429, and are retried after `retry_interval`.
+/// Other 4xx answers mean the lines are refused, so they are discarded and the error is returned.
+/// At most `max_pending` lines are kept; beyond that the oldest are dropped and the next failing flush reports how many.
+pub struct InfluxHttp {
+    url: http::Url,
+    token: Option<String>,
+    measurement: String,
+    device: Option<String>,
+    batch_size: usize,
+    timeout: Duration,
+    max_pending: usize,
+    retry_interval: Duration,
+    /// No automatic flush before this after a failure.
+    next_retry: Option<Instant>,
+    buffer: VecDeque<String>,
+    /// Lines dropped since the last report.
+    dropped: usize,
+}
+
+impl InfluxHttp {
+    pub const DEFAULT_BATCH_SIZE: usize = 100;
+    pub const DEFAULT_MAX_PENDING: usize = 100_000;
+
+    pub fn new(url: &str) -> io::Result<Self> {
+        Ok(Self {
+            url: http::Url::parse(url)?,
+            token: None,
+            measurement: DEFAULT_MEASUREMENT.to_owned(),
+            device: None,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+            timeout: Duration::from_secs(10),
+            max_pending: Self::DEFAULT_MAX_PENDING,
+            retry_interval: Duration::from_secs(10),
+            next_retry: None,
+            buffer: VecDeque::new(),
+            dropped: 0,
+        })
+    }
+
+    /// API token sent as `Authorization: Token <token>`.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_owned());
+        self
+    }
+
+    pub fn with_measurement(mut self, measurement: &str) -> Self {
+        self.measurement = measurement.to_owned();
+        self
+    }
+
+    pub fn with_device(mut self, device: &str) -> Self {
+        self.device = Some(device.to_owned());
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size != 0, "batch_size should not be zero");
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Maximum number of lines kept while the server cannot be reached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_pending` is zero.
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        assert!(max_pending != 0, "max_pending should not be zero");
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Time after a failed request before `write()` tries again. Default is 10 seconds. `flush()` always tries.
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Lines waiting to be sent.
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl Sink for InfluxHttp {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        if self.max_pending <= self.buffer.len() {
+            self.buffer.pop_front();
+            self.dropped += 1;
+        }
+        self.buffer.push_back(format_line(reading, &self.measurement, self.device.as_deref()));
+        if self.batch_size <= self.buffer.len() && self.next_retry.is_none_or(|t| t <= Instant::now()) {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        for line in &self.buffer {
+            body.push_str(line);
+            body.push('\n');
+        }
+        let authorization = self.token.as_ref().map(|t| format!("Token {}", t));
+        let mut headers = vec![("Content-Type", "text/plain; charset=utf-8")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        let result = http::post(&self.url, &headers, body.as_bytes(), self.timeout).and_then(|(status, response)| {
+            let message = || format!("InfluxDB returned {}: {}", status, String::from_utf8_lossy(&response).trim());
+            match status {
+                200..=299 => Ok(()),
+                400..=499 if status != 408 && status != 429 => {
+                    let lines = self.buffer.len();
+                    self.buffer.clear();
+                    Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}; {} lines discarded", message(), lines)))
+                }
+                _ => Err(io::Error::other(message())),
+            }
+        });
+        self.next_retry = match &result {
+            Err(e) if e.kind() != io::ErrorKind::InvalidData => Some(Instant::now() + self.retry_interval),
+            _ => None,
+        };
+        match result {
+            Ok(()) => {
+                self.buffer.clear();
+                self.dropped = 0;
+                Ok(())
+            }
+            Err(e) if self.dropped != 0 => {
+                let e = io::Error::new(e.kind(), format!("{} ({} oldest lines dropped)", e, self.dropped));
+                self.dropped = 0;
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Read}, net::TcpListener, thread, time::SystemTime};
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_296_496_789), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn line() {
+        assert_eq!(
+            format_line(&reading("00002?<0:"), "es51986", Some("bench 1")),
+            "es51986,function=milli_ampere,coupling=DC,range=0,unit=A,device=bench\\ 1 value=-0.00002,overflow=false,battery_low=false,auto=true 1709296496789000000"
+        );
+        assert_eq!(
+            format_line(&reading("560003902"), "es51986", None),
+            "es51986,function=ohm,range=5,unit=Ω overflow=true,battery_low=false,auto=true 1709296496789000000"
+        );
+    }
+
+    #[test]
+    fn writer() {
+        let mut w = InfluxWriter::new(vec![]).with_measurement("dmm").with_device("psu");
+        w.write(&reading("00136>800")).unwrap();
+        assert_eq!(
+            String::from_utf8(w.into_inner()).unwrap(),
+            "dmm,function=adp0,range=0,device=psu overflow=false,battery_low=false,auto=false 1709296496789000000\n"
+        );
+    }
+
+    /// Accepts one request, answers with `status` and returns the request text.
+    fn mock_server(status: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v2/write?bucket=meter&precision=ns", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(v) = line.strip_prefix("Content-Length: ") {
+                    len = v.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            let mut stream = stream;
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 5\r\n\r\nerror", status).unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn post() {
+        let (url, server) = mock_server("204 No Content");
+        let mut sink = InfluxHttp::new(&url).unwrap().with_token("secret").with_batch_size(2);
+        sink.write(&reading("00002?<0:")).unwrap();
+        assert_eq!(sink.pending(), 1);
+        sink.write(&reading("20989;806")).unwrap();
+        assert_eq!(sink.pending(), 0);
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /api/v2/write?bucket=meter&precision=ns HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Token secret\r\n"));
+        assert!(request.ends_with("\r\n\r\nes51986,function=milli_ampere,coupling=DC,range=0,unit=A value=-0.00002,overflow=false,battery_low=false,auto=true 1709296496789000000\n\
+            es51986,function=voltage,coupling=AC,range=2,unit=V value=98.9,overflow=false,battery_low=false,auto=true 1709296496789000000\n"));
+    }
+
+    #[test]
+    fn post_failure_keeps_lines() {
+        let (url, server) = mock_server("503 Service Unavailable");
+        let mut sink = InfluxHttp::new(&url).unwrap().with_batch_size(1);
+        let err = sink.write(&reading("00002?<0:")).unwrap_err();
+        assert_eq!(err.to_string(), "InfluxDB returned 503: error");
+        server.join().unwrap();
+        // No new request until the retry interval has passed.
+        sink.write(&reading("00002?<0:")).unwrap();
+        assert_eq!(sink.pending(), 2);
+    }
+
+    #[test]
+    fn refused_lines_discarded() {
+        let (url, server) = mock_server("400 Bad Request");
+        let mut sink = InfluxHttp::new(&url).unwrap();
+        sink.write(&reading("00002?<0:")).unwrap();
+        let err = sink.flush().unwrap_err();
+        assert_eq!(err.to_string(), "InfluxDB returned 400: error; 1 lines discarded");
+        assert_eq!(sink.pending(), 0);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn pending_lines_capped() {
+        let (url, server) = mock_server("500 Internal Server Error");
+        let mut sink = InfluxHttp::new(&url).unwrap().with_max_pending(2);
+        for _ in 0..3 {
+            sink.write(&reading("00002?<0:")).unwrap();
+        }
+        assert_eq!(sink.pending(), 2);
+        let err = sink.flush().unwrap_err();
+        assert_eq!(err.to_string(), "InfluxDB returned 500: error (1 oldest lines dropped)");
+        assert_eq!(sink.pending(), 2);
+        assert_eq!(server.join().unwrap().lines().filter(|l| l.starts_with("es51986,")).count(), 2);
+    }
+}
@@ -0,0 +1,249 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{Function, Range, reading::Reading};
+
+/// Voltage used to derive energy from the integrated current.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Voltage {
+    /// Energy is not calculated.
+    None,
+    /// Constant voltage in volts.
+    Fixed(f64),
+    /// Latest value given by `ChargeIntegrator::push_voltage()`.
+    Measured,
+}
+
+/// Tells how the integrator handled the readings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntegrationReport {
+    /// Current readings used for integration.
+    pub samples: usize,
+    /// Intervals longer than the gap threshold. They are not integrated.
+    pub gaps: usize,
+    /// Total length of the skipped intervals.
+    pub gap_time: Duration,
+    /// Overflow frames. The interval before and after such a frame is not integrated.
+    pub overflow_frames: usize,
+    /// Range changes between consecutive current readings. Integration continues across them.
+    pub range_changes: usize,
+    /// Readings that are not DC current (other functions, AC). They break the integration like a gap.
+    pub ignored: usize,
+    /// Total time actually integrated.
+    pub integrated_time: Duration,
+}
+
+/// Integrates timestamped current readings into charge with the trapezoidal rule.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use es51986::{Output, integrator::{ChargeIntegrator, Voltage}, reading::Reading};
+///
+/// let mut integrator = ChargeIntegrator::new().with_voltage(Voltage::Fixed(5.0));
+/// // 1.000 A DC for one second.
+/// let output = Output::parse(b"010009808").unwrap();
+/// integrator.push(&Reading::new(SystemTime::UNIX_EPOCH, output.clone()));
+/// integrator.push(&Reading::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1), output.clone()));
+/// assert_eq!(integrator.charge_mah(), 1000.0 / 3600.0);
+/// assert_eq!(integrator.energy_wh(), Some(5.0 / 3600.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChargeIntegrator {
+    gap_threshold: Duration,
+    voltage: Voltage,
+    measured_voltage: Option<f64>,
+    prev: Option<Sample>,
+    coulombs: f64,
+    joules: f64,
+    report: IntegrationReport,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    timestamp: SystemTime,
+    amperes: f64,
+    watts: Option<f64>,
+    range: Range,
+}
+
+impl Default for ChargeIntegrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChargeIntegrator {
+    pub const DEFAULT_GAP_THRESHOLD: Duration = Duration::from_secs(2);
+
+    pub fn new() -> Self {
+        Self {
+            gap_threshold: Self::DEFAULT_GAP_THRESHOLD,
+            voltage: Voltage::None,
+            measured_voltage: None,
+            prev: None,
+            coulombs: 0.0,
+            joules: 0.0,
+            report: IntegrationReport::default(),
+        }
+    }
+
+    /// Interval between readings regarded as missing data.
+    pub fn with_gap_threshold(mut self, gap_threshold: Duration) -> Self {
+        self.gap_threshold = gap_threshold;
+        self
+    }
+
+    pub fn with_voltage(mut self, voltage: Voltage) -> Self {
+        self.voltage = voltage;
+        self
+    }
+
+    fn volts(&self) -> Option<f64> {
+        match self.voltage {
+            Voltage::None => None,
+            Voltage::Fixed(v) => Some(v),
+            Voltage::Measured => self.measured_voltage,
+        }
+    }
+
+    /// Update the voltage for `Voltage::Measured` from a DC voltage reading of another meter.
+    /// Readings of other functions and overflow frames are ignored.
+    pub fn push_voltage(&mut self, reading: &Reading) {
+        if reading.output.function == Function::Voltage && !reading.output.option2.is_ac {
+            if let Some(v) = reading.si_value() {
+                self.measured_voltage = Some(v);
+            }
+        }
+    }
+
+    /// Add a current reading.
+    pub fn push(&mut self, reading: &Reading) {
+        let output = &reading.output;
+        if !output.function.is_current() || output.option2.is_ac {
+            self.report.ignored += 1;
+            self.prev = None;
+            return;
+        }
+        if output.status.is_overflow {
+            self.report.overflow_frames += 1;
+            self.prev = None;
+            return;
+        }
+        let Some(amperes) = reading.si_value() else {
+            self.report.ignored += 1;
+            self.prev = None;
+            return;
+        };
+
+        let sample = Sample {
+            timestamp: reading.timestamp,
+            amperes,
+            watts: self.volts().map(|v| v * amperes),
+            range: output.range,
+        };
+        self.report.samples += 1;
+        if let Some(prev) = self.prev {
+            if prev.range != sample.range {
+                self.report.range_changes += 1;
+            }
+            if let Ok(dt) = sample.timestamp.duration_since(prev.timestamp) {
+                if self.gap_threshold < dt {
+                    self.report.gaps += 1;
+                    self.report.gap_time += dt;
+                } else {
+                    let secs = dt.as_secs_f64();
+                    self.coulombs += (prev.amperes + sample.amperes) / 2.0 * secs;
+                    if let (Some(w0), Some(w1)) = (prev.watts, sample.watts) {
+                        self.joules += (w0 + w1) / 2.0 * secs;
+                    }
+                    self.report.integrated_time += dt;
+                }
+            }
+        }
+        self.prev = Some(sample);
+    }
+
+    pub fn charge_coulombs(&self) -> f64 {
+        self.coulombs
+    }
+
+    pub fn charge_ah(&self) -> f64 {
+        self.coulombs / 3600.0
+    }
+
+    pub fn charge_mah(&self) -> f64 {
+        self.coulombs / 3.6
+    }
+
+    /// Integrated energy. `None` if no voltage is configured.
+    pub fn energy_wh(&self) -> Option<f64> {
+        match self.voltage {
+            Voltage::None => None,
+            _ => Some(self.joules / 3600.0),
+        }
+    }
+
+    pub fn report(&self) -> &IntegrationReport {
+        &self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(secs: f64, frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_secs_f64(secs), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn trapezoid() {
+        let mut i = ChargeIntegrator::new();
+        // 0 mA -> 10.0 mA over one second (MilliAmpere, range 1).
+        i.push(&reading(0.0, "10000?80:"));
+        i.push(&reading(1.0, "10100?80:"));
+        assert!((i.charge_coulombs() - 0.005).abs() < 1e-12);
+        assert_eq!(i.report().samples, 2);
+        assert_eq!(i.report().range_changes, 0);
+        assert_eq!(i.energy_wh(), None);
+    }
+
+    #[test]
+    fn gaps_and_overflow() {
+        let mut i = ChargeIntegrator::new();
+        i.push(&reading(0.0, "010009808"));
+        i.push(&reading(10.0, "010009808"));
+        i.push(&reading(10.5, "010009908"));
+        i.push(&reading(11.0, "010009808"));
+        i.push(&reading(12.0, "010009808"));
+        let report = i.report();
+        assert_eq!(report.gaps, 1);
+        assert_eq!(report.gap_time, Duration::from_secs(10));
+        assert_eq!(report.overflow_frames, 1);
+        assert_eq!(report.integrated_time, Duration::from_secs(1));
+        assert!((i.charge_coulombs() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn range_change() {
+        let mut i = ChargeIntegrator::new();
+        // 1.00 mA in range 0 then 1.0 mA in range 1.
+        i.push(&reading(0.0, "00100?80:"));
+        i.push(&reading(1.0, "10010?80:"));
+        assert_eq!(i.report().range_changes, 1);
+        assert!((i.charge_coulombs() - 0.001).abs() < 1e-12);
+    }
+
+    #[test]
+    fn measured_voltage() {
+        let mut i = ChargeIntegrator::new().with_voltage(Voltage::Measured);
+        i.push(&reading(0.0, "010009808"));
+        i.push_voltage(&reading(0.0, "11200;80:"));
+        i.push(&reading(1.0, "010009808"));
+        i.push(&reading(2.0, "010009808"));
+        // The first interval has no voltage yet.
+        assert!((i.energy_wh().unwrap() - 12.0 / 3600.0).abs() < 1e-12);
+        assert!((i.charge_ah() - 2.0 / 3600.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,232 @@
+//! Versioned JSON Lines record format.
+//!
+//! Each reading is written as one JSON object per line. Every field is always present (`null` when not applicable), so consumers can rely on the field set.
+//! Fields are only added in new schema versions, never removed or changed in meaning.
+//!
+//! Schema version 1:
+//!
+//! | field       | type            | description                                                            |
+//! |-------------|-----------------|------------------------------------------------------------------------|
+//! | `schema`    | number          | Schema version. Always 1.                                              |
+//! | `timestamp` | string          | RFC 3339 in UTC with millisecond precision.                            |
+//! | `meter`     | string or null  | Meter id if configured.                                                |
+//! | `function`  | string          | `voltage`, `micro_ampere`, `milli_ampere`, `auto_ampere`, `manual_ampere`, `ohm`, `continuity`, `diode`, `frequency`, `capacitor`, `temperature`, `adp0` - `adp3`. |
+//! | `coupling`  | string or null  | `AC`, `DC`, `AC+DC` or null.                                           |
+//! | `range`     | number          | Range index 0-6.                                                       |
+//! | `value`     | number or null  | Signed value in `unit`. null for overflow frames and functions without a defined unit. |
+//! | `unit`      | string or null  | Unit symbol with prefix, e.g. `mV`, `kΩ`, `MHz`, `nF`.                 |
+//! | `base_unit` | string or null  | `A`, `V`, `Ω`, `Hz` or `F`.                                            |
+//! | `exponent`  | number or null  | SI exponent of the prefix. `value * 10^exponent` is in `base_unit`.    |
+//! | `flags`     | object          | `overflow`, `battery_low`, `auto` booleans.                            |
+//!
+//! Example:
+//!
+//! ```json
+//! {"schema":1,"timestamp":"2024-03-01T12:34:56.789Z","meter":null,"function":"milli_ampere","coupling":"DC","range":0,"value":-0.02,"unit":"mA","base_unit":"A","exponent":-3,"flags":{"overflow":false,"battery_low":false,"auto":true}}
+//! ```
+
+use std::{fmt, io::{self, Write}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Digits, Function, Output, Range, SIGN_MINUS, SIGN_PLUS, reading::Reading, sink::Sink, timestamp};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Flags {
+    pub overflow: bool,
+    pub battery_low: bool,
+    pub auto: bool,
+}
+
+/// One line of the JSON Lines output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub schema: u32,
+    pub timestamp: String,
+    pub meter: Option<String>,
+    pub function: String,
+    pub coupling: Option<String>,
+    pub range: u8,
+    pub value: Option<f64>,
+    pub unit: Option<String>,
+    pub base_unit: Option<String>,
+    pub exponent: Option<i32>,
+    pub flags: Flags,
+}
+
+impl Record {
+    pub fn new(reading: &Reading, meter: Option<&str>) -> Self {
+        let output = &reading.output;
+        let value_unit = reading.value_unit();
+        let coupling = output.option2.coupling();
+        Self {
+            schema: SCHEMA_VERSION,
+            timestamp: timestamp::rfc3339(reading.timestamp),
+            meter: meter.map(|m| m.to_owned()),
+            function: output.function.name().to_owned(),
+            coupling: (!coupling.is_empty()).then(|| coupling.to_owned()),
+            range: output.range.index(),
+            value: reading.format_value().and_then(|v| v.parse().ok()),
+            unit: value_unit.map(|u| u.symbol()),
+            base_unit: value_unit.map(|u| u.base_unit.symbol().to_owned()),
+            exponent: value_unit.map(|u| u.prefix_unit.exponent()),
+            flags: Flags {
+                overflow: output.status.is_overflow,
+                battery_low: output.status.is_battery_depleted,
+                auto: output.option2.is_auto,
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // Serializing a struct of plain fields never fails.
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Errors of `Record::to_reading()`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordError {
+    /// `timestamp` is not RFC 3339.
+    InvalidTimestamp(String),
+    /// `function` is not a name of `Function::name()`.
+    UnknownFunction(String),
+    /// `range` is not 0-6.
+    InvalidRange(u8),
+    /// `value` does not fit the four digits of the function and range.
+    ValueOutOfRange(f64),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTimestamp(t) => write!(f, "invalid timestamp '{}'", t),
+            Self::UnknownFunction(name) => write!(f, "unknown function '{}'", name),
+            Self::InvalidRange(range) => write!(f, "invalid range {}", range),
+            Self::ValueOutOfRange(value) => write!(f, "value {} does not fit the range", value),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl Record {
+    /// Rebuild the reading, for converting a decoded log to another format.
+    ///
+    /// The frame is reconstructed from `function`, `range`, `coupling`, `flags` and `value`. `unit`, `base_unit` and `exponent` follow from those and are not read.
+    /// The temperature unit is not recorded and comes back as Celsius.
+    pub fn to_reading(&self) -> Result<Reading, RecordError> {
+        let timestamp = timestamp::parse_rfc3339(&self.timestamp).ok_or_else(|| RecordError::InvalidTimestamp(self.timestamp.clone()))?;
+        let function = Function::from_name(&self.function).ok_or_else(|| RecordError::UnknownFunction(self.function.clone()))?;
+        let range = Range::parse(b'0'.saturating_add(self.range)).map_err(|_| RecordError::InvalidRange(self.range))?;
+        let mut output = Output::parse(&[b'0' + range.index(), b'0', b'0', b'0', b'0', function.code(), b'8', b'0', b'0']).unwrap();
+        output.status.is_overflow = self.flags.overflow;
+        output.status.is_battery_depleted = self.flags.battery_low;
+        output.option2.is_auto = self.flags.auto;
+        let coupling = self.coupling.as_deref().unwrap_or("");
+        output.option2.is_ac = coupling.contains("AC");
+        output.option2.is_dc = coupling.contains("DC");
+        if let (Some(value), Some(zero), false) = (self.value, output.get_value(), self.flags.overflow) {
+            let decimals = zero.digits.find('.').map_or(0, |p| zero.digits.len() - p - 1);
+            let counts = (value.abs() * 10f64.powi(decimals as i32)).round();
+            if 9999.0 < counts {
+                return Err(RecordError::ValueOutOfRange(value));
+            }
+            let counts = counts as u16;
+            output.digits = Digits { digits: [(counts / 1000) as u8, (counts / 100 % 10) as u8, (counts / 10 % 10) as u8, (counts % 10) as u8] };
+            output.status.sign = if value.is_sign_negative() { SIGN_MINUS } else { SIGN_PLUS };
+        }
+        let mut reading = Reading::new(timestamp, output);
+        if reading.value.is_some() {
+            // Keep filtered values as they were.
+            reading.value = self.value;
+        }
+        Ok(reading)
+    }
+}
+
+/// Writes readings as JSON Lines records.
+pub struct JsonlWriter<W: Write> {
+    writer: W,
+    meter: Option<String>,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, meter: None }
+    }
+
+    pub fn with_meter(mut self, meter: &str) -> Self {
+        self.meter = Some(meter.to_owned());
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for JsonlWriter<W> {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        writeln!(self.writer, "{}", Record::new(reading, self.meter.as_deref()).to_json())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_296_496_789), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn record() {
+        assert_eq!(
+            Record::new(&reading("00002?<0:"), None).to_json(),
+            r#"{"schema":1,"timestamp":"2024-03-01T12:34:56.789Z","meter":null,"function":"milli_ampere","coupling":"DC","range":0,"value":-0.02,"unit":"mA","base_unit":"A","exponent":-3,"flags":{"overflow":false,"battery_low":false,"auto":true}}"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for frame in ["00002?<0:", "20989;806", "109853802", "11000;:0:"] {
+            let reading = reading(frame);
+            assert_eq!(Record::new(&reading, None).to_reading().unwrap(), reading, "{}", frame);
+        }
+        // The digits of frames without a value are not recorded, but everything in the record is kept.
+        for frame in ["560003902", "00136>800"] {
+            let record = Record::new(&reading(frame), None);
+            assert_eq!(Record::new(&record.to_reading().unwrap(), None), record, "{}", frame);
+        }
+        let mut record = Record::new(&reading("11000;80:"), None);
+        record.value = Some(123.0);
+        assert_eq!(record.to_reading(), Err(RecordError::ValueOutOfRange(123.0)));
+        record.function = "volt".to_owned();
+        assert_eq!(record.to_reading(), Err(RecordError::UnknownFunction("volt".to_owned())));
+    }
+
+    #[test]
+    fn overflow_has_no_value() {
+        let mut w = JsonlWriter::new(vec![]).with_meter("bench-1");
+        w.write(&reading("560003902")).unwrap();
+        let line = String::from_utf8(w.into_inner()).unwrap();
+        let record: Record = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(record.meter.as_deref(), Some("bench-1"));
+        assert_eq!(record.value, None);
+        assert_eq!(record.unit.as_deref(), Some("MΩ"));
+        assert_eq!(record.exponent, Some(6));
+        assert!(record.flags.overflow);
+        assert!(line.contains(r#""value":null"#));
+    }
+}
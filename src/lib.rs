@@ -1,9 +1,40 @@
 use parser::ParseError;
 use serde::{Deserialize, Serialize};
 
+pub mod binning;
+pub mod bucket;
+pub mod capture;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod csv;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod filter;
+pub mod hub;
+mod http;
+pub mod influx;
+pub mod integrator;
+pub mod jsonl;
+pub mod logger;
+#[cfg(feature = "modbus")]
+pub mod modbus;
+pub mod mqtt;
 pub mod parser;
-
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod reading;
+pub mod replay;
+#[cfg(feature = "scpi")]
+pub mod scpi;
+#[cfg(any(feature = "dashboard", feature = "modbus", feature = "prometheus", feature = "scpi"))]
+pub mod server;
+pub mod segment;
+pub mod sink;
+pub mod stability;
+pub mod timestamp;
+pub mod watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Range {
     Range0,
     Range1,
@@ -27,9 +58,14 @@ impl Range {
             _ => Err(ParseError::InvalidRange(c)),
         }
     }
+
+    /// 0 for `Range0`, 1 for `Range1` and so on.
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Function {
     Voltage,
     MicroAmpere,
@@ -49,6 +85,11 @@ pub enum Function {
 }
 
 impl Function {
+    pub const ALL: [Function; 15] = [
+        Self::Voltage, Self::MicroAmpere, Self::MilliAmpere, Self::AutoAmpere, Self::ManualAmpere, Self::Ohm, Self::Continuity, Self::Diode,
+        Self::Frequency, Self::Capacitor, Self::Temperature, Self::Adp0, Self::Adp1, Self::Adp2, Self::Adp3,
+    ];
+
     pub fn parse(c: u8) -> Result<Function, ParseError> {
         match c {
             0x3b => Ok(Self::Voltage),
@@ -69,15 +110,66 @@ impl Function {
             _ => Err(ParseError::InvalidFunction(c)),
         }
     }
+
+    /// Stable lower snake case name used by the output formats.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Voltage => "voltage",
+            Self::MicroAmpere => "micro_ampere",
+            Self::MilliAmpere => "milli_ampere",
+            Self::AutoAmpere => "auto_ampere",
+            Self::ManualAmpere => "manual_ampere",
+            Self::Ohm => "ohm",
+            Self::Continuity => "continuity",
+            Self::Diode => "diode",
+            Self::Frequency => "frequency",
+            Self::Capacitor => "capacitor",
+            Self::Temperature => "temperature",
+            Self::Adp0 => "adp0",
+            Self::Adp1 => "adp1",
+            Self::Adp2 => "adp2",
+            Self::Adp3 => "adp3",
+        }
+    }
+
+    /// Inverse of `name()`.
+    pub fn from_name(name: &str) -> Option<Function> {
+        Self::ALL.iter().copied().find(|f| f.name() == name)
+    }
+
+    /// Byte of the function in the frame. Inverse of `parse()`.
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Voltage => 0x3b,
+            Self::MicroAmpere => 0x3d,
+            Self::MilliAmpere => 0x3f,
+            Self::AutoAmpere => 0x30,
+            Self::ManualAmpere => 0x39,
+            Self::Ohm => 0x33,
+            Self::Continuity => 0x35,
+            Self::Diode => 0x31,
+            Self::Frequency => 0x32,
+            Self::Capacitor => 0x36,
+            Self::Temperature => 0x34,
+            Self::Adp0 => 0x3e,
+            Self::Adp1 => 0x3c,
+            Self::Adp2 => 0x38,
+            Self::Adp3 => 0x3a,
+        }
+    }
+
+    pub fn is_current(&self) -> bool {
+        matches!(self, Self::MicroAmpere | Self::MilliAmpere | Self::AutoAmpere | Self::ManualAmpere)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TemperatureUnit {
     Celsius,
     Fahrenheit,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Sign(bool);
 
 pub const SIGN_PLUS: Sign = Sign(false);
@@ -93,30 +185,35 @@ impl Sign {
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<i8> for Sign {
     fn into(self) -> i8 {
         if self.is_minus() { -1 } else { 1 }
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<i16> for Sign {
     fn into(self) -> i16 {
         if self.is_minus() { -1 } else { 1 }
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<i32> for Sign {
     fn into(self) -> i32 {
         if self.is_minus() { -1 } else { 1 }
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<i64> for Sign {
     fn into(self) -> i64 {
         if self.is_minus() { -1 } else { 1 }
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<i128> for Sign {
     fn into(self) -> i128 {
         if self.is_minus() { -1 } else { 1 }
@@ -157,9 +254,19 @@ impl Option2 {
             is_auto: (c & 0x02) != 0,
         }
     }
+
+    /// "AC", "DC", "AC+DC" or "" if neither is indicated.
+    pub fn coupling(&self) -> &'static str {
+        match (self.is_ac, self.is_dc) {
+            (true, true) => "AC+DC",
+            (true, false) => "AC",
+            (false, true) => "DC",
+            (false, false) => "",
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PrefixUnit {
     Mega,
     Kilo,
@@ -169,7 +276,32 @@ pub enum PrefixUnit {
     Nano,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+impl PrefixUnit {
+    /// Power of ten of the prefix. `Kilo` is 3, `Millis` is -3 and so on.
+    pub fn exponent(&self) -> i32 {
+        match self {
+            Self::Mega => 6,
+            Self::Kilo => 3,
+            Self::None => 0,
+            Self::Millis => -3,
+            Self::Micro => -6,
+            Self::Nano => -9,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Mega => "M",
+            Self::Kilo => "k",
+            Self::None => "",
+            Self::Millis => "m",
+            Self::Micro => "µ",
+            Self::Nano => "n",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BaseUnit {
     Ampere,
     Volt,
@@ -178,7 +310,19 @@ pub enum BaseUnit {
     Farad,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+impl BaseUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Ampere => "A",
+            Self::Volt => "V",
+            Self::Ohm => "Ω",
+            Self::Hearts => "Hz",
+            Self::Farad => "F",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ValueUnit {
     pub prefix_unit: PrefixUnit,
     pub base_unit: BaseUnit,
@@ -190,6 +334,11 @@ impl ValueUnit {
             prefix_unit, base_unit
         }
     }
+
+    /// Unit symbol such as "mV" or "kΩ".
+    pub fn symbol(&self) -> String {
+        format!("{}{}", self.prefix_unit.symbol(), self.base_unit.symbol())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -206,6 +355,7 @@ pub enum DigitRadix {
 }
 
 impl Digits {
+    #[allow(clippy::manual_range_contains)]
     fn parse_digit(c: u8) -> Result<u8, ParseError> {
         if 0x30 <= c && c <= 0x39 {
             Ok(c - 0x30)
@@ -452,6 +602,18 @@ impl Output {
             }
         }
     }
+
+    /// Signed numeric value in the unit of `get_value()`.
+    ///
+    /// Returns `None` if the frame is overflow or `get_value()` returns `None`, because the digits do not represent a reading in that case.
+    pub fn get_numeric_value(&self) -> Option<f64> {
+        if self.status.is_overflow {
+            return None;
+        }
+        let value = self.get_value()?;
+        let abs: f64 = value.digits.parse().ok()?;
+        Some(if self.status.sign.is_minus() { -abs } else { abs })
+    }
 }
 
 #[cfg(test)]
@@ -711,4 +873,23 @@ mod tests {
         assert_eq!(out.get_value(), Some(OutputValue { digits: "0.001".to_owned(), value_unit: ValueUnit { prefix_unit: PrefixUnit::None, base_unit: BaseUnit::Ampere}}));
         assert_eq!(results[0].as_ref(), results[1].as_ref());
     }
+
+    #[test]
+    fn numeric_value() {
+        assert_eq!(Output::parse(&to_u8("00002?<0:")).unwrap().get_numeric_value(), Some(-0.02));
+        assert_eq!(Output::parse(&to_u8("20989;806")).unwrap().get_numeric_value(), Some(98.9));
+        // Overflow
+        assert_eq!(Output::parse(&to_u8("560003902")).unwrap().get_numeric_value(), None);
+        // Not supported by get_value()
+        assert_eq!(Output::parse(&to_u8("00136>800")).unwrap().get_numeric_value(), None);
+    }
+
+    #[test]
+    fn function_codes() {
+        for function in Function::ALL {
+            assert_eq!(Function::parse(function.code()), Ok(function));
+            assert_eq!(Function::from_name(function.name()), Some(function));
+        }
+        assert_eq!(Function::from_name("volt"), None);
+    }
 }
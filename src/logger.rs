@@ -0,0 +1,460 @@
+//! Long-running logging to rotating files.
+//!
+//! Files are named `<prefix>-<YYYYMMDD>-<NNN>.<jsonl|csv>` with the UTC date of the file's first reading and a sequence number.
+//! A new file is started when the day changes (with `with_daily(true)`) or when the file reaches the size limit.
+//!
+//! Each reading is written as a complete line, and the file is fsynced every sync interval, on `flush()` and on rotation.
+//! After a restart the latest file is appended to if it may still be written to. A partial line left by a crash is removed first.
+//!
+//! When a file is closed, by rotation, `finish()` or drop, a `FileSummary` line is appended to `<prefix>-summary.jsonl` in the same directory.
+//! A file left without an up-to-date summary by a crash is summarized when the next run starts a new file instead of resuming it.
+//! A resumed file gets another summary of the whole file when it is closed again; the last line of a file is the one that counts.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{csv::CsvWriter, jsonl::{JsonlWriter, Record}, reading::Reading, sink::Sink, timestamp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `jsonl::Record` lines.
+    Jsonl,
+    /// CSV with all columns in the standard dialect.
+    Csv,
+}
+
+impl LogFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+/// Summary of a rotated file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSummary {
+    /// File name without the directory.
+    pub file: String,
+    /// RFC 3339 timestamp of the first reading.
+    pub first: Option<String>,
+    /// RFC 3339 timestamp of the last reading.
+    pub last: Option<String>,
+    pub readings: u64,
+    pub overflow: u64,
+    pub battery_low: u64,
+}
+
+impl FileSummary {
+    fn add(&mut self, timestamp: String, overflow: bool, battery_low: bool) {
+        if self.first.is_none() {
+            self.first = Some(timestamp.clone());
+        }
+        self.last = Some(timestamp);
+        self.readings += 1;
+        self.overflow += overflow as u64;
+        self.battery_low += battery_low as u64;
+    }
+}
+
+/// UTC date as "YYYYMMDD".
+fn date_of(t: SystemTime) -> String {
+    let secs = timestamp::unix_nanos(t).div_euclid(1_000_000_000) as i64;
+    let (year, month, day) = timestamp::civil_from_days(secs.div_euclid(86_400));
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+struct Current {
+    date: String,
+    path: PathBuf,
+    sink: Box<dyn Sink + Send>,
+    /// Second handle of the file for fsync and size.
+    file: File,
+    summary: FileSummary,
+    last_sync: Instant,
+}
+
+/// Writes readings to rotating files.
+///
+/// ```no_run
+/// use es51986::logger::{LogFormat, RotatingLogger};
+///
+/// let mut logger = RotatingLogger::new("logs", LogFormat::Jsonl)
+///     .with_daily(true)
+///     .with_max_size(100 * 1024 * 1024);
+/// // logger.write(&reading) ...
+/// ```
+pub struct RotatingLogger {
+    dir: PathBuf,
+    format: LogFormat,
+    prefix: String,
+    meter: Option<String>,
+    max_size: Option<u64>,
+    daily: bool,
+    sync_interval: Duration,
+    current: Option<Current>,
+}
+
+impl RotatingLogger {
+    pub const DEFAULT_PREFIX: &'static str = "es51986";
+
+    pub fn new<P: Into<PathBuf>>(dir: P, format: LogFormat) -> Self {
+        Self {
+            dir: dir.into(),
+            format,
+            prefix: Self::DEFAULT_PREFIX.to_owned(),
+            meter: None,
+            max_size: None,
+            daily: false,
+            sync_interval: Duration::from_secs(10),
+            current: None,
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// Meter id put in JSON records.
+    pub fn with_meter(mut self, meter: &str) -> Self {
+        self.meter = Some(meter.to_owned());
+        self
+    }
+
+    /// Start a new file once the current one has reached `bytes`. The check is done before each reading, so a file may exceed `bytes` by one line.
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Start a new file when the UTC date of the readings changes.
+    pub fn with_daily(mut self, daily: bool) -> Self {
+        self.daily = daily;
+        self
+    }
+
+    /// Maximum time between fsyncs. Default is 10 seconds.
+    pub fn with_sync_interval(mut self, interval: Duration) -> Self {
+        self.sync_interval = interval;
+        self
+    }
+
+    /// File currently written to, if any.
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|c| c.path.as_path())
+    }
+
+    pub fn summary_path(&self) -> PathBuf {
+        self.dir.join(format!("{}-summary.jsonl", self.prefix))
+    }
+
+    fn file_name(&self, date: &str, seq: u32) -> String {
+        format!("{}-{}-{:03}.{}", self.prefix, date, seq, self.format.extension())
+    }
+
+    /// (date, sequence) of the existing log files, sorted.
+    fn existing(&self) -> io::Result<Vec<(String, u32)>> {
+        let mut files = vec![];
+        let head = format!("{}-", self.prefix);
+        let tail = format!(".{}", self.format.extension());
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(rest) = name.to_str().and_then(|n| n.strip_prefix(&head)).and_then(|n| n.strip_suffix(&tail)) else { continue };
+            let Some((date, seq)) = rest.split_once('-') else { continue };
+            if date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()) && 3 <= seq.len() {
+                if let Ok(seq) = seq.parse() {
+                    files.push((date.to_owned(), seq));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Remove a partial last line and summarize the complete ones.
+    fn recover(&self, path: &Path) -> io::Result<FileSummary> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut summary = FileSummary::default();
+        let mut complete = 0u64;
+        let mut reader = BufReader::new(&file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let len = reader.read_line(&mut line)?;
+            if len == 0 || !line.ends_with('\n') {
+                break;
+            }
+            complete += len as u64;
+            let line = line.trim_end();
+            match self.format {
+                LogFormat::Jsonl => {
+                    if let Ok(record) = serde_json::from_str::<Record>(line) {
+                        summary.add(record.timestamp, record.flags.overflow, record.flags.battery_low);
+                    }
+                }
+                LogFormat::Csv => {
+                    let fields: Vec<&str> = line.split(',').collect();
+                    if fields.len() == 8 && fields[0] != "timestamp" {
+                        summary.add(fields[0].to_owned(), fields[6] == "1", fields[7] == "1");
+                    }
+                }
+            }
+        }
+        if complete < file.metadata()?.len() {
+            file.set_len(complete)?;
+            file.sync_all()?;
+        }
+        Ok(summary)
+    }
+
+    /// Whether the summary file already has `summary`.
+    fn summarized(&self, summary: &FileSummary) -> io::Result<bool> {
+        let text = match fs::read_to_string(self.summary_path()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        Ok(text.lines().filter_map(|l| serde_json::from_str::<FileSummary>(l).ok()).any(|s| s == *summary))
+    }
+
+    fn append_summary(&self, summary: &FileSummary) -> io::Result<()> {
+        let mut summaries = OpenOptions::new().create(true).append(true).open(self.summary_path())?;
+        writeln!(summaries, "{}", serde_json::to_string(summary).map_err(io::Error::other)?)?;
+        summaries.sync_all()
+    }
+
+    /// Open the file for a reading at `t`. The latest file is resumed when `resume` is set and it is still usable.
+    fn open(&mut self, t: SystemTime, resume: bool) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let today = date_of(t);
+        let existing = self.existing()?;
+        let resumable = existing.last().filter(|(date, seq)| {
+            resume && (!self.daily || *date == today) && self.max_size.is_none_or(|max| {
+                fs::metadata(self.dir.join(self.file_name(date, *seq))).map(|m| m.len() < max).unwrap_or(false)
+            })
+        }).cloned();
+        let (date, seq, summary) = match resumable {
+            Some((date, seq)) => {
+                let summary = self.recover(&self.dir.join(self.file_name(&date, seq)))?;
+                (date, seq, summary)
+            }
+            None => {
+                if let Some((date, seq)) = existing.last().filter(|_| resume) {
+                    let file = self.file_name(date, *seq);
+                    let summary = FileSummary { file: file.clone(), ..self.recover(&self.dir.join(&file))? };
+                    if !self.summarized(&summary)? {
+                        self.append_summary(&summary)?;
+                    }
+                }
+                let seq = existing.iter().filter(|(d, _)| *d == today).map(|(_, s)| s + 1).max().unwrap_or(0);
+                (today, seq, FileSummary::default())
+            }
+        };
+        let path = self.dir.join(self.file_name(&date, seq));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let empty = file.metadata()?.len() == 0;
+        let writer = BufWriter::new(file.try_clone()?);
+        let sink: Box<dyn Sink + Send> = match self.format {
+            LogFormat::Jsonl => {
+                let writer = JsonlWriter::new(writer);
+                Box::new(match &self.meter {
+                    Some(meter) => writer.with_meter(meter),
+                    None => writer,
+                })
+            }
+            LogFormat::Csv => Box::new(CsvWriter::new(writer).with_header(empty)),
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        self.current = Some(Current { date, path, sink, file, summary: FileSummary { file: file_name, ..summary }, last_sync: Instant::now() });
+        Ok(())
+    }
+
+    /// Sync the current file and record its summary.
+    fn close(&mut self) -> io::Result<()> {
+        let Some(mut current) = self.current.take() else { return Ok(()) };
+        current.sink.flush()?;
+        current.file.sync_all()?;
+        self.append_summary(&current.summary)
+    }
+
+    /// Sync and close the current file and record its summary. A later reading resumes the file as after a restart.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn needs_rotation(&self, current: &Current, reading: &Reading) -> io::Result<bool> {
+        if self.daily && date_of(reading.timestamp) != current.date {
+            return Ok(true);
+        }
+        match self.max_size {
+            Some(max) => Ok(max <= current.file.metadata()?.len()),
+            None => Ok(false),
+        }
+    }
+}
+
+impl Sink for RotatingLogger {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        match &self.current {
+            Some(current) => if self.needs_rotation(current, reading)? {
+                self.close()?;
+                self.open(reading.timestamp, false)?;
+            },
+            None => self.open(reading.timestamp, true)?,
+        }
+        let sync_interval = self.sync_interval;
+        let current = self.current.as_mut().unwrap();
+        current.sink.write(reading)?;
+        // Complete lines reach the file right away. Only fsync is deferred.
+        current.sink.flush()?;
+        current.summary.add(timestamp::rfc3339(reading.timestamp), reading.output.status.is_overflow, reading.output.status.is_battery_depleted);
+        if sync_interval <= current.last_sync.elapsed() {
+            current.file.sync_data()?;
+            current.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(current) = self.current.as_mut() {
+            current.sink.flush()?;
+            current.file.sync_data()?;
+            current.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RotatingLogger {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::Output;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!("es51986-logger-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn reading(frame: &str, secs: u64) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_secs(secs), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    fn summaries(logger: &RotatingLogger) -> Vec<FileSummary> {
+        fs::read_to_string(logger.summary_path()).unwrap_or_default().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    // 2024-03-01T00:00:00Z
+    const DAY: u64 = 1_709_251_200;
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = temp_dir();
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Jsonl).with_max_size(400);
+        for i in 0..5 {
+            logger.write(&reading(if i == 2 { "560003902" } else { "00002?<0:" }, DAY + i)).unwrap();
+        }
+        assert_eq!(logger.current_path().unwrap(), dir.join("es51986-20240301-002.jsonl"));
+        let summaries = summaries(&logger);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].file, "es51986-20240301-000.jsonl");
+        assert_eq!(summaries[0].readings, 2);
+        assert_eq!(summaries[0].first.as_deref(), Some("2024-03-01T00:00:00.000Z"));
+        assert_eq!(summaries[0].last.as_deref(), Some("2024-03-01T00:00:01.000Z"));
+        assert_eq!(summaries[1].overflow, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_daily() {
+        let dir = temp_dir();
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Csv).with_daily(true);
+        logger.write(&reading("11000;80:", DAY + 86_399)).unwrap();
+        logger.write(&reading("11200;80:", DAY + 86_400)).unwrap();
+        assert_eq!(logger.current_path().unwrap(), dir.join("es51986-20240302-000.csv"));
+        let first = fs::read_to_string(dir.join("es51986-20240301-000.csv")).unwrap();
+        assert_eq!(first, "timestamp,function,coupling,range,value,unit,overflow,battery\r\n2024-03-01T23:59:59.000Z,voltage,DC,1,10.00,V,0,0\r\n");
+        assert_eq!(summaries(&logger)[0].readings, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resume_after_restart() {
+        let dir = temp_dir();
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Csv).with_daily(true);
+        logger.write(&reading("11000;80:", DAY)).unwrap();
+        logger.write(&reading("11000;:0:", DAY + 1)).unwrap();
+        drop(logger);
+        // A crash in the middle of a line.
+        let path = dir.join("es51986-20240301-000.csv");
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"2024-03-01T00:00:02.000Z,vo").unwrap();
+
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Csv).with_daily(true);
+        logger.write(&reading("11200;80:", DAY + 3)).unwrap();
+        assert_eq!(logger.current_path().unwrap(), path);
+        logger.write(&reading("11200;80:", DAY + 86_400)).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 4);
+        assert!(text.ends_with("2024-03-01T00:00:03.000Z,voltage,DC,1,12.00,V,0,0\r\n"), "{}", text);
+        // One summary from the drop and one of the whole file from the rotation.
+        let summaries = summaries(&logger);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].readings, 2);
+        assert_eq!(summaries[1].readings, 3);
+        assert_eq!(summaries[1].battery_low, 1);
+        assert_eq!(summaries[1].first.as_deref(), Some("2024-03-01T00:00:00.000Z"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summary_without_rotation() {
+        let dir = temp_dir();
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Jsonl).with_daily(true);
+        logger.write(&reading("11000;80:", DAY)).unwrap();
+        logger.finish().unwrap();
+        assert_eq!(logger.current_path(), None);
+        assert_eq!(summaries(&logger).len(), 1);
+
+        // A crash leaves the next file without a summary. The next day's run adds it.
+        logger.write(&reading("11000;80:", DAY + 1)).unwrap();
+        std::mem::forget(logger);
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Jsonl).with_daily(true);
+        logger.write(&reading("11200;80:", DAY + 86_400)).unwrap();
+        let summaries = summaries(&logger);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[1].file, "es51986-20240301-000.jsonl");
+        assert_eq!(summaries[1].readings, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sequence_past_999() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("es51986-20240301-999.csv"), "").unwrap();
+        fs::write(dir.join("es51986-20240301-1000.csv"), "").unwrap();
+        fs::write(dir.join(RotatingLogger::DEFAULT_PREFIX.to_owned() + "-summary.jsonl"), "").unwrap();
+        let mut logger = RotatingLogger::new(&dir, LogFormat::Csv).with_max_size(0);
+        logger.write(&reading("11000;80:", DAY)).unwrap();
+        assert_eq!(logger.current_path().unwrap(), dir.join("es51986-20240301-1001.csv"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+//! Modbus TCP server exposing the latest reading as input registers.
+//!
+//! Default register map (input registers, function code 4, starting at address 0):
+//!
+//! | Address | Field | Description |
+//! |---|---|---|
+//! | 0 | `Value` | Signed value as a scaled integer. `Value * 10^Exponent` is in the base unit. -32768 when there is no value (overflow, no reading yet or no defined unit). |
+//! | 1 | `Exponent` | Signed power of ten of `Value`. |
+//! | 2 | `UnitCode` | 0: none, 1: V, 2: A, 3: Ω, 4: Hz, 5: F. |
+//! | 3 | `FunctionCode` | 0: voltage, 1: µA, 2: mA, 3: auto A, 4: manual A, 5: Ω, 6: continuity, 7: diode, 8: frequency, 9: capacitor, 10: temperature, 11-14: ADP0-ADP3. |
+//! | 4 | `Status` | Bit 0: overflow, 1: battery low, 2: auto, 3: DC, 4: AC, 15: no reading yet. |
+//! | 5 | `Range` | Range index 0-6. |
+//! | 6 | `FrameCounterHigh` | Upper 16 bits of the number of frames received. |
+//! | 7 | `FrameCounterLow` | Lower 16 bits. The counter stops changing when the meter goes silent, which a PLC can use to detect stale data. |
+//!
+//! The base address and the address of each field can be changed with `RegisterMap`. Addresses between mapped fields read as 0.
+//! Holding register reads (function code 3) return the same registers for clients that cannot read input registers.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+};
+
+use crate::{BaseUnit, reading::Reading, server::Server};
+
+/// Registered port of Modbus TCP.
+pub const DEFAULT_PORT: u16 = 502;
+
+const NO_VALUE: i16 = i16::MIN;
+const MAX_REGISTERS: u16 = 125;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Value,
+    Exponent,
+    UnitCode,
+    FunctionCode,
+    Status,
+    Range,
+    FrameCounterHigh,
+    FrameCounterLow,
+}
+
+/// Address of each field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterMap {
+    fields: BTreeMap<u16, Field>,
+}
+
+impl Default for RegisterMap {
+    fn default() -> Self {
+        Self::empty()
+            .with_field(0, Field::Value)
+            .with_field(1, Field::Exponent)
+            .with_field(2, Field::UnitCode)
+            .with_field(3, Field::FunctionCode)
+            .with_field(4, Field::Status)
+            .with_field(5, Field::Range)
+            .with_field(6, Field::FrameCounterHigh)
+            .with_field(7, Field::FrameCounterLow)
+    }
+}
+
+impl RegisterMap {
+    /// The documented default map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A map without fields, to be built with `with_field()`.
+    pub fn empty() -> Self {
+        Self { fields: BTreeMap::new() }
+    }
+
+    /// Place `field` at `address`, replacing the field previously at that address.
+    pub fn with_field(mut self, address: u16, field: Field) -> Self {
+        self.fields.insert(address, field);
+        self
+    }
+
+    /// Move all fields so that the first one is at `address`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a field would be moved beyond address 65535.
+    pub fn with_base(self, address: u16) -> Self {
+        let Some(&first) = self.fields.keys().next() else { return self };
+        let fields = self.fields.into_iter()
+            .map(|(a, f)| (address.checked_add(a - first).expect("register address overflow"), f))
+            .collect();
+        Self { fields }
+    }
+
+    pub fn field(&self, address: u16) -> Option<Field> {
+        self.fields.get(&address).copied()
+    }
+}
+
+fn unit_code(base_unit: BaseUnit) -> u16 {
+    match base_unit {
+        BaseUnit::Volt => 1,
+        BaseUnit::Ampere => 2,
+        BaseUnit::Ohm => 3,
+        BaseUnit::Hearts => 4,
+        BaseUnit::Farad => 5,
+    }
+}
+
+/// Modbus exception codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// Function code other than 3 and 4.
+    IllegalFunction = 1,
+    /// Range outside the register map.
+    IllegalDataAddress = 2,
+    /// Register count is 0 or more than 125.
+    IllegalDataValue = 3,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    latest: Option<Reading>,
+    frames: u32,
+}
+
+/// Registers served to Modbus clients. Clones share the same state, so one clone can be updated by the reader while another is served.
+#[derive(Debug, Clone, Default)]
+pub struct Registers {
+    state: Arc<Mutex<State>>,
+    map: Arc<RegisterMap>,
+}
+
+impl Registers {
+    pub fn new(map: RegisterMap) -> Self {
+        Self { state: Arc::default(), map: Arc::new(map) }
+    }
+
+    pub fn update(&self, reading: &Reading) {
+        let mut state = self.state.lock().unwrap();
+        state.latest = Some(reading.clone());
+        state.frames = state.frames.wrapping_add(1);
+    }
+
+    fn value(state: &State, field: Field) -> u16 {
+        let reading = state.latest.as_ref();
+        let scaled = reading.and_then(|r| {
+            let (value, output_value) = (r.value?, r.output.get_value()?);
+            let decimals = output_value.digits.find('.').map_or(0, |p| output_value.digits.len() - p - 1) as i32;
+            let scaled = (value * 10f64.powi(decimals)).round().clamp(i16::MIN as f64 + 1.0, i16::MAX as f64) as i16;
+            Some((scaled, output_value.value_unit.prefix_unit.exponent() - decimals))
+        });
+        match field {
+            Field::Value => scaled.map_or(NO_VALUE, |(v, _)| v) as u16,
+            Field::Exponent => scaled.map_or(0, |(_, e)| e as i16) as u16,
+            Field::UnitCode => reading.and_then(|r| r.value_unit()).map_or(0, |u| unit_code(u.base_unit)),
+            Field::FunctionCode => reading.map_or(0, |r| r.output.function as u16),
+            Field::Status => match reading {
+                Some(r) => {
+                    let (status, option2) = (&r.output.status, &r.output.option2);
+                    status.is_overflow as u16 | (status.is_battery_depleted as u16) << 1 | (option2.is_auto as u16) << 2
+                        | (option2.is_dc as u16) << 3 | (option2.is_ac as u16) << 4
+                }
+                None => 1 << 15,
+            },
+            Field::Range => reading.map_or(0, |r| r.output.range.index() as u16),
+            Field::FrameCounterHigh => (state.frames >> 16) as u16,
+            Field::FrameCounterLow => state.frames as u16,
+        }
+    }
+
+    /// Read `count` registers from `address`.
+    pub fn read(&self, address: u16, count: u16) -> Result<Vec<u16>, Exception> {
+        if count == 0 || MAX_REGISTERS < count {
+            return Err(Exception::IllegalDataValue);
+        }
+        let (Some((&first, _)), Some((&last, _))) = (self.map.fields.first_key_value(), self.map.fields.last_key_value()) else {
+            return Err(Exception::IllegalDataAddress);
+        };
+        let end = address as u32 + count as u32 - 1;
+        if address < first || end > last as u32 {
+            return Err(Exception::IllegalDataAddress);
+        }
+        let state = self.state.lock().unwrap();
+        Ok((address..=end as u16).map(|a| self.map.field(a).map_or(0, |f| Self::value(&state, f))).collect())
+    }
+
+    /// Response PDU for a request PDU.
+    fn respond(&self, pdu: &[u8]) -> Vec<u8> {
+        let function = pdu.first().copied().unwrap_or(0);
+        let result = match (function, pdu) {
+            (3 | 4, [_, a0, a1, c0, c1]) => self.read(u16::from_be_bytes([*a0, *a1]), u16::from_be_bytes([*c0, *c1])),
+            (3 | 4, _) => Err(Exception::IllegalDataValue),
+            _ => Err(Exception::IllegalFunction),
+        };
+        match result {
+            Ok(registers) => {
+                let mut response = vec![function, (registers.len() * 2) as u8];
+                for r in registers {
+                    response.extend_from_slice(&r.to_be_bytes());
+                }
+                response
+            }
+            Err(e) => vec![function | 0x80, e as u8],
+        }
+    }
+}
+
+fn handle(stream: TcpStream, registers: &Registers) -> io::Result<()> {
+    let mut stream = stream;
+    loop {
+        let mut header = [0u8; 7];
+        match stream.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            r => r?,
+        }
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if header[2..4] != [0, 0] || !(2..=254).contains(&length) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid MBAP header"));
+        }
+        let mut pdu = vec![0u8; length - 1];
+        stream.read_exact(&mut pdu)?;
+        let response = registers.respond(&pdu);
+        let mut frame = Vec::with_capacity(7 + response.len());
+        frame.extend_from_slice(&header[0..4]);
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend_from_slice(&response);
+        stream.write_all(&frame)?;
+    }
+}
+
+/// Serve `registers` over Modbus TCP in a background thread. Any unit id is accepted.
+///
+/// ```no_run
+/// use es51986::modbus::{serve, RegisterMap, Registers};
+///
+/// let registers = Registers::new(RegisterMap::new().with_base(1000));
+/// let server = serve("0.0.0.0:1502", registers.clone()).unwrap();
+/// // Feed readings with registers.update(&reading) ...
+/// ```
+pub fn serve<A: ToSocketAddrs>(addr: A, registers: Registers) -> io::Result<Server> {
+    Server::spawn(addr, move |stream| {
+        let _ = handle(stream, &registers);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn registers() {
+        let registers = Registers::new(RegisterMap::new());
+        assert_eq!(registers.read(0, 8).unwrap(), vec![0x8000, 0, 0, 0, 0x8000, 0, 0, 0]);
+
+        registers.update(&reading("00002?<0:"));
+        // -0.02 mA = -2 * 10^-5 A, DC and auto.
+        assert_eq!(registers.read(0, 8).unwrap(), vec![-2i16 as u16, -5i16 as u16, 2, 2, 0b01100, 0, 0, 1]);
+        registers.update(&reading("560003902"));
+        assert_eq!(registers.read(0, 6).unwrap(), vec![0x8000, 0, 3, 5, 0b00101, 5]);
+
+        assert_eq!(registers.read(7, 2), Err(Exception::IllegalDataAddress));
+        assert_eq!(registers.read(0, 0), Err(Exception::IllegalDataValue));
+
+        let registers = Registers::new(RegisterMap::empty().with_field(0, Field::FrameCounterLow).with_field(3, Field::Range).with_base(100));
+        registers.update(&reading("20989;806"));
+        assert_eq!(registers.read(100, 4).unwrap(), vec![1, 0, 0, 2]);
+        assert_eq!(registers.read(99, 1), Err(Exception::IllegalDataAddress));
+    }
+
+    #[test]
+    fn tcp_client() {
+        let registers = Registers::new(RegisterMap::new());
+        registers.update(&reading("20989;806"));
+        let server = serve("127.0.0.1:0", registers.clone()).unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        let mut request = |pdu: &[u8]| {
+            let mut frame = vec![0x12, 0x34, 0, 0];
+            frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+            frame.push(7);
+            frame.extend_from_slice(pdu);
+            stream.write_all(&frame).unwrap();
+            let mut header = [0u8; 7];
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(header[0..4], [0x12, 0x34, 0, 0]);
+            assert_eq!(header[6], 7);
+            let mut response = vec![0u8; u16::from_be_bytes([header[4], header[5]]) as usize - 1];
+            stream.read_exact(&mut response).unwrap();
+            response
+        };
+        // 98.9 V AC = 989 * 10^-1 V.
+        assert_eq!(request(&[4, 0, 0, 0, 3]), vec![4, 6, 0x03, 0xdd, 0xff, 0xff, 0, 1]);
+        assert_eq!(request(&[3, 0, 5, 0, 1]), vec![3, 2, 0, 2]);
+        assert_eq!(request(&[6, 0, 0, 0, 1]), vec![0x86, 1]);
+        assert_eq!(request(&[4, 0, 8, 0, 1]), vec![0x84, 2]);
+    }
+}
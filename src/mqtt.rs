@@ -0,0 +1,588 @@
+//! MQTT publisher with optional Home Assistant discovery.
+//!
+//! A minimal MQTT 3.1.1 client (QoS 0 only) publishes each reading as a `jsonl::Record` to `<prefix>/<meter>/state`.
+//! With availability enabled, `online` is published to `<prefix>/<meter>/availability` on connect and `offline` is left as the will message, both retained.
+//! With discovery enabled, a sensor config is published to `<discovery prefix>/sensor/<meter>/value/config` so the meter appears in Home Assistant automatically.
+//! The config is published again when the measured quantity changes, because Home Assistant needs a fixed unit per sensor.
+//! When readings may stop for longer than the keep-alive interval, call `MqttPublisher::keep_alive()` regularly so the broker keeps the connection.
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use crate::{BaseUnit, http, jsonl::Record, reading::Reading, sink::Sink};
+
+fn encode_remaining_length(buf: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut b = (len % 128) as u8;
+        len /= 128;
+        if len != 0 {
+            b |= 0x80;
+        }
+        buf.push(b);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_string(buf: &mut Vec<u8>, s: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(s.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "MQTT string is longer than 65535 bytes"))?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(s);
+    Ok(())
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![header];
+    encode_remaining_length(&mut buf, body.len());
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> io::Result<Vec<u8>> {
+    let mut body = vec![];
+    encode_string(&mut body, topic.as_bytes())?;
+    body.extend_from_slice(payload);
+    Ok(packet(0x30 | retain as u8, &body))
+}
+
+/// Longest wait between connection attempts after repeated failures, unless the reconnect interval is longer.
+const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(300);
+
+const PINGREQ: [u8; 2] = [0xc0, 0x00];
+const DISCONNECT: [u8; 2] = [0xe0, 0x00];
+
+/// Home Assistant device class, if there is one for the unit.
+fn device_class(base_unit: BaseUnit) -> Option<&'static str> {
+    match base_unit {
+        BaseUnit::Volt => Some("voltage"),
+        BaseUnit::Ampere => Some("current"),
+        BaseUnit::Hearts => Some("frequency"),
+        BaseUnit::Ohm | BaseUnit::Farad => None,
+    }
+}
+
+/// Publishes readings to an MQTT broker. Reconnects on the next write after the connection is lost.
+///
+/// ```no_run
+/// use es51986::mqtt::MqttPublisher;
+///
+/// let publisher = MqttPublisher::new("localhost", 1883, "bench")
+///     .with_prefix("lab/meters")
+///     .with_retain(true)
+///     .with_availability(true)
+///     .with_discovery("homeassistant");
+/// // publisher.write(&reading) ...
+/// ```
+pub struct MqttPublisher {
+    host: String,
+    port: u16,
+    meter: String,
+    client_id: String,
+    credentials: Option<(String, String)>,
+    keep_alive: Duration,
+    prefix: String,
+    retain: bool,
+    availability: bool,
+    discovery_prefix: Option<String>,
+    reconnect_interval: Duration,
+    timeout: Duration,
+
+    stream: Option<TcpStream>,
+    last_attempt: Option<Instant>,
+    /// Connection attempts failed in a row.
+    failures: u32,
+    last_sent: Instant,
+    discovered: Option<BaseUnit>,
+}
+
+impl MqttPublisher {
+    pub const DEFAULT_PREFIX: &'static str = "es51986";
+
+    pub fn new(host: &str, port: u16, meter: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            meter: meter.to_owned(),
+            client_id: format!("es51986-{}", meter),
+            credentials: None,
+            keep_alive: Duration::from_secs(60),
+            prefix: Self::DEFAULT_PREFIX.to_owned(),
+            retain: false,
+            availability: false,
+            discovery_prefix: None,
+            reconnect_interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(10),
+            stream: None,
+            last_attempt: None,
+            failures: 0,
+            last_sent: Instant::now(),
+            discovered: None,
+        }
+    }
+
+    pub fn with_client_id(mut self, client_id: &str) -> Self {
+        self.client_id = client_id.to_owned();
+        self
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// Keep-alive interval told to the broker. Default is 60 seconds. Zero turns keep-alive off.
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Topic prefix. Default is "es51986".
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Publish the state retained, so new subscribers get the last value immediately.
+    pub fn with_retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    pub fn with_availability(mut self, availability: bool) -> Self {
+        self.availability = availability;
+        self
+    }
+
+    /// Publish Home Assistant discovery config under `prefix` (usually "homeassistant").
+    pub fn with_discovery(mut self, prefix: &str) -> Self {
+        self.discovery_prefix = Some(prefix.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Minimum interval between connection attempts. It doubles after each failed attempt, up to 5 minutes.
+    pub fn with_reconnect_interval(mut self, interval: Duration) -> Self {
+        self.reconnect_interval = interval;
+        self
+    }
+
+    /// Timeout of connecting, of waiting for CONNACK and of each write. Default is 10 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Wait before the next connection attempt.
+    fn retry_delay(&self) -> Duration {
+        let doubled = self.reconnect_interval.saturating_mul(1 << self.failures.saturating_sub(1).min(16));
+        doubled.min(self.reconnect_interval.max(MAX_RECONNECT_INTERVAL))
+    }
+
+    /// Topic levels must not contain wildcards, and the meter id is a single level.
+    fn check_topics(&self) -> io::Result<()> {
+        if self.meter.is_empty() || self.meter.contains(['/', '+', '#']) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("MQTT meter id '{}' must not be empty or contain '/', '+' or '#'", self.meter)));
+        }
+        for prefix in [Some(&self.prefix), self.discovery_prefix.as_ref()].into_iter().flatten() {
+            if prefix.contains(['+', '#']) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("MQTT topic prefix '{}' must not contain '+' or '#'", prefix)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn state_topic(&self) -> String {
+        format!("{}/{}/state", self.prefix, self.meter)
+    }
+
+    pub fn availability_topic(&self) -> String {
+        format!("{}/{}/availability", self.prefix, self.meter)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn connect_packet(&self) -> io::Result<Vec<u8>> {
+        let mut body = vec![];
+        encode_string(&mut body, b"MQTT")?;
+        body.push(4);
+        let mut flags = 0x02; // Clean session
+        if self.availability {
+            flags |= 0x04 | 0x20; // Will, will retain
+        }
+        if self.credentials.is_some() {
+            flags |= 0x80 | 0x40;
+        }
+        body.push(flags);
+        body.extend_from_slice(&(self.keep_alive.as_secs().min(u16::MAX as u64) as u16).to_be_bytes());
+        encode_string(&mut body, self.client_id.as_bytes())?;
+        if self.availability {
+            encode_string(&mut body, self.availability_topic().as_bytes())?;
+            encode_string(&mut body, b"offline")?;
+        }
+        if let Some((username, password)) = &self.credentials {
+            encode_string(&mut body, username.as_bytes())?;
+            encode_string(&mut body, password.as_bytes())?;
+        }
+        Ok(packet(0x10, &body))
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        self.last_attempt = Some(Instant::now());
+        self.check_topics()?;
+        let mut stream = http::connect(&self.host, self.port, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(&self.connect_packet()?)?;
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[1] != 0x02 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected reply to MQTT CONNECT"));
+        }
+        if connack[3] != 0 {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("MQTT connection refused with code {}", connack[3])));
+        }
+        if self.availability {
+            stream.write_all(&publish_packet(&self.availability_topic(), b"online", true)?)?;
+        }
+        self.stream = Some(stream);
+        self.last_sent = Instant::now();
+        // Discovery config has to be published again for a broker that may have lost it.
+        self.discovered = None;
+        Ok(())
+    }
+
+    fn discovery_config(&self, base_unit: BaseUnit) -> (String, String) {
+        let prefix = self.discovery_prefix.as_deref().unwrap_or("homeassistant");
+        let topic = format!("{}/sensor/{}/value/config", prefix, self.meter);
+        let mut config = serde_json::json!({
+            "name": format!("ES51986 {}", self.meter),
+            "unique_id": format!("es51986_{}_value", self.meter),
+            "state_topic": self.state_topic(),
+            "value_template": "{{ value_json.value * 10 ** value_json.exponent if value_json.value is not none else none }}",
+            "unit_of_measurement": base_unit.symbol(),
+            "state_class": "measurement",
+            "device": {
+                "identifiers": [format!("es51986_{}", self.meter)],
+                "name": format!("ES51986 {}", self.meter),
+                "model": "ES51986",
+            },
+        });
+        if let Some(class) = device_class(base_unit) {
+            config["device_class"] = class.into();
+        }
+        if self.availability {
+            config["availability_topic"] = self.availability_topic().into();
+        }
+        (topic, config.to_string())
+    }
+
+    fn publish(&mut self, reading: &Reading) -> io::Result<()> {
+        let mut packets = vec![];
+        if self.discovery_prefix.is_some() {
+            if let Some(unit) = reading.value_unit() {
+                if self.discovered != Some(unit.base_unit) {
+                    let (topic, config) = self.discovery_config(unit.base_unit);
+                    packets.push(publish_packet(&topic, config.as_bytes(), true)?);
+                }
+            }
+        }
+        let record = Record::new(reading, Some(&self.meter));
+        packets.push(publish_packet(&self.state_topic(), record.to_json().as_bytes(), self.retain)?);
+        let stream = self.stream.as_mut().ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        for p in packets {
+            stream.write_all(&p)?;
+        }
+        self.last_sent = Instant::now();
+        if let Some(unit) = reading.value_unit() {
+            self.discovered = Some(unit.base_unit);
+        }
+        Ok(())
+    }
+
+    /// Discard what the broker sent (PINGRESP) and fail if it closed the connection.
+    fn poll(&mut self) -> io::Result<()> {
+        let Some(stream) = self.stream.as_mut() else { return Ok(()) };
+        stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 64];
+        let result = loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break Err(io::Error::new(io::ErrorKind::ConnectionAborted, "MQTT broker closed the connection")),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        result
+    }
+
+    /// Send PINGREQ when nothing has been sent for half the keep-alive interval.
+    ///
+    /// A broker drops a client that is silent for longer than the interval, so call this regularly when readings may stop.
+    /// A lost connection is dropped here and reestablished on the next write.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        if self.stream.is_none() || self.keep_alive.is_zero() || self.last_sent.elapsed() < self.keep_alive / 2 {
+            return Ok(());
+        }
+        let result = self.poll().and_then(|_| self.stream.as_mut().unwrap().write_all(&PINGREQ));
+        match result {
+            Ok(()) => self.last_sent = Instant::now(),
+            Err(_) => self.stream = None,
+        }
+        result
+    }
+
+    /// Publish `offline` (if availability is enabled) and disconnect.
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            if self.availability {
+                stream.write_all(&publish_packet(&self.availability_topic(), b"offline", true)?)?;
+            }
+            stream.write_all(&DISCONNECT)?;
+            stream.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for MqttPublisher {
+    /// Connects if needed. While the broker is unreachable, connection attempts are limited to one per (growing) reconnect interval and the readings in between fail with `NotConnected`.
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        // Writing to a connection the broker closed would lose the reading silently.
+        if self.poll().is_err() {
+            self.stream = None;
+        }
+        if self.stream.is_none() {
+            if self.last_attempt.is_some_and(|t| t.elapsed() < self.retry_delay()) {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            if let Err(e) = self.connect() {
+                self.failures = self.failures.saturating_add(1);
+                return Err(e);
+            }
+            self.failures = 0;
+        }
+        let result = self.publish(reading);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MqttPublisher {
+    fn drop(&mut self) {
+        let _ = self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::mpsc, thread, time::SystemTime};
+
+    use crate::Output;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Packet {
+        Connect { client_id: String, will_topic: Option<String> },
+        Publish { topic: String, payload: String, retain: bool },
+        Pingreq,
+        Disconnect,
+    }
+
+    fn read_string(body: &[u8], pos: &mut usize) -> String {
+        let len = u16::from_be_bytes([body[*pos], body[*pos + 1]]) as usize;
+        let s = String::from_utf8(body[*pos + 2..*pos + 2 + len].to_vec()).unwrap();
+        *pos += 2 + len;
+        s
+    }
+
+    fn read_packet(stream: &mut TcpStream) -> Option<Packet> {
+        let mut header = [0u8];
+        if stream.read(&mut header).ok()? == 0 {
+            return None;
+        }
+        let (mut len, mut shift) = (0usize, 0);
+        loop {
+            let mut b = [0u8];
+            stream.read_exact(&mut b).unwrap();
+            len |= ((b[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if b[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).unwrap();
+        let mut pos = 0;
+        match header[0] & 0xf0 {
+            0x10 => {
+                read_string(&body, &mut pos);
+                let flags = body[pos + 1];
+                pos += 4;
+                let client_id = read_string(&body, &mut pos);
+                let will_topic = (flags & 0x04 != 0).then(|| read_string(&body, &mut pos));
+                Some(Packet::Connect { client_id, will_topic })
+            }
+            0x30 => {
+                let topic = read_string(&body, &mut pos);
+                Some(Packet::Publish { topic, payload: String::from_utf8(body[pos..].to_vec()).unwrap(), retain: header[0] & 1 != 0 })
+            }
+            0xc0 => Some(Packet::Pingreq),
+            0xe0 => Some(Packet::Disconnect),
+            other => panic!("unexpected packet {:x}", other),
+        }
+    }
+
+    fn broker() -> (u16, mpsc::Receiver<Packet>) {
+        broker_closing_after(usize::MAX)
+    }
+
+    /// Broker stand-in: accepts connections, acknowledges CONNECT and PINGREQ and forwards the packets.
+    /// The first connection is closed after `count` packets.
+    fn broker_closing_after(count: usize) -> (u16, mpsc::Receiver<Packet>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut count = count;
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                while let Some(p) = read_packet(&mut stream) {
+                    match p {
+                        Packet::Connect { .. } => stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap(),
+                        Packet::Pingreq => stream.write_all(&[0xd0, 0x00]).unwrap(),
+                        _ => {}
+                    }
+                    let done = p == Packet::Disconnect;
+                    tx.send(p).unwrap();
+                    count -= 1;
+                    if done || count == 0 {
+                        count = usize::MAX;
+                        break;
+                    }
+                }
+            }
+        });
+        (port, rx)
+    }
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    fn recv(rx: &mpsc::Receiver<Packet>) -> Packet {
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn publish_with_discovery() {
+        let (port, rx) = broker();
+        let mut publisher = MqttPublisher::new("127.0.0.1", port, "bench")
+            .with_prefix("lab/").with_retain(true).with_availability(true).with_discovery("homeassistant");
+        publisher.write(&reading("00002?<0:")).unwrap();
+        publisher.write(&reading("00003?<0:")).unwrap();
+        drop(publisher);
+
+        assert_eq!(recv(&rx), Packet::Connect { client_id: "es51986-bench".to_owned(), will_topic: Some("lab/bench/availability".to_owned()) });
+        assert_eq!(recv(&rx), Packet::Publish { topic: "lab/bench/availability".to_owned(), payload: "online".to_owned(), retain: true });
+        let Packet::Publish { topic, payload, retain } = recv(&rx) else { panic!() };
+        assert_eq!(topic, "homeassistant/sensor/bench/value/config");
+        assert!(retain);
+        let config: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(config["state_topic"], "lab/bench/state");
+        assert_eq!(config["unit_of_measurement"], "A");
+        assert_eq!(config["device_class"], "current");
+        let Packet::Publish { topic, payload, retain } = recv(&rx) else { panic!() };
+        assert_eq!(topic, "lab/bench/state");
+        assert!(retain);
+        let record: Record = serde_json::from_str(&payload).unwrap();
+        assert_eq!(record.value, Some(-0.02));
+        assert_eq!(record.meter.as_deref(), Some("bench"));
+        // Discovery is not repeated for the same unit.
+        let Packet::Publish { topic, .. } = recv(&rx) else { panic!() };
+        assert_eq!(topic, "lab/bench/state");
+        assert_eq!(recv(&rx), Packet::Publish { topic: "lab/bench/availability".to_owned(), payload: "offline".to_owned(), retain: true });
+        assert_eq!(recv(&rx), Packet::Disconnect);
+    }
+
+    #[test]
+    fn reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let mut publisher = MqttPublisher::new("127.0.0.1", port, "bench").with_reconnect_interval(Duration::from_secs(3600));
+        assert!(publisher.write(&reading("00002?<0:")).is_err());
+        // Within the reconnect interval no connection is attempted.
+        assert_eq!(publisher.write(&reading("00002?<0:")).unwrap_err().kind(), io::ErrorKind::NotConnected);
+        assert!(!publisher.is_connected());
+
+        let (port, rx) = broker();
+        let mut publisher = MqttPublisher::new("127.0.0.1", port, "bench").with_reconnect_interval(Duration::ZERO);
+        publisher.write(&reading("00002?<0:")).unwrap();
+        assert!(matches!(recv(&rx), Packet::Connect { will_topic: None, .. }));
+        assert!(matches!(recv(&rx), Packet::Publish { retain: false, .. }));
+    }
+
+    #[test]
+    fn backoff_and_topic_checks() {
+        let mut publisher = MqttPublisher::new("127.0.0.1", 1, "bench").with_reconnect_interval(Duration::from_secs(5));
+        let delays: Vec<u64> = [0, 1, 3, 10].iter().map(|&f| {
+            publisher.failures = f;
+            publisher.retry_delay().as_secs()
+        }).collect();
+        assert_eq!(delays, [5, 5, 20, 300]);
+
+        let mut publisher = MqttPublisher::new("127.0.0.1", 1, "a/b");
+        assert_eq!(publisher.write(&reading("00002?<0:")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let mut publisher = MqttPublisher::new("127.0.0.1", 1, "bench").with_prefix("lab/+");
+        assert_eq!(publisher.write(&reading("00002?<0:")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reconnect_after_broker_closed() {
+        let (port, rx) = broker_closing_after(2);
+        let mut publisher = MqttPublisher::new("127.0.0.1", port, "bench").with_reconnect_interval(Duration::ZERO);
+        publisher.write(&reading("00002?<0:")).unwrap();
+        assert!(matches!(recv(&rx), Packet::Connect { .. }));
+        assert!(matches!(recv(&rx), Packet::Publish { .. }));
+        // Let the close reach the publisher.
+        thread::sleep(Duration::from_millis(200));
+        publisher.write(&reading("00003?<0:")).unwrap();
+        assert!(matches!(recv(&rx), Packet::Connect { .. }));
+        let Packet::Publish { payload, .. } = recv(&rx) else { panic!() };
+        assert_eq!(serde_json::from_str::<Record>(&payload).unwrap().value, Some(-0.03));
+    }
+
+    #[test]
+    fn keep_alive() {
+        let (port, rx) = broker();
+        let mut publisher = MqttPublisher::new("127.0.0.1", port, "bench").with_keep_alive(Duration::from_millis(20));
+        publisher.write(&reading("00002?<0:")).unwrap();
+        publisher.keep_alive().unwrap();
+        thread::sleep(Duration::from_millis(20));
+        publisher.keep_alive().unwrap();
+        thread::sleep(Duration::from_millis(20));
+        publisher.keep_alive().unwrap();
+        assert!(matches!(recv(&rx), Packet::Connect { .. }));
+        assert!(matches!(recv(&rx), Packet::Publish { .. }));
+        assert_eq!(recv(&rx), Packet::Pingreq);
+        assert_eq!(recv(&rx), Packet::Pingreq);
+        assert!(publisher.is_connected());
+        assert!(publish_packet(&"a".repeat(65_536), b"", false).is_err());
+    }
+}
@@ -36,6 +36,7 @@ pub struct Parser {
 }
 
 impl Parser {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
             state: ParserState::Idle,
@@ -43,6 +44,7 @@ impl Parser {
         }
     }
     
+    #[allow(clippy::redundant_closure)]
     fn parse_ch(&mut self, ch: u8) -> Result<Option<Output>, ParseError> {
         match self.state {
             ParserState::Idle => {
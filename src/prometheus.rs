@@ -0,0 +1,215 @@
+//! Prometheus exporter for live readings.
+//!
+//! `serve()` exposes the metrics at `/metrics` in the Prometheus text format:
+//!
+//! ```text
+//! es51986_voltage_volts{meter="bench",function="voltage",coupling="DC",unit="V"} 1.234
+//! es51986_frames_total{meter="bench"} 120
+//! es51986_parse_errors_total{meter="bench",kind="invalid_digit"} 1
+//! es51986_overflow_frames_total{meter="bench"} 3
+//! es51986_battery_low{meter="bench"} 0
+//! ```
+//!
+//! Only the quantity of the latest reading has a gauge. Overflow frames remove the gauge until the next valid reading.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+};
+
+use crate::{BaseUnit, http, parser::ParseError, reading::Reading, server::Server, timestamp};
+
+const PARSE_ERROR_KINDS: [&str; 4] = ["length_error", "invalid_range", "invalid_function", "invalid_digit"];
+
+fn parse_error_kind(err: &ParseError) -> &'static str {
+    match err {
+        ParseError::LengthError { .. } => PARSE_ERROR_KINDS[0],
+        ParseError::InvalidRange(_) => PARSE_ERROR_KINDS[1],
+        ParseError::InvalidFunction(_) => PARSE_ERROR_KINDS[2],
+        ParseError::InvalidDigit(_) => PARSE_ERROR_KINDS[3],
+    }
+}
+
+fn gauge_name(base_unit: BaseUnit) -> &'static str {
+    match base_unit {
+        BaseUnit::Volt => "es51986_voltage_volts",
+        BaseUnit::Ampere => "es51986_current_amperes",
+        BaseUnit::Ohm => "es51986_resistance_ohms",
+        BaseUnit::Hearts => "es51986_frequency_hertz",
+        BaseUnit::Farad => "es51986_capacitance_farads",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[derive(Debug, Default)]
+struct MeterState {
+    latest: Option<Reading>,
+    frames: u64,
+    overflow_frames: u64,
+    parse_errors: BTreeMap<&'static str, u64>,
+}
+
+/// Metrics of one or more meters. Clones share the same state, so one clone can be updated by the reader while another is served.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    meters: Arc<Mutex<BTreeMap<String, MeterState>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_reading(&self, meter: &str, reading: &Reading) {
+        let mut meters = self.meters.lock().unwrap();
+        let state = meters.entry(meter.to_owned()).or_default();
+        state.frames += 1;
+        if reading.output.status.is_overflow {
+            state.overflow_frames += 1;
+        }
+        state.latest = Some(reading.clone());
+    }
+
+    pub fn record_error(&self, meter: &str, err: &ParseError) {
+        let mut meters = self.meters.lock().unwrap();
+        *meters.entry(meter.to_owned()).or_default().parse_errors.entry(parse_error_kind(err)).or_insert(0) += 1;
+    }
+
+    /// Metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let meters = self.meters.lock().unwrap();
+        let mut gauges: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (meter, state) in meters.iter() {
+            let Some(reading) = &state.latest else { continue };
+            let (Some(value), Some(unit)) = (reading.si_value(), reading.value_unit()) else { continue };
+            gauges.entry(gauge_name(unit.base_unit)).or_default().push(format!(
+                "{{meter=\"{}\",function=\"{}\",coupling=\"{}\",unit=\"{}\"}} {}",
+                escape(meter), reading.output.function.name(), reading.output.option2.coupling(), unit.base_unit.symbol(), value
+            ));
+        }
+
+        let mut out = String::new();
+        for (name, samples) in gauges {
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for sample in samples {
+                let _ = writeln!(out, "{}{}", name, sample);
+            }
+        }
+        let mut family = |name: &str, kind: &str, help: &str, sample: &dyn Fn(&str, &MeterState) -> Vec<String>| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (meter, state) in meters.iter() {
+                for line in sample(&escape(meter), state) {
+                    let _ = writeln!(out, "{}{}", name, line);
+                }
+            }
+        };
+        family("es51986_frames_total", "counter", "Frames decoded.", &|m, s| vec![format!("{{meter=\"{}\"}} {}", m, s.frames)]);
+        family("es51986_parse_errors_total", "counter", "Frames that failed to decode.", &|m, s| {
+            PARSE_ERROR_KINDS.iter().map(|kind| {
+                format!("{{meter=\"{}\",kind=\"{}\"}} {}", m, kind, s.parse_errors.get(kind).copied().unwrap_or(0))
+            }).collect()
+        });
+        family("es51986_overflow_frames_total", "counter", "Overflow (OL) frames.", &|m, s| {
+            vec![format!("{{meter=\"{}\"}} {}", m, s.overflow_frames)]
+        });
+        family("es51986_battery_low", "gauge", "1 if the latest frame indicates low battery.", &|m, s| {
+            let low = s.latest.as_ref().is_some_and(|r| r.output.status.is_battery_depleted);
+            vec![format!("{{meter=\"{}\"}} {}", m, low as u8)]
+        });
+        family("es51986_last_frame_timestamp_seconds", "gauge", "Arrival time of the latest frame.", &|m, s| {
+            s.latest.iter().map(|r| {
+                format!("{{meter=\"{}\"}} {}", m, timestamp::unix_nanos(r.timestamp) as f64 / 1e9)
+            }).collect()
+        });
+        out
+    }
+}
+
+fn handle(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(http::REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = http::read_request(&mut reader)?;
+    let mut stream = stream;
+    match (request.method.as_str(), request.route()) {
+        ("GET", "/metrics") => {
+            http::write_response(&mut stream, 200, "text/plain; version=0.0.4; charset=utf-8", metrics.render().as_bytes())
+        }
+        (_, "/metrics") => http::write_response(&mut stream, 405, "text/plain", b"method not allowed\n"),
+        _ => http::write_response(&mut stream, 404, "text/plain", b"not found\n"),
+    }
+}
+
+/// Serve `metrics` at `http://<addr>/metrics` in a background thread.
+///
+/// ```no_run
+/// use es51986::prometheus::{serve, Metrics};
+///
+/// let metrics = Metrics::new();
+/// let server = serve("0.0.0.0:9186", metrics.clone()).unwrap();
+/// // Feed readings with metrics.record_reading("bench", &reading) ...
+/// ```
+pub fn serve<A: ToSocketAddrs>(addr: A, metrics: Metrics) -> io::Result<Server> {
+    Server::spawn(addr, move |stream| {
+        let _ = handle(stream, &metrics);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, time::{Duration, SystemTime}};
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_secs(10), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.record_reading("bench", &reading("00002?<0:"));
+        metrics.record_reading("bench", &reading("560003902"));
+        metrics.record_reading("psu", &reading("20989;806"));
+        metrics.record_error("bench", &ParseError::InvalidDigit(b'x'));
+        let text = metrics.render();
+        assert!(text.contains("es51986_voltage_volts{meter=\"psu\",function=\"voltage\",coupling=\"AC\",unit=\"V\"} 98.9\n"));
+        // Overflow frame removes the resistance gauge.
+        assert!(!text.contains("es51986_resistance_ohms"));
+        assert!(!text.contains("es51986_current_amperes"));
+        assert!(text.contains("es51986_frames_total{meter=\"bench\"} 2\n"));
+        assert!(text.contains("es51986_parse_errors_total{meter=\"bench\",kind=\"invalid_digit\"} 1\n"));
+        assert!(text.contains("es51986_parse_errors_total{meter=\"psu\",kind=\"invalid_digit\"} 0\n"));
+        assert!(text.contains("es51986_overflow_frames_total{meter=\"bench\"} 1\n"));
+        assert!(text.contains("es51986_battery_low{meter=\"bench\"} 0\n"));
+        assert!(text.contains("es51986_last_frame_timestamp_seconds{meter=\"psu\"} 10\n"));
+    }
+
+    #[test]
+    fn http() {
+        let metrics = Metrics::new();
+        metrics.record_reading("bench", &reading("00002?<0:"));
+        let server = serve("127.0.0.1:0", metrics.clone()).unwrap();
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("es51986_current_amperes{meter=\"bench\",function=\"milli_ampere\",coupling=\"DC\",unit=\"A\"} -0.00002\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+        server.shutdown();
+    }
+}
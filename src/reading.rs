@@ -0,0 +1,107 @@
+use std::{fmt, time::SystemTime};
+
+use crate::{Function, Output, Range, ValueUnit};
+
+/// A decoded frame together with its arrival time and numeric value.
+///
+/// `value` starts as `Output::get_numeric_value()` and may be replaced by filters, while `output` keeps the original frame so that sinks can still report function, range, flags and so on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub timestamp: SystemTime,
+    pub output: Output,
+    pub value: Option<f64>,
+}
+
+impl Reading {
+    pub fn new(timestamp: SystemTime, output: Output) -> Self {
+        let value = output.get_numeric_value();
+        Self { timestamp, output, value }
+    }
+
+    /// Creates a reading stamped with the current time.
+    pub fn now(output: Output) -> Self {
+        Self::new(SystemTime::now(), output)
+    }
+
+    pub fn value_unit(&self) -> Option<ValueUnit> {
+        self.output.get_value().map(|v| v.value_unit)
+    }
+
+    /// Value converted to the base unit (V, A, Ohm, Hz or F).
+    pub fn si_value(&self) -> Option<f64> {
+        let value = self.value?;
+        let unit = self.value_unit()?;
+        Some(value * 10f64.powi(unit.prefix_unit.exponent()))
+    }
+
+    /// `value` formatted with the resolution of the meter (the number of decimals of `get_value()`).
+    pub fn format_value(&self) -> Option<String> {
+        let value = self.value?;
+        let digits = self.output.get_value()?.digits;
+        let decimals = digits.find('.').map_or(0, |p| digits.len() - p - 1);
+        Some(format!("{:.*}", decimals, value))
+    }
+
+    pub fn key(&self) -> MeasurementKey {
+        MeasurementKey::of(&self.output)
+    }
+}
+
+/// Human readable form such as "-12.34 mV DC AUTO".
+///
+/// The value is "OL" for overflow frames and "----" when there is no value. Functions without a unit show their name instead, and "BAT" is appended when the battery is low.
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let output = &self.output;
+        let value = if output.status.is_overflow { "OL".to_owned() } else { self.format_value().unwrap_or_else(|| "----".to_owned()) };
+        let unit = self.value_unit().map_or_else(|| output.function.name().to_owned(), |u| u.symbol());
+        write!(f, "{} {}", value, unit)?;
+        let coupling = output.option2.coupling();
+        if !coupling.is_empty() {
+            write!(f, " {}", coupling)?;
+        }
+        if output.option2.is_auto {
+            write!(f, " AUTO")?;
+        }
+        if output.status.is_battery_depleted {
+            write!(f, " BAT")?;
+        }
+        Ok(())
+    }
+}
+
+/// Identifies what is being measured. Values with different keys must not be mixed in statistics or filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeasurementKey {
+    pub function: Function,
+    pub range: Range,
+    pub value_unit: Option<ValueUnit>,
+}
+
+impl MeasurementKey {
+    pub fn of(output: &Output) -> Self {
+        Self {
+            function: output.function,
+            range: output.range,
+            value_unit: output.get_value().map(|v| v.value_unit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(reading("00002?<0:").to_string(), "-0.02 mA DC AUTO");
+        assert_eq!(reading("20989;806").to_string(), "98.9 V AC AUTO");
+        assert_eq!(reading("560003902").to_string(), "OL MΩ AUTO");
+        assert_eq!(reading("00136>800").to_string(), "---- adp0");
+        assert_eq!(reading("11000;:0:").to_string(), "10.00 V DC AUTO BAT");
+    }
+}
@@ -0,0 +1,293 @@
+use std::{fmt, io::{self, Read, Write}, thread, time::{Duration, Instant}};
+
+use crate::{capture::{CaptureError, CaptureHeader, CaptureReader, Chunk}, parser::Parser, reading::Reading, sink::Sink};
+
+/// Replay pacing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// Original pacing.
+    RealTime,
+    /// N times faster than the original. `Scaled(2.0)` replays a one hour capture in 30 minutes.
+    Scaled(f64),
+    /// No delay between chunks.
+    AsFastAsPossible,
+}
+
+/// Errors of `Replayer`.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The capture could not be read, or `f` of `Replayer::for_each()` failed (as `CaptureError::Io`).
+    Capture(CaptureError),
+    /// `Speed::Scaled` with a factor that is not positive.
+    InvalidSpeed(f64),
+    /// A chunk would be due later than a `Duration` can hold at `Speed::Scaled` with this factor.
+    TooSlow(f64),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Capture(e) => write!(f, "{}", e),
+            Self::InvalidSpeed(speed) => write!(f, "replay speed should be positive but {}", speed),
+            Self::TooSlow(speed) => write!(f, "replay speed {} is too slow", speed),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<CaptureError> for ReplayError {
+    fn from(e: CaptureError) -> Self {
+        Self::Capture(e)
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        Self::Capture(CaptureError::Io(e))
+    }
+}
+
+/// What a replay has done.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub chunks: usize,
+    pub bytes: usize,
+    /// Frames decoded by `Parser`. Only counted by `Replayer::to_sink()`.
+    pub frames: usize,
+    /// Parse errors. Only counted by `Replayer::to_sink()`.
+    pub parse_errors: usize,
+}
+
+/// Reads a recorded capture and delivers its chunks with the original pacing, scaled pacing or as fast as possible.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use es51986::{capture::{CaptureHeader, CaptureWriter, LineSettings}, jsonl::JsonlWriter, replay::{Replayer, Speed}};
+///
+/// let start = SystemTime::UNIX_EPOCH;
+/// let mut writer = CaptureWriter::new(vec![], CaptureHeader::new("test", LineSettings::default(), start)).unwrap();
+/// writer.write_chunk(start + Duration::from_secs(1), b"00000;<0:\r\n").unwrap();
+/// let file = writer.into_inner();
+///
+/// let mut sink = JsonlWriter::new(vec![]);
+/// let stats = Replayer::new(&file[..], Speed::AsFastAsPossible).unwrap().to_sink(&mut sink).unwrap();
+/// assert_eq!(stats.frames, 1);
+/// ```
+pub struct Replayer<R: Read> {
+    reader: CaptureReader<R>,
+    speed: Speed,
+}
+
+impl<R: Read> Replayer<R> {
+    /// Fails with `ReplayError::InvalidSpeed` if the speed is `Speed::Scaled` with a value that is not positive.
+    pub fn new(reader: R, speed: Speed) -> Result<Self, ReplayError> {
+        if let Speed::Scaled(s) = speed {
+            if s.is_nan() || s <= 0.0 {
+                return Err(ReplayError::InvalidSpeed(s));
+            }
+        }
+        Ok(Self { reader: CaptureReader::new(reader)?, speed })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        self.reader.header()
+    }
+
+    /// Call `f` for each chunk at its (scaled) time.
+    pub fn for_each<F>(self, mut f: F) -> Result<ReplayStats, ReplayError>
+        where F: FnMut(&Chunk) -> io::Result<()>
+    {
+        let started = Instant::now();
+        let origin = self.reader.header().start();
+        let speed = self.speed;
+        let mut stats = ReplayStats::default();
+        for chunk in self.reader {
+            let chunk = chunk?;
+            let offset = chunk.timestamp.duration_since(origin).unwrap_or_default();
+            let due = match speed {
+                Speed::RealTime => Some(offset),
+                Speed::Scaled(s) => Some(Duration::try_from_secs_f64(offset.as_secs_f64() / s).map_err(|_| ReplayError::TooSlow(s))?),
+                Speed::AsFastAsPossible => None,
+            };
+            if let Some(wait) = due.and_then(|due| due.checked_sub(started.elapsed())) {
+                thread::sleep(wait);
+            }
+            f(&chunk)?;
+            stats.chunks += 1;
+            stats.bytes += chunk.data.len();
+        }
+        Ok(stats)
+    }
+
+    /// Write the raw bytes to `writer`, e.g. the master side of a `Pty` or a serial port.
+    pub fn to_writer<W: Write>(self, writer: &mut W) -> Result<ReplayStats, ReplayError> {
+        self.for_each(|chunk| {
+            writer.write_all(&chunk.data)?;
+            writer.flush()
+        })
+    }
+
+    /// Decode the chunks with `Parser` and write the readings to `sink`.
+    /// Readings are stamped with the recorded arrival time, not the replay time.
+    pub fn to_sink<S: Sink + ?Sized>(self, sink: &mut S) -> Result<ReplayStats, ReplayError> {
+        let mut parser = Parser::new();
+        let mut frames = 0;
+        let mut parse_errors = 0;
+        let mut stats = self.for_each(|chunk| {
+            for result in parser.parse(&chunk.data) {
+                match result {
+                    Ok(output) => {
+                        frames += 1;
+                        sink.write(&Reading::new(chunk.timestamp, output))?;
+                    }
+                    Err(_) => parse_errors += 1,
+                }
+            }
+            Ok(())
+        })?;
+        sink.flush()?;
+        stats.frames = frames;
+        stats.parse_errors = parse_errors;
+        Ok(stats)
+    }
+}
+
+/// Pseudo-terminal to let third-party software read a replayed capture as if the meter were attached.
+///
+/// Write to the `Pty` and open `slave_path()` from the other program.
+#[cfg(unix)]
+pub struct Pty {
+    master: std::fs::File,
+    slave: std::fs::File,
+    slave_path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl Pty {
+    /// Open a new pseudo-terminal in raw mode.
+    pub fn open() -> io::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+        let mut name = [0 as libc::c_char; 256];
+        // SAFETY: all pointers are valid for the duration of the call, and `name` is large enough for the device name.
+        let rc = unsafe {
+            libc::openpty(&mut master, &mut slave, name.as_mut_ptr(), std::ptr::null(), std::ptr::null())
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openpty() succeeded, so both descriptors are open and owned by nobody else.
+        let (master, slave) = unsafe { (std::fs::File::from_raw_fd(master), std::fs::File::from_raw_fd(slave)) };
+        // SAFETY: openpty() wrote a NUL terminated string into `name`.
+        let slave_path = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned().into();
+        let pty = Self { master, slave, slave_path };
+        pty.make_raw()?;
+        Ok(pty)
+    }
+
+    fn make_raw(&self) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        // SAFETY: termios is plain data, and the descriptor is a valid terminal.
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(self.slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(self.slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Device path to give to the other program, e.g. "/dev/pts/3".
+    pub fn slave_path(&self) -> &std::path::Path {
+        &self.slave_path
+    }
+
+    /// The slave side. Keeping it open prevents writes to the master from failing while no other program has the device open.
+    pub fn slave(&self) -> &std::fs::File {
+        &self.slave
+    }
+}
+
+#[cfg(unix)]
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::capture::{CaptureWriter, LineSettings};
+
+    use super::*;
+
+    fn capture(chunks: &[(u64, &[u8])]) -> Vec<u8> {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut w = CaptureWriter::new(vec![], CaptureHeader::new("test", LineSettings::default(), start)).unwrap();
+        for (millis, data) in chunks {
+            w.write_chunk(start + Duration::from_millis(*millis), data).unwrap();
+        }
+        w.into_inner()
+    }
+
+    #[test]
+    fn pacing() {
+        let file = capture(&[(0, b"0000"), (200, b"0;<0:\r\n")]);
+        let started = Instant::now();
+        let stats = Replayer::new(&file[..], Speed::Scaled(2.0)).unwrap().to_writer(&mut vec![]).unwrap();
+        assert!(Duration::from_millis(100) <= started.elapsed());
+        assert_eq!(stats, ReplayStats { chunks: 2, bytes: 11, frames: 0, parse_errors: 0 });
+        for speed in [0.0, -1.0, f64::NAN] {
+            assert!(matches!(Replayer::new(&file[..], Speed::Scaled(speed)), Err(ReplayError::InvalidSpeed(_))));
+        }
+        let err = Replayer::new(&file[..], Speed::Scaled(1e-300)).unwrap().to_writer(&mut vec![]).unwrap_err();
+        assert!(matches!(err, ReplayError::TooSlow(_)));
+    }
+
+    #[test]
+    fn sink() {
+        struct Collect(Vec<Reading>);
+        impl Sink for Collect {
+            fn write(&mut self, reading: &Reading) -> io::Result<()> {
+                self.0.push(reading.clone());
+                Ok(())
+            }
+        }
+
+        let file = capture(&[(0, b"0000"), (60_000, b"0;<0:\r\n"), (60_500, b"X0000;<0:\r\n")]);
+        let started = Instant::now();
+        let mut sink = Collect(vec![]);
+        let stats = Replayer::new(&file[..], Speed::AsFastAsPossible).unwrap().to_sink(&mut sink).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(stats.frames, 1);
+        assert_eq!(stats.parse_errors, 1);
+        assert_eq!(sink.0[0].timestamp, SystemTime::UNIX_EPOCH + Duration::from_secs(60));
+        assert_eq!(sink.0[0].value, Some(-0.0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pty() {
+        let file = capture(&[(0, b"00000;<0:\r\n")]);
+        let mut pty = Pty::open().unwrap();
+        let mut slave = std::fs::File::open(pty.slave_path()).unwrap();
+        Replayer::new(&file[..], Speed::RealTime).unwrap().to_writer(&mut pty).unwrap();
+        let mut buf = [0u8; 11];
+        slave.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"00000;<0:\r\n");
+    }
+}
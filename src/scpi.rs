@@ -0,0 +1,432 @@
+//! SCPI-over-TCP server so that the meter can stand in for a bench DMM.
+//!
+//! Commands are terminated by a newline, may be chained with `;` and accept the usual short and long forms in any case.
+//!
+//! | Command | Response |
+//! |---|---|
+//! | `*IDN?` | `CYRUSTEK,ES51986,<serial>,<crate version>` |
+//! | `*RST`, `*CLS` | Clear the configured function and the error queue. |
+//! | `*OPC?` | `1` |
+//! | `CONFigure?` | Current function, range and resolution, e.g. `"VOLT:DC +1.000000E+01,+1.000000E-03"` |
+//! | `CONFigure:<function>` | Expect `<function>` for the following `READ?` and `FETCh?`. |
+//! | `MEASure[:<function>]?` | Value of the next reading. |
+//! | `READ?` | Value of the next reading. |
+//! | `FETCh?` | Value of the latest reading. |
+//! | `SYSTem:ERRor?` | Oldest error in the queue, e.g. `-221,"Settings conflict;meter is in RES"`, or `+0,"No error"`. |
+//!
+//! Functions are `VOLTage[:DC]`, `VOLTage:AC`, `CURRent[:DC]`, `CURRent:AC`, `RESistance`, `FREQuency`, `CAPacitance`, `CONTinuity`, `DIODe` and `TEMPerature`.
+//! Values are in V, A, Ω, Hz or F. The meter cannot be configured remotely, so the range and resolution parameters of `CONF` and `MEAS` are ignored.
+//!
+//! Since the frame has four digits, the range reported by `CONF?` is 10000 times the resolution.
+//! Overflow is reported as `+9.900000E+37`. When a query fails, for example `MEAS:VOLT:DC?` while the meter is in Ohm mode, `+9.910000E+37` (not a number) is returned and the error is queued.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use crate::{Function, reading::Reading, server::Server};
+
+/// Port registered for SCPI over raw sockets.
+pub const DEFAULT_PORT: u16 = 5025;
+
+const OVERFLOW: f64 = 9.9e37;
+const NOT_A_NUMBER: f64 = 9.91e37;
+const MAX_ERRORS: usize = 20;
+/// Longest command line accepted. A longer line closes the connection.
+const MAX_LINE_LEN: u64 = 8192;
+
+/// Measurement function in SCPI terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScpiFunction {
+    VoltageDc,
+    VoltageAc,
+    CurrentDc,
+    CurrentAc,
+    Resistance,
+    Frequency,
+    Capacitance,
+    Continuity,
+    Diode,
+    Temperature,
+}
+
+impl ScpiFunction {
+    /// Function of the reading. `None` for the adapter (ADP) functions, which have no SCPI equivalent.
+    pub fn of(reading: &Reading) -> Option<Self> {
+        let ac = reading.output.option2.is_ac && !reading.output.option2.is_dc;
+        match reading.output.function {
+            Function::Voltage => Some(if ac { Self::VoltageAc } else { Self::VoltageDc }),
+            f if f.is_current() => Some(if ac { Self::CurrentAc } else { Self::CurrentDc }),
+            Function::Ohm => Some(Self::Resistance),
+            Function::Frequency => Some(Self::Frequency),
+            Function::Capacitor => Some(Self::Capacitance),
+            Function::Continuity => Some(Self::Continuity),
+            Function::Diode => Some(Self::Diode),
+            Function::Temperature => Some(Self::Temperature),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::VoltageDc => "VOLT:DC",
+            Self::VoltageAc => "VOLT:AC",
+            Self::CurrentDc => "CURR:DC",
+            Self::CurrentAc => "CURR:AC",
+            Self::Resistance => "RES",
+            Self::Frequency => "FREQ",
+            Self::Capacitance => "CAP",
+            Self::Continuity => "CONT",
+            Self::Diode => "DIOD",
+            Self::Temperature => "TEMP",
+        }
+    }
+
+    /// Parse the keywords after `CONF:` or `MEAS:`, e.g. `["VOLTage", "DC"]`.
+    fn parse(keywords: &[&str]) -> Option<Self> {
+        let (first, rest) = keywords.split_first()?;
+        let coupling = match rest {
+            [] => Some(true),
+            [k] if matches(k, "DC") => Some(true),
+            [k] if matches(k, "AC") => Some(false),
+            _ => None,
+        };
+        let plain = |f: Self| rest.is_empty().then_some(f);
+        if matches(first, "VOLTage") {
+            coupling.map(|dc| if dc { Self::VoltageDc } else { Self::VoltageAc })
+        } else if matches(first, "CURRent") {
+            coupling.map(|dc| if dc { Self::CurrentDc } else { Self::CurrentAc })
+        } else if matches(first, "RESistance") {
+            plain(Self::Resistance)
+        } else if matches(first, "FREQuency") {
+            plain(Self::Frequency)
+        } else if matches(first, "CAPacitance") {
+            plain(Self::Capacitance)
+        } else if matches(first, "CONTinuity") {
+            plain(Self::Continuity)
+        } else if matches(first, "DIODe") {
+            plain(Self::Diode)
+        } else if matches(first, "TEMPerature") {
+            plain(Self::Temperature)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `keyword` is the short form (the upper case part) or the long form of `mnemonic`, ignoring case.
+fn matches(keyword: &str, mnemonic: &str) -> bool {
+    let short: String = mnemonic.chars().filter(|c| c.is_ascii_uppercase()).collect();
+    keyword.eq_ignore_ascii_case(&short) || keyword.eq_ignore_ascii_case(mnemonic)
+}
+
+/// SCPI NR3 format such as `+1.234000E-03`.
+fn format_nr3(value: f64) -> String {
+    let s = format!("{:.6E}", value);
+    let (mantissa, exp) = s.split_once('E').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    format!("{}{}E{}{:02}", if value.is_sign_negative() { "" } else { "+" }, mantissa, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+#[derive(Debug, Default)]
+struct State {
+    latest: Option<Reading>,
+    frames: u64,
+}
+
+/// Live state served to SCPI clients. Clones share the same state, so one clone can be updated by the reader while another is served.
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    state: Arc<(Mutex<State>, Condvar)>,
+    serial: String,
+    read_timeout: Duration,
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Self { state: Arc::default(), serial: "0".to_owned(), read_timeout: Duration::from_secs(5) }
+    }
+}
+
+impl Instrument {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serial number field of `*IDN?`. Default is "0".
+    pub fn with_serial(mut self, serial: &str) -> Self {
+        self.serial = serial.to_owned();
+        self
+    }
+
+    /// How long `READ?` and `MEAS?` wait for the next reading. Default is 5 seconds.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn update(&self, reading: &Reading) {
+        let (state, cond) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.latest = Some(reading.clone());
+        state.frames += 1;
+        cond.notify_all();
+    }
+
+    fn latest(&self) -> Option<Reading> {
+        self.state.0.lock().unwrap().latest.clone()
+    }
+
+    /// Wait for a reading that arrives after the call.
+    fn next(&self) -> Option<Reading> {
+        let (state, cond) = &*self.state;
+        let state = state.lock().unwrap();
+        let frames = state.frames;
+        let (state, _) = cond.wait_timeout_while(state, self.read_timeout, |s| s.frames == frames).unwrap();
+        if state.frames == frames { None } else { state.latest.clone() }
+    }
+}
+
+/// Per connection state of the SCPI parser.
+struct Session {
+    instrument: Instrument,
+    configured: Option<ScpiFunction>,
+    errors: VecDeque<(i32, String)>,
+}
+
+impl Session {
+    fn new(instrument: Instrument) -> Self {
+        Self { instrument, configured: None, errors: VecDeque::new() }
+    }
+
+    fn push_error(&mut self, code: i32, message: String) {
+        if self.errors.len() < MAX_ERRORS - 1 {
+            self.errors.push_back((code, message));
+        } else if self.errors.len() == MAX_ERRORS - 1 {
+            self.errors.push_back((-350, "Queue overflow".to_owned()));
+        }
+    }
+
+    /// Value of `reading` if it is measured with `expected` (when given), otherwise NaN with an error queued.
+    fn value(&mut self, reading: Option<Reading>, expected: Option<ScpiFunction>) -> String {
+        let Some(reading) = reading else {
+            self.push_error(-230, "Data corrupt or stale;no reading from the meter".to_owned());
+            return format_nr3(NOT_A_NUMBER);
+        };
+        let actual = ScpiFunction::of(&reading);
+        if let Some(expected) = expected {
+            if actual != Some(expected) {
+                let actual = actual.map_or(reading.output.function.name().to_ascii_uppercase(), |f| f.name().to_owned());
+                self.push_error(-221, format!("Settings conflict;meter is in {}", actual));
+                return format_nr3(NOT_A_NUMBER);
+            }
+        }
+        if reading.output.status.is_overflow {
+            return format_nr3(OVERFLOW);
+        }
+        match reading.si_value() {
+            Some(value) => format_nr3(value),
+            None => {
+                self.push_error(-230, "Data corrupt or stale;no value in this function".to_owned());
+                format_nr3(NOT_A_NUMBER)
+            }
+        }
+    }
+
+    fn configuration(&mut self) -> String {
+        let Some(reading) = self.instrument.latest() else {
+            self.push_error(-230, "Data corrupt or stale;no reading from the meter".to_owned());
+            return "\"NONE\"".to_owned();
+        };
+        let function = ScpiFunction::of(&reading).map_or(reading.output.function.name().to_ascii_uppercase(), |f| f.name().to_owned());
+        let resolution = reading.output.get_value().map(|v| {
+            let decimals = v.digits.find('.').map_or(0, |p| v.digits.len() - p - 1) as i32;
+            10f64.powi(v.value_unit.prefix_unit.exponent() - decimals)
+        });
+        match resolution {
+            Some(resolution) => format!("\"{} {},{}\"", function, format_nr3(resolution * 1e4), format_nr3(resolution)),
+            None => format!("\"{}\"", function),
+        }
+    }
+
+    /// Execute one command. Returns the response of a query.
+    fn execute(&mut self, command: &str) -> Option<String> {
+        let header = command.split_whitespace().next().unwrap_or("");
+        let (header, query) = match header.strip_suffix('?') {
+            Some(h) => (h, true),
+            None => (header, false),
+        };
+        let keywords: Vec<&str> = header.trim_start_matches(':').split(':').collect();
+        match (keywords.as_slice(), query) {
+            ([k], true) if k.eq_ignore_ascii_case("*IDN") => Some(format!("CYRUSTEK,ES51986,{},{}", self.instrument.serial, env!("CARGO_PKG_VERSION"))),
+            ([k], false) if k.eq_ignore_ascii_case("*RST") => {
+                self.configured = None;
+                None
+            }
+            ([k], false) if k.eq_ignore_ascii_case("*CLS") => {
+                self.errors.clear();
+                None
+            }
+            ([k], true) if k.eq_ignore_ascii_case("*OPC") => Some("1".to_owned()),
+            ([k], true) if matches(k, "CONFigure") => Some(self.configuration()),
+            ([k, function @ ..], false) if matches(k, "CONFigure") && !function.is_empty() => {
+                match ScpiFunction::parse(function) {
+                    Some(f) => self.configured = Some(f),
+                    None => self.push_error(-113, format!("Undefined header;{}", command)),
+                }
+                None
+            }
+            ([k, function @ ..], true) if matches(k, "MEASure") => {
+                let expected = if function.is_empty() { None } else { ScpiFunction::parse(function) };
+                if !function.is_empty() && expected.is_none() {
+                    self.push_error(-113, format!("Undefined header;{}", command));
+                    return Some(format_nr3(NOT_A_NUMBER));
+                }
+                let reading = self.instrument.next();
+                Some(self.value(reading, expected))
+            }
+            ([k], true) if matches(k, "READ") => {
+                let reading = self.instrument.next();
+                Some(self.value(reading, self.configured))
+            }
+            ([k], true) if matches(k, "FETCh") => {
+                let reading = self.instrument.latest();
+                Some(self.value(reading, self.configured))
+            }
+            ([s, e], true) if matches(s, "SYSTem") && matches(e, "ERRor") => Some(match self.errors.pop_front() {
+                Some((code, message)) => format!("{},\"{}\"", code, message),
+                None => "+0,\"No error\"".to_owned(),
+            }),
+            _ => {
+                self.push_error(-113, format!("Undefined header;{}", command));
+                None
+            }
+        }
+    }
+
+    /// Execute a line of `;` separated commands and return the joined responses, if any.
+    fn execute_line(&mut self, line: &str) -> Option<String> {
+        let responses: Vec<String> = line.split(';').map(str::trim).filter(|c| !c.is_empty())
+            .filter_map(|c| self.execute(c)).collect();
+        (!responses.is_empty()).then(|| responses.join(";"))
+    }
+}
+
+fn handle(stream: TcpStream, instrument: Instrument) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let mut session = Session::new(instrument);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let len = (&mut reader).take(MAX_LINE_LEN).read_line(&mut line)?;
+        if len == 0 {
+            return Ok(());
+        }
+        if len as u64 == MAX_LINE_LEN && !line.ends_with('\n') {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "SCPI line too long"));
+        }
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        if let Some(response) = session.execute_line(line.strip_suffix('\r').unwrap_or(line)) {
+            writeln!(stream, "{}", response)?;
+        }
+    }
+}
+
+/// Serve SCPI at `addr` (usually port 5025) in a background thread.
+///
+/// ```no_run
+/// use es51986::scpi::{serve, Instrument};
+///
+/// let instrument = Instrument::new();
+/// let server = serve(("0.0.0.0", es51986::scpi::DEFAULT_PORT), instrument.clone()).unwrap();
+/// // Feed readings with instrument.update(&reading) ...
+/// ```
+pub fn serve<A: ToSocketAddrs>(addr: A, instrument: Instrument) -> io::Result<Server> {
+    Server::spawn(addr, move |stream| {
+        let _ = handle(stream, instrument.clone());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::SystemTime};
+
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn nr3() {
+        assert_eq!(format_nr3(0.00123), "+1.230000E-03");
+        assert_eq!(format_nr3(-98.9), "-9.890000E+01");
+        assert_eq!(format_nr3(OVERFLOW), "+9.900000E+37");
+    }
+
+    #[test]
+    fn commands() {
+        let instrument = Instrument::new().with_read_timeout(Duration::from_millis(10));
+        let mut session = Session::new(instrument.clone());
+        assert_eq!(session.execute_line("FETC?").as_deref(), Some("+9.910000E+37"));
+        assert!(session.execute_line(":syst:err?").unwrap().starts_with("-230,"));
+
+        instrument.update(&reading("109853802"));
+        assert_eq!(session.execute_line("*idn?").unwrap(), format!("CYRUSTEK,ES51986,0,{}", env!("CARGO_PKG_VERSION")));
+        assert_eq!(session.execute_line("CONF?").as_deref(), Some("\"RES +1.000000E+04,+1.000000E+00\""));
+        assert_eq!(session.execute_line("fetch?;SYSTem:ERRor?").as_deref(), Some("+9.850000E+02;+0,\"No error\""));
+        // Function mismatch.
+        session.execute_line("CONF:VOLT:DC");
+        assert_eq!(session.execute_line("FETC?").as_deref(), Some("+9.910000E+37"));
+        assert_eq!(session.execute_line("SYST:ERR?").as_deref(), Some("-221,\"Settings conflict;meter is in RES\""));
+        session.execute_line("*RST");
+        assert_eq!(session.execute_line("FETC?").as_deref(), Some("+9.850000E+02"));
+
+        session.execute_line("BOGUS");
+        assert!(session.execute_line("SYST:ERR?").unwrap().starts_with("-113,"));
+
+        instrument.update(&reading("560003902"));
+        assert_eq!(session.execute_line("FETC?").as_deref(), Some("+9.900000E+37"));
+    }
+
+    #[test]
+    fn measure_over_tcp() {
+        let instrument = Instrument::new();
+        let server = serve("127.0.0.1:0", instrument.clone()).unwrap();
+        let stream = TcpStream::connect(server.local_addr()).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+
+        let feeder = {
+            let instrument = instrument.clone();
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    instrument.update(&reading("00002?<0:"));
+                    thread::sleep(Duration::from_millis(20));
+                }
+            })
+        };
+        let mut query = |q: &str| {
+            writeln!(stream, "{}", q).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.trim_end().to_owned()
+        };
+        assert_eq!(query("MEAS:CURR:DC?"), "-2.000000E-05");
+        assert_eq!(query("MEASure:VOLTage:DC? 10,0.001"), "+9.910000E+37");
+        assert_eq!(query("SYST:ERR?"), "-221,\"Settings conflict;meter is in CURR:DC\"");
+        feeder.join().unwrap();
+
+        let mut long = TcpStream::connect(server.local_addr()).unwrap();
+        long.write_all(&[b'A'; MAX_LINE_LEN as usize]).unwrap();
+        assert_eq!(long.read(&mut [0; 1]).unwrap_or(0), 0);
+        server.shutdown();
+    }
+}
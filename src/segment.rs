@@ -0,0 +1,217 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{Function, Range, reading::Reading};
+
+/// What stays the same throughout a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentKey {
+    pub function: Function,
+    pub range: Range,
+    /// "AC", "DC", "AC+DC" or "".
+    pub coupling: &'static str,
+}
+
+impl SegmentKey {
+    pub fn of(reading: &Reading) -> Self {
+        let output = &reading.output;
+        Self { function: output.function, range: output.range, coupling: output.option2.coupling() }
+    }
+}
+
+/// Statistics of a contiguous run of readings with the same function, range and coupling.
+///
+/// Values are in `unit`, the unit shown on the meter, which is the same throughout a segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub key: SegmentKey,
+    /// Unit symbol such as "mV". None for functions without a unit.
+    pub unit: Option<String>,
+    /// Timestamp of the first reading.
+    pub start: SystemTime,
+    /// Timestamp of the last reading.
+    pub end: SystemTime,
+    /// Number of readings including overflow frames.
+    pub count: usize,
+    pub overflow_count: usize,
+    /// Frames that failed to parse while this segment was in progress.
+    pub parse_errors: usize,
+    /// `None` if no reading in the segment had a value.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// Sample standard deviation. `None` with fewer than two values.
+    pub stddev: Option<f64>,
+    /// Intervals between readings longer than the gap threshold.
+    pub gaps: usize,
+    /// Total length of those intervals.
+    pub gap_duration: Duration,
+    value_count: usize,
+    /// Sum of squared differences from the mean (Welford's algorithm).
+    m2: f64,
+}
+
+impl Segment {
+    fn new(reading: &Reading) -> Self {
+        Self {
+            key: SegmentKey::of(reading), unit: reading.value_unit().map(|u| u.symbol()), start: reading.timestamp, end: reading.timestamp,
+            count: 0, overflow_count: 0, parse_errors: 0, min: None, max: None, mean: None, stddev: None,
+            gaps: 0, gap_duration: Duration::ZERO, value_count: 0, m2: 0.0,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+
+    fn add(&mut self, reading: &Reading) {
+        self.count += 1;
+        self.end = reading.timestamp;
+        if reading.output.status.is_overflow {
+            self.overflow_count += 1;
+        }
+        if let Some(v) = reading.value {
+            self.min = Some(self.min.map_or(v, |m| m.min(v)));
+            self.max = Some(self.max.map_or(v, |m| m.max(v)));
+            self.value_count += 1;
+            let mean = self.mean.unwrap_or(0.0);
+            let delta = v - mean;
+            let mean = mean + delta / self.value_count as f64;
+            self.m2 += delta * (v - mean);
+            self.mean = Some(mean);
+            self.stddev = (1 < self.value_count).then(|| (self.m2 / (self.value_count - 1) as f64).sqrt());
+        }
+    }
+}
+
+/// Splits readings into segments.
+///
+/// A segment is closed when the function, range or coupling changes. Parse errors are counted in the segment in progress, or in the next one if none is.
+///
+/// ```
+/// use std::time::SystemTime;
+/// use es51986::{Output, reading::Reading, segment::Segmenter};
+///
+/// let mut segmenter = Segmenter::new();
+/// let reading = |frame: &str| Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap());
+/// assert_eq!(segmenter.push(&reading("11000;80:")), None);
+/// segmenter.push_error();
+/// let closed = segmenter.push(&reading("109853802")).unwrap();
+/// assert_eq!((closed.count, closed.parse_errors), (1, 1));
+/// assert_eq!(segmenter.finish().unwrap().unit.as_deref(), Some("kΩ"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Segmenter {
+    gap_threshold: Duration,
+    current: Option<Segment>,
+    pending_errors: usize,
+}
+
+impl Default for Segmenter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Segmenter {
+    /// Default gap threshold. The meter sends about 2 frames per second.
+    pub const DEFAULT_GAP_THRESHOLD: Duration = Duration::from_secs(2);
+
+    pub fn new() -> Self {
+        Self { gap_threshold: Self::DEFAULT_GAP_THRESHOLD, current: None, pending_errors: 0 }
+    }
+
+    /// Interval between readings regarded as missing data.
+    pub fn with_gap_threshold(mut self, gap_threshold: Duration) -> Self {
+        self.gap_threshold = gap_threshold;
+        self
+    }
+
+    /// Add a reading. Returns the segment closed by this reading, if any.
+    pub fn push(&mut self, reading: &Reading) -> Option<Segment> {
+        let closed = match self.current.take() {
+            Some(mut segment) if segment.key == SegmentKey::of(reading) => {
+                if let Ok(interval) = reading.timestamp.duration_since(segment.end) {
+                    if self.gap_threshold < interval {
+                        segment.gaps += 1;
+                        segment.gap_duration += interval;
+                    }
+                }
+                self.current = Some(segment);
+                None
+            }
+            closed => closed,
+        };
+        let current = self.current.get_or_insert_with(|| {
+            let mut segment = Segment::new(reading);
+            segment.parse_errors = std::mem::take(&mut self.pending_errors);
+            segment
+        });
+        current.add(reading);
+        closed
+    }
+
+    /// Count a frame that failed to parse.
+    pub fn push_error(&mut self) {
+        match self.current.as_mut() {
+            Some(segment) => segment.parse_errors += 1,
+            None => self.pending_errors += 1,
+        }
+    }
+
+    /// Close the segment in progress.
+    pub fn finish(&mut self) -> Option<Segment> {
+        self.current.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(secs: f64, frame: &str) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_secs_f64(secs), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn statistics() {
+        let mut s = Segmenter::new();
+        for (i, frame) in ["11000;80:", "11200;80:", "11000;90:", "11400;80:"].iter().enumerate() {
+            assert_eq!(s.push(&reading(i as f64 * 0.5, frame)), None);
+        }
+        let segment = s.finish().unwrap();
+        assert_eq!(segment.key, SegmentKey { function: Function::Voltage, range: Range::Range1, coupling: "DC" });
+        assert_eq!(segment.unit.as_deref(), Some("V"));
+        assert_eq!(segment.duration(), Duration::from_millis(1500));
+        assert_eq!((segment.count, segment.overflow_count), (4, 1));
+        assert_eq!((segment.min, segment.max, segment.mean), (Some(10.0), Some(14.0), Some(12.0)));
+        assert!((segment.stddev.unwrap() - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn split_on_range_and_coupling() {
+        let mut s = Segmenter::new();
+        s.push(&reading(0.0, "11000;80:"));
+        // Same function in another range.
+        assert_eq!(s.push(&reading(0.5, "01000;80:")).unwrap().count, 1);
+        // AC instead of DC.
+        assert_eq!(s.push(&reading(1.0, "01000;806")).unwrap().key.coupling, "DC");
+        assert_eq!(s.finish().unwrap().key.coupling, "AC");
+        assert_eq!(s.finish(), None);
+    }
+
+    #[test]
+    fn gaps_and_errors() {
+        let mut s = Segmenter::new().with_gap_threshold(Duration::from_secs(1));
+        s.push_error();
+        s.push(&reading(0.0, "11000;80:"));
+        s.push(&reading(0.5, "11000;80:"));
+        s.push_error();
+        s.push(&reading(10.5, "11000;80:"));
+        let segment = s.finish().unwrap();
+        assert_eq!(segment.parse_errors, 2);
+        assert_eq!((segment.gaps, segment.gap_duration), (1, Duration::from_secs(10)));
+        assert_eq!(segment.stddev, Some(0.0));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Connections served at once. Further connections are closed right after accept.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// A connection is closed when a read waits longer than this.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A connection is closed when a write waits longer than this.
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Decrements the connection count when the connection ends.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Background TCP server. Each connection is handled in its own thread.
+///
+/// At most `MAX_CONNECTIONS` connections are served at once, with `READ_TIMEOUT` and `WRITE_TIMEOUT` set on each.
+/// The server stops when `shutdown()` is called or the handle is dropped. Connections already accepted are served to the end.
+pub struct Server {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    pub(crate) fn spawn<A, F>(addr: A, handler: F) -> io::Result<Self>
+        where A: ToSocketAddrs, F: Fn(TcpStream) + Send + Sync + 'static
+    {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let handler = Arc::new(handler);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    if MAX_CONNECTIONS <= connections.fetch_add(1, Ordering::SeqCst) {
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                        continue;
+                    }
+                    let slot = Slot(connections.clone());
+                    if stream.set_read_timeout(Some(READ_TIMEOUT)).and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT))).is_err() {
+                        continue;
+                    }
+                    let handler = handler.clone();
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        handler(stream)
+                    });
+                }
+            })
+        };
+        Ok(Self { local_addr, stop, thread: Some(thread) })
+    }
+
+    /// The bound address. Useful when bound to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections and wait for the accept loop to end.
+    pub fn shutdown(mut self) {
+        self.stop_thread();
+    }
+
+    fn stop_thread(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::SeqCst);
+            // Wake up the blocking accept().
+            let _ = TcpStream::connect(self.local_addr);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::mpsc};
+
+    use super::*;
+
+    #[test]
+    fn connection_limit() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let rx = Arc::new(std::sync::Mutex::new(rx));
+        // Each handler holds its connection until told to end.
+        let server = Server::spawn("127.0.0.1:0", move |_| { let _ = rx.lock().unwrap().recv(); }).unwrap();
+        let held: Vec<TcpStream> = (0..MAX_CONNECTIONS).map(|_| TcpStream::connect(server.local_addr()).unwrap()).collect();
+        let mut rejected = TcpStream::connect(server.local_addr()).unwrap();
+        rejected.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let closed = rejected.read(&mut [0; 1]);
+        assert!(matches!(closed, Ok(0)) || closed.is_err_and(|e| e.kind() == io::ErrorKind::ConnectionReset));
+        drop(tx);
+        drop(held);
+    }
+}
@@ -0,0 +1,22 @@
+use std::io;
+
+use crate::reading::Reading;
+
+/// Destination of readings such as a file format or a network publisher.
+pub trait Sink {
+    fn write(&mut self, reading: &Reading) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn write(&mut self, reading: &Reading) -> io::Result<()> {
+        (**self).write(reading)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
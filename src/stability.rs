@@ -0,0 +1,257 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Function, reading::Reading};
+
+/// One point of the Allan deviation plot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AllanPoint {
+    /// Averaging time `m * tau0`.
+    pub tau: Duration,
+    pub m: usize,
+    /// Overlapping Allan deviation in the base unit (V, Hz, ...).
+    pub deviation: f64,
+    /// Number of differences used. Differences touching a missing sample are skipped.
+    pub terms: usize,
+}
+
+/// Least-squares line fitted over the samples.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Drift {
+    /// Slope in base unit per second.
+    pub rate_per_second: f64,
+    /// Value of the fitted line at the first sample.
+    pub intercept: f64,
+}
+
+impl Drift {
+    pub fn rate_per_hour(&self) -> f64 {
+        self.rate_per_second * 3600.0
+    }
+}
+
+/// Long-term stability analysis of readings such as a reference voltage or `Function::Frequency`.
+///
+/// Readings are placed on a grid of `tau0` (the nominal interval of the meter). Slots without a reading are treated as missing, and overflow frames are excluded.
+/// Values are converted to the base unit so that range changes do not disturb the statistics. Readings whose function differs from the first one are excluded.
+/// A reading more than `max_gap` (default one hour) after the latest sample is treated as a stray timestamp and excluded, so that it cannot grow the grid without bound.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use es51986::{Output, reading::Reading, stability::Stability};
+///
+/// let mut stability = Stability::new(Duration::from_millis(500));
+/// for (i, frame) in ["01000;80:", "01002;80:", "01000;80:", "01002;80:"].iter().enumerate() {
+///     let t = SystemTime::UNIX_EPOCH + Duration::from_millis(500 * i as u64);
+///     stability.push(&Reading::new(t, Output::parse(frame.as_bytes()).unwrap()));
+/// }
+/// let adev = stability.allan_deviation(&[1]);
+/// assert!((adev[0].deviation - 0.002 / 2f64.sqrt()).abs() < 1e-9);
+/// assert!((stability.peak_to_peak().unwrap() - 0.002).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Stability {
+    tau0: Duration,
+    max_gap: Duration,
+    function: Option<Function>,
+    start: Option<SystemTime>,
+    slots: Vec<Option<(f64, usize)>>,
+    samples: Vec<(f64, f64)>,
+    overflow_frames: usize,
+    excluded: usize,
+}
+
+impl Stability {
+    pub const DEFAULT_MAX_GAP: Duration = Duration::from_secs(3600);
+
+    /// # Panics
+    ///
+    /// Panics if `tau0` is zero.
+    pub fn new(tau0: Duration) -> Self {
+        assert!(!tau0.is_zero(), "tau0 should not be zero");
+        Self {
+            tau0, max_gap: Self::DEFAULT_MAX_GAP, function: None, start: None, slots: vec![], samples: vec![],
+            overflow_frames: 0, excluded: 0,
+        }
+    }
+
+    /// Longest gap after the latest sample before a reading is excluded.
+    pub fn with_max_gap(mut self, max_gap: Duration) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    pub fn push(&mut self, reading: &Reading) {
+        if reading.output.status.is_overflow {
+            self.overflow_frames += 1;
+            return;
+        }
+        let function = *self.function.get_or_insert(reading.output.function);
+        let (Some(value), true) = (reading.si_value(), function == reading.output.function) else {
+            self.excluded += 1;
+            return;
+        };
+        let start = *self.start.get_or_insert(reading.timestamp);
+        let Ok(elapsed) = reading.timestamp.duration_since(start) else {
+            self.excluded += 1;
+            return;
+        };
+        let last = self.samples.last().map_or(0.0, |s| s.0);
+        if self.max_gap.as_secs_f64() < elapsed.as_secs_f64() - last {
+            self.excluded += 1;
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        let slot = (secs / self.tau0.as_secs_f64()).round() as usize;
+        if self.slots.len() <= slot {
+            self.slots.resize(slot + 1, None);
+        }
+        // Average frames that fall in the same slot.
+        let (sum, count) = self.slots[slot].unwrap_or((0.0, 0));
+        self.slots[slot] = Some((sum + value, count + 1));
+        self.samples.push((secs, value));
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Grid slots that received no reading.
+    pub fn missing_slots(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_none()).count()
+    }
+
+    pub fn overflow_frames(&self) -> usize {
+        self.overflow_frames
+    }
+
+    /// Readings excluded because the function changed, they had no value or their timestamp was out of place.
+    pub fn excluded(&self) -> usize {
+        self.excluded
+    }
+
+    fn grid(&self) -> Vec<Option<f64>> {
+        self.slots.iter().map(|s| s.map(|(sum, count)| sum / count as f64)).collect()
+    }
+
+    /// Overlapping Allan deviation for each averaging factor in `ms`. Factors without any complete difference are omitted.
+    pub fn allan_deviation(&self, ms: &[usize]) -> Vec<AllanPoint> {
+        let grid = self.grid();
+        ms.iter().filter(|&&m| m != 0).filter_map(|&m| {
+            let averages: Vec<Option<f64>> = grid.windows(m).map(|w| {
+                w.iter().try_fold(0.0, |acc, v| v.map(|v| acc + v)).map(|sum| sum / m as f64)
+            }).collect();
+            let (sum, terms) = averages.iter().zip(averages.iter().skip(m)).fold((0.0, 0), |(sum, terms), pair| {
+                match pair {
+                    (Some(a), Some(b)) => (sum + (b - a) * (b - a), terms + 1),
+                    _ => (sum, terms),
+                }
+            });
+            (terms != 0).then(|| AllanPoint {
+                tau: self.tau0 * m as u32,
+                m,
+                deviation: (sum / (2.0 * terms as f64)).sqrt(),
+                terms,
+            })
+        }).collect()
+    }
+
+    /// Allan deviation for averaging factors 1, 2, 4, ... up to a third of the record.
+    pub fn allan_deviation_octaves(&self) -> Vec<AllanPoint> {
+        let max = self.slots.len() / 3;
+        let ms: Vec<usize> = std::iter::successors(Some(1usize), |m| Some(m * 2)).take_while(|&m| m <= max.max(1)).collect();
+        self.allan_deviation(&ms)
+    }
+
+    /// Linear drift. `None` if fewer than two samples or all samples have the same timestamp.
+    pub fn drift(&self) -> Option<Drift> {
+        let n = self.samples.len() as f64;
+        if n < 2.0 {
+            return None;
+        }
+        let mean_t = self.samples.iter().map(|s| s.0).sum::<f64>() / n;
+        let mean_v = self.samples.iter().map(|s| s.1).sum::<f64>() / n;
+        let stt: f64 = self.samples.iter().map(|s| (s.0 - mean_t) * (s.0 - mean_t)).sum();
+        if stt == 0.0 {
+            return None;
+        }
+        let stv: f64 = self.samples.iter().map(|s| (s.0 - mean_t) * (s.1 - mean_v)).sum();
+        let rate_per_second = stv / stt;
+        Some(Drift { rate_per_second, intercept: mean_v - rate_per_second * mean_t })
+    }
+
+    /// Peak-to-peak of the values.
+    pub fn peak_to_peak(&self) -> Option<f64> {
+        let min = self.samples.iter().map(|s| s.1).reduce(f64::min)?;
+        let max = self.samples.iter().map(|s| s.1).reduce(f64::max)?;
+        Some(max - min)
+    }
+
+    /// Peak-to-peak of the residuals after removing the linear drift.
+    pub fn peak_to_peak_noise(&self) -> Option<f64> {
+        let drift = self.drift()?;
+        let residuals = self.samples.iter().map(|&(t, v)| v - (drift.intercept + drift.rate_per_second * t));
+        let (min, max) = residuals.fold((f64::MAX, f64::MIN), |(min, max), r| (min.min(r), max.max(r)));
+        Some(max - min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Output;
+
+    use super::*;
+
+    fn feed(stability: &mut Stability, samples: &[(u64, &str)]) {
+        for (millis, frame) in samples {
+            let t = SystemTime::UNIX_EPOCH + Duration::from_millis(*millis);
+            stability.push(&Reading::new(t, Output::parse(frame.as_bytes()).unwrap()));
+        }
+    }
+
+    #[test]
+    fn allan_with_gap_and_overflow() {
+        let mut s = Stability::new(Duration::from_secs(1));
+        feed(&mut s, &[
+            (0, "01000;80:"), (1000, "01002;80:"), (2000, "01000;90:"), (3000, "01002;80:"), (4000, "01000;80:"),
+        ]);
+        assert_eq!(s.overflow_frames(), 1);
+        assert_eq!(s.missing_slots(), 1);
+        let adev = s.allan_deviation(&[1, 2]);
+        assert_eq!(adev.len(), 1);
+        assert_eq!(adev[0].m, 1);
+        assert_eq!(adev[0].terms, 2);
+        assert!((adev[0].deviation - 0.002 / 2f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn drift() {
+        let mut s = Stability::new(Duration::from_secs(1));
+        feed(&mut s, &[(0, "01000;80:"), (1000, "01001;80:"), (2000, "01002;80:"), (3000, "01003;80:")]);
+        let drift = s.drift().unwrap();
+        assert!((drift.rate_per_second - 0.001).abs() < 1e-9);
+        assert!((drift.intercept - 1.0).abs() < 1e-9);
+        assert!(s.peak_to_peak_noise().unwrap() < 1e-9);
+        assert!((s.peak_to_peak().unwrap() - 0.003).abs() < 1e-9);
+    }
+
+    #[test]
+    fn excludes_other_function() {
+        let mut s = Stability::new(Duration::from_secs(1));
+        feed(&mut s, &[(0, "01000;80:"), (1000, "000003802"), (2000, "11000;80:")]);
+        assert_eq!(s.excluded(), 1);
+        assert_eq!(s.sample_count(), 2);
+        assert_eq!(s.allan_deviation_octaves().len(), 0);
+    }
+
+    #[test]
+    fn stray_timestamp_excluded() {
+        let mut s = Stability::new(Duration::from_millis(1)).with_max_gap(Duration::from_secs(10));
+        feed(&mut s, &[(0, "01000;80:"), (1, "01002;80:"), (1_000_000_000, "01000;80:"), (2, "01001;80:")]);
+        assert_eq!(s.excluded(), 1);
+        assert_eq!(s.sample_count(), 3);
+        assert_eq!(s.missing_slots(), 0);
+        assert!((s.peak_to_peak().unwrap() - 0.002).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,126 @@
+//! Timestamp formatting shared by the output formats.
+
+use std::time::{Duration, SystemTime};
+
+/// Civil date (year, month, day) of days since 1970-01-01.
+/// Howard Hinnant's `civil_from_days` algorithm.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 of a civil date. Inverse of `civil_from_days`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let m = month as i64;
+    let doy = (153 * (if 2 < m { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Nanoseconds since the UNIX epoch. Times before the epoch are negative.
+pub fn unix_nanos(t: SystemTime) -> i128 {
+    match t.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    }
+}
+
+/// RFC 3339 in UTC with millisecond precision, e.g. "2024-03-01T12:34:56.789Z".
+pub fn rfc3339(t: SystemTime) -> String {
+    let millis = unix_nanos(t).div_euclid(1_000_000) as i64;
+    let secs = millis.div_euclid(1000);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let sod = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, sod / 3600, sod % 3600 / 60, sod % 60, millis.rem_euclid(1000)
+    )
+}
+
+fn number(s: &str) -> Option<u32> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parse RFC 3339 such as "2024-03-01T12:34:56.789Z" or "2024-03-01T21:34:56+09:00". Fractional seconds are optional.
+pub fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+    let (date, time) = s.split_at_checked(10)?;
+    let mut date = date.split('-');
+    let (year, month, day) = (number(date.next()?)?, number(date.next()?)?, number(date.next()?)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let time = time.strip_prefix(['T', 't', ' '])?;
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let split = time.rfind(['+', '-'])?;
+        let (time, offset) = time.split_at(split);
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        let (hours, minutes) = (number(hours)?, number(minutes)?);
+        if 23 < hours || 59 < minutes {
+            return None;
+        }
+        let seconds = (hours * 3600 + minutes * 60) as i64;
+        (time, if offset.starts_with('-') { -seconds } else { seconds })
+    };
+    let (hms, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut hms = hms.split(':');
+    let (hour, minute, second) = (number(hms.next()?)?, number(hms.next()?)?, number(hms.next()?)?);
+    if hms.next().is_some() || 23 < hour || 59 < minute || 60 < second || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let nanos: u32 = format!("{:0<9}", fraction.get(..9).unwrap_or(fraction)).parse().ok()?;
+    let secs = days_from_civil(year as i64, month, day) * 86_400 + (hour * 3600 + minute * 60 + second) as i64 - offset;
+    let t = if 0 <= secs {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    };
+    Some(t + Duration::from_nanos(nanos as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn format() {
+        assert_eq!(rfc3339(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_296_496_789)), "2024-03-01T12:34:56.789Z");
+    }
+
+    #[test]
+    fn parse() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_296_496_789);
+        assert_eq!(parse_rfc3339("2024-03-01T12:34:56.789Z"), Some(t));
+        assert_eq!(parse_rfc3339("2024-03-01T21:34:56.789+09:00"), Some(t));
+        assert_eq!(parse_rfc3339("2024-03-01 07:34:56.789-05:00"), Some(t));
+        assert_eq!(parse_rfc3339("2024-03-01T12:34:56+24:00"), None);
+        assert_eq!(parse_rfc3339("2024-03-01T12:34:56+00:60"), None);
+        assert_eq!(parse_rfc3339("2024-03-01T12:34:56+4294967295:00"), None);
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(SystemTime::UNIX_EPOCH));
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59.5Z"), Some(SystemTime::UNIX_EPOCH - Duration::from_millis(500)));
+        assert_eq!(days_from_civil(2024, 3, 1), 19_783);
+        assert_eq!(civil_from_days(days_from_civil(2000, 2, 29)), (2000, 2, 29));
+        assert_eq!(parse_rfc3339("2024-03-01T12:34:56"), None);
+        assert_eq!(parse_rfc3339("2024-13-01T12:34:56Z"), None);
+        assert_eq!(parse_rfc3339("yesterday"), None);
+    }
+}
@@ -0,0 +1,418 @@
+//! Conditions on readings such as "DCV > 14.4" and a watcher that reports when they fire and clear.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{BaseUnit, reading::Reading};
+
+/// What a threshold compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    /// `Reading::value` in the unit shown on the meter, whatever it is.
+    Value,
+    /// Value in `base_unit` (see `Reading::si_value()`). With a coupling, only readings with that coupling match.
+    Unit { coupling: Option<Coupling>, base_unit: BaseUnit },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coupling {
+    Dc,
+    Ac,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+        }
+    }
+
+    fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Greater => threshold < value,
+            Self::GreaterOrEqual => threshold <= value,
+            Self::Less => value < threshold,
+            Self::LessOrEqual => value <= threshold,
+        }
+    }
+
+    /// True while an active condition is kept, that is until the value moves `hysteresis` back past the threshold.
+    fn keeps(&self, value: f64, threshold: f64, hysteresis: f64) -> bool {
+        match self {
+            Self::Greater => threshold - hysteresis < value,
+            Self::GreaterOrEqual => threshold - hysteresis <= value,
+            Self::Less => value < threshold + hysteresis,
+            Self::LessOrEqual => value <= threshold + hysteresis,
+        }
+    }
+}
+
+/// A condition on readings.
+///
+/// ```
+/// use es51986::watch::Condition;
+///
+/// let condition: Condition = "DCV > 14.4".parse().unwrap();
+/// assert_eq!(condition.to_string(), "DCV > 14.4");
+/// assert!("ohm < 10k".parse::<Condition>().is_ok());
+/// assert!("no data for 10s".parse::<Condition>().is_ok());
+/// assert!("DCV >> 1".parse::<Condition>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// `<quantity> <op> <number>` such as "DCV > 14.4", "A >= 2m" or "value < 0". The number may end with an SI prefix (p, n, u, m, k, M, G).
+    Threshold { quantity: Quantity, comparison: Comparison, threshold: f64 },
+    /// "overflow": the meter shows OL.
+    Overflow,
+    /// "battery_low": the meter reports a low battery.
+    BatteryLow,
+    /// "no data for <duration>" such as "no data for 10s". The duration takes ms, s, m or h.
+    NoData(Duration),
+}
+
+/// Error of parsing a `Condition`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError(pub String);
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+fn parse_base_unit(s: &str) -> Option<BaseUnit> {
+    match s.to_ascii_uppercase().as_str() {
+        "V" => Some(BaseUnit::Volt),
+        "A" => Some(BaseUnit::Ampere),
+        "OHM" | "Ω" => Some(BaseUnit::Ohm),
+        "HZ" => Some(BaseUnit::Hearts),
+        "F" => Some(BaseUnit::Farad),
+        _ => None,
+    }
+}
+
+fn parse_quantity(s: &str) -> Option<Quantity> {
+    if s.eq_ignore_ascii_case("value") {
+        return Some(Quantity::Value);
+    }
+    if let Some(base_unit) = parse_base_unit(s) {
+        return Some(Quantity::Unit { coupling: None, base_unit });
+    }
+    let (coupling, rest) = s.split_at_checked(2)?;
+    let coupling = match coupling.to_ascii_uppercase().as_str() {
+        "DC" => Coupling::Dc,
+        "AC" => Coupling::Ac,
+        _ => return None,
+    };
+    parse_base_unit(rest).map(|base_unit| Quantity::Unit { coupling: Some(coupling), base_unit })
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    let (number, exponent) = match s.char_indices().last()? {
+        (i, 'p') => (&s[..i], -12),
+        (i, 'n') => (&s[..i], -9),
+        (i, 'u' | 'µ') => (&s[..i], -6),
+        (i, 'm') => (&s[..i], -3),
+        (i, 'k') => (&s[..i], 3),
+        (i, 'M') => (&s[..i], 6),
+        (i, 'G') => (&s[..i], 9),
+        _ => (s, 0),
+    };
+    number.parse::<f64>().ok().filter(|n| n.is_finite()).map(|n| n * 10f64.powi(exponent))
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = s.split_at(split);
+    let seconds = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(number.parse::<f64>().ok()? * seconds).ok()
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            [flag] if flag.eq_ignore_ascii_case("overflow") => return Ok(Self::Overflow),
+            [flag] if flag.eq_ignore_ascii_case("battery_low") => return Ok(Self::BatteryLow),
+            [no, data, for_, duration] if no.eq_ignore_ascii_case("no") && data.eq_ignore_ascii_case("data") && for_.eq_ignore_ascii_case("for") => {
+                return parse_duration(duration).map(Self::NoData)
+                    .ok_or_else(|| ConditionError(format!("invalid duration '{}' in '{}' (use ms, s, m or h)", duration, s)));
+            }
+            _ => {}
+        }
+        // Spaces around the operator are optional.
+        let op_start = s.find(['<', '>']).ok_or_else(|| {
+            ConditionError(format!("invalid condition '{}' (expected '<quantity> <op> <number>', overflow, battery_low or 'no data for <duration>')", s))
+        })?;
+        let (comparison, op_len) = match &s[op_start..] {
+            r if r.starts_with(">=") => (Comparison::GreaterOrEqual, 2),
+            r if r.starts_with("<=") => (Comparison::LessOrEqual, 2),
+            r if r.starts_with('>') => (Comparison::Greater, 1),
+            _ => (Comparison::Less, 1),
+        };
+        let quantity = s[..op_start].trim();
+        let quantity = parse_quantity(quantity).ok_or_else(|| {
+            ConditionError(format!("unknown quantity '{}' in '{}' (use value or an optional DC/AC followed by V, A, ohm, Hz or F)", quantity, s))
+        })?;
+        let number = s[op_start + op_len..].trim();
+        let threshold = parse_number(number).ok_or_else(|| ConditionError(format!("invalid number '{}' in '{}'", number, s)))?;
+        Ok(Self::Threshold { quantity, comparison, threshold })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Threshold { quantity, comparison, threshold } => {
+                match quantity {
+                    Quantity::Value => write!(f, "value")?,
+                    Quantity::Unit { coupling, base_unit } => {
+                        match coupling {
+                            Some(Coupling::Dc) => write!(f, "DC")?,
+                            Some(Coupling::Ac) => write!(f, "AC")?,
+                            None => {}
+                        }
+                        write!(f, "{}", base_unit.symbol())?;
+                    }
+                }
+                write!(f, " {} {}", comparison.symbol(), threshold)
+            }
+            Self::Overflow => write!(f, "overflow"),
+            Self::BatteryLow => write!(f, "battery_low"),
+            Self::NoData(duration) => write!(f, "no data for {}s", duration.as_secs_f64()),
+        }
+    }
+}
+
+impl Condition {
+    /// Value of `reading` that a threshold compares, or None if the reading does not match the quantity.
+    fn value_of(quantity: &Quantity, reading: &Reading) -> Option<f64> {
+        match quantity {
+            Quantity::Value => reading.value,
+            Quantity::Unit { coupling, base_unit } => {
+                if reading.value_unit()?.base_unit != *base_unit {
+                    return None;
+                }
+                let option2 = &reading.output.option2;
+                match coupling {
+                    Some(Coupling::Dc) if !option2.is_dc => None,
+                    Some(Coupling::Ac) if !option2.is_ac => None,
+                    _ => reading.si_value(),
+                }
+            }
+        }
+    }
+}
+
+/// Change of the state of a `Watch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Fired,
+    Cleared,
+}
+
+/// Tracks one condition over readings, with debounce and hysteresis.
+///
+/// A reading that the condition does not apply to, such as an ohm reading for "DCV > 14.4" or an overflow frame, leaves the state unchanged.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use es51986::{Output, reading::Reading, watch::{Event, Watch}};
+///
+/// let mut watch = Watch::new("V > 11".parse().unwrap()).with_hysteresis(0.5);
+/// let reading = |frame: &str| Reading::new(SystemTime::UNIX_EPOCH, Output::parse(frame.as_bytes()).unwrap());
+/// assert_eq!(watch.update(&reading("11200;80:")), Some(Event::Fired));
+/// assert_eq!(watch.update(&reading("11080;80:")), None);
+/// assert_eq!(watch.update(&reading("11000;80:")), Some(Event::Cleared));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Watch {
+    condition: Condition,
+    hysteresis: f64,
+    debounce: Duration,
+    active: bool,
+    /// Since when the condition has disagreed with `active`.
+    pending: Option<SystemTime>,
+    last_seen: Option<SystemTime>,
+}
+
+impl Watch {
+    pub fn new(condition: Condition) -> Self {
+        Self { condition, hysteresis: 0.0, debounce: Duration::ZERO, active: false, pending: None, last_seen: None }
+    }
+
+    /// Distance in the threshold's unit that the value has to move back past the threshold to clear.
+    pub fn with_hysteresis(mut self, hysteresis: f64) -> Self {
+        self.hysteresis = hysteresis.abs();
+        self
+    }
+
+    /// How long the condition has to hold, or not hold, before the state changes.
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn condition(&self) -> &Condition {
+        &self.condition
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Feed a reading.
+    pub fn update(&mut self, reading: &Reading) -> Option<Event> {
+        self.last_seen = Some(reading.timestamp);
+        let status = &reading.output.status;
+        let holds = match &self.condition {
+            Condition::Threshold { quantity, comparison, threshold } => {
+                let value = if status.is_overflow { None } else { Condition::value_of(quantity, reading) };
+                value.map(|v| if self.active { comparison.keeps(v, *threshold, self.hysteresis) } else { comparison.holds(v, *threshold) })
+            }
+            Condition::Overflow => Some(status.is_overflow),
+            Condition::BatteryLow => Some(status.is_battery_depleted),
+            Condition::NoData(_) => Some(false),
+        };
+        self.step(holds, reading.timestamp)
+    }
+
+    /// Advance the time without a reading. Needed for "no data" and for debounce to complete while readings stop.
+    pub fn tick(&mut self, now: SystemTime) -> Option<Event> {
+        let holds = match &self.condition {
+            Condition::NoData(timeout) => {
+                let last_seen = *self.last_seen.get_or_insert(now);
+                Some(*timeout <= now.duration_since(last_seen).unwrap_or_default())
+            }
+            _ => None,
+        };
+        self.step(holds, now)
+    }
+
+    fn step(&mut self, holds: Option<bool>, now: SystemTime) -> Option<Event> {
+        match holds {
+            Some(holds) if holds != self.active => {
+                self.pending.get_or_insert(now);
+            }
+            Some(_) => self.pending = None,
+            None => {}
+        }
+        let since = self.pending?;
+        if now.duration_since(since).unwrap_or_default() < self.debounce {
+            return None;
+        }
+        self.pending = None;
+        self.active = !self.active;
+        Some(if self.active { Event::Fired } else { Event::Cleared })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Output;
+
+    use super::*;
+
+    fn reading(frame: &str, millis: u64) -> Reading {
+        Reading::new(SystemTime::UNIX_EPOCH + Duration::from_millis(millis), Output::parse(frame.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "DCV > 14.4".parse(),
+            Ok(Condition::Threshold {
+                quantity: Quantity::Unit { coupling: Some(Coupling::Dc), base_unit: BaseUnit::Volt }, comparison: Comparison::Greater, threshold: 14.4
+            })
+        );
+        assert_eq!(
+            "a<=-2m".parse(),
+            Ok(Condition::Threshold { quantity: Quantity::Unit { coupling: None, base_unit: BaseUnit::Ampere }, comparison: Comparison::LessOrEqual, threshold: -0.002 })
+        );
+        assert_eq!("Overflow".parse(), Ok(Condition::Overflow));
+        assert_eq!("battery_low".parse(), Ok(Condition::BatteryLow));
+        assert_eq!("no data for 500ms".parse(), Ok(Condition::NoData(Duration::from_millis(500))));
+        assert_eq!("no data for 10s".parse::<Condition>().unwrap().to_string(), "no data for 10s");
+        assert!("DCX > 1".parse::<Condition>().unwrap_err().0.contains("unknown quantity 'DCX'"));
+        assert!("V > x".parse::<Condition>().is_err());
+        assert!("no data for 10".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn threshold_matches_unit_and_coupling() {
+        let mut watch = Watch::new("ACV > 50".parse().unwrap());
+        assert_eq!(watch.update(&reading("11200;80:", 0)), None);
+        assert_eq!(watch.update(&reading("20989;806", 0)), Some(Event::Fired));
+        // Other units and overflow frames leave the state unchanged.
+        assert_eq!(watch.update(&reading("109853802", 0)), None);
+        assert_eq!(watch.update(&reading("560003902", 0)), None);
+        assert!(watch.is_active());
+
+        let mut watch = Watch::new("DCA < -10u".parse().unwrap());
+        assert_eq!(watch.update(&reading("00002?<0:", 0)), Some(Event::Fired));
+    }
+
+    #[test]
+    fn steady_at_threshold() {
+        let mut watch = Watch::new("V >= 12".parse().unwrap());
+        assert_eq!(watch.update(&reading("11200;80:", 0)), Some(Event::Fired));
+        for millis in [500, 1000, 1500] {
+            assert_eq!(watch.update(&reading("11200;80:", millis)), None);
+        }
+        let mut watch = Watch::new("V <= 12".parse().unwrap());
+        assert_eq!(watch.update(&reading("11200;80:", 0)), Some(Event::Fired));
+        assert_eq!(watch.update(&reading("11200;80:", 500)), None);
+        assert!(watch.is_active());
+    }
+
+    #[test]
+    fn debounce() {
+        let mut watch = Watch::new("V >= 12".parse().unwrap()).with_debounce(Duration::from_secs(1));
+        assert_eq!(watch.update(&reading("11200;80:", 0)), None);
+        assert_eq!(watch.update(&reading("11000;80:", 500)), None);
+        assert_eq!(watch.update(&reading("11200;80:", 1000)), None);
+        assert_eq!(watch.update(&reading("11200;80:", 1500)), None);
+        assert_eq!(watch.tick(SystemTime::UNIX_EPOCH + Duration::from_millis(2000)), Some(Event::Fired));
+        assert_eq!(watch.update(&reading("11000;80:", 2500)), None);
+        assert_eq!(watch.update(&reading("11000;80:", 3500)), Some(Event::Cleared));
+    }
+
+    #[test]
+    fn flags_and_no_data() {
+        let mut watch = Watch::new(Condition::BatteryLow);
+        assert_eq!(watch.update(&reading("11000;:0:", 0)), Some(Event::Fired));
+        assert_eq!(watch.update(&reading("11000;80:", 0)), Some(Event::Cleared));
+
+        let mut watch = Watch::new("no data for 10s".parse().unwrap());
+        let t = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(watch.tick(t(0)), None);
+        assert_eq!(watch.tick(t(9)), None);
+        assert_eq!(watch.tick(t(10)), Some(Event::Fired));
+        assert_eq!(watch.update(&reading("11000;80:", 11_000)), Some(Event::Cleared));
+        assert_eq!(watch.tick(t(15)), None);
+    }
+}